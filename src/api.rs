@@ -0,0 +1,723 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A small client for the registry web API, used for metadata that the
+//! index does not carry, such as publish dates.
+//!
+//! Responses that come with an ETag are kept under the cargo home, and
+//! revalidated with `If-None-Match` when they are asked for again. With
+//! `--api-cache`, every response is kept in the given directory instead, and
+//! used without asking the registry until it is older than the cache's TTL.
+//! When cargo is offline, cached responses are used however old they are.
+//!
+//! Requests use cargo's HTTP configuration. Without a proxy there, the
+//! `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY`, and `NO_PROXY` environment
+//! variables are honored. With `--limit-rate`, each request receives no
+//! faster than the given rate.
+
+use std::cell::Cell;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use cargo::util::{short_hash, CargoResult, CargoResultExt, Config, ProgressStyle};
+
+use curl::easy::List;
+
+use failure::bail;
+
+use rand::Rng;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::auth;
+use crate::exit::{self, Failure};
+use crate::progress::{human_size, Progress};
+
+/// Base URL of the crates.io web API.
+pub const CRATES_IO_API: &str = "https://crates.io";
+
+/// The crates.io crawler policy asks for at most one request per second.
+const CRATES_IO_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a failed request is retried before giving up, unless
+/// `net.retry` or `--retries` says otherwise.
+const MAX_RETRIES: u32 = 5;
+
+/// The delay before the first retry, which doubles for each one after it.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest `Retry-After` that is waited out rather than reported.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// When the last request to crates.io was made, for throttling.
+static LAST_CRATES_IO_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// The directory given with `--api-cache`, and how long a response kept in
+/// it is used before the registry is asked again.
+static CACHE: Mutex<Option<(PathBuf, Duration)>> = Mutex::new(None);
+
+/// The most bytes per second a single request may receive, if limited.
+static RATE_LIMIT: Mutex<Option<u64>> = Mutex::new(None);
+
+thread_local! {
+    /// Whether requests made on this thread are tried only once; see
+    /// `without_retries`.
+    static NO_RETRIES: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` with every request it makes tried only once, for lookups that
+/// are not worth waiting for when the registry cannot be reached.
+pub fn without_retries<T>(f: impl FnOnce() -> T) -> T {
+    let before = NO_RETRIES.with(|no_retries| no_retries.replace(true));
+    let result = f();
+    NO_RETRIES.with(|no_retries| no_retries.set(before));
+    result
+}
+
+/// Limits every request made here to receiving `bytes_per_sec`.
+pub fn limit_rate(bytes_per_sec: u64) {
+    *RATE_LIMIT.lock().unwrap() = Some(bytes_per_sec);
+}
+
+/// Returns the limit set with `limit_rate`, if any.
+pub fn rate_limit() -> Option<u64> {
+    *RATE_LIMIT.lock().unwrap()
+}
+
+/// Keeps every API response in `dir`, and uses it for `ttl` without making a
+/// request.
+pub fn set_cache(dir: &Path, ttl: Duration) {
+    *CACHE.lock().unwrap() = Some((dir.to_path_buf(), ttl));
+}
+
+/// A published version as reported by `/api/v1/crates/<name>/versions`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Version {
+    pub num: String,
+    pub yanked: bool,
+    pub created_at: String,
+    /// Who published the version, which registries only record for
+    /// versions published after they started to.
+    #[serde(default)]
+    pub published_by: Option<Publisher>,
+    /// The size of the `.crate` file in bytes.
+    #[serde(default)]
+    pub crate_size: Option<u64>,
+}
+
+/// The registry user who published a version.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Publisher {
+    pub login: String,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Versions {
+    versions: Vec<Version>,
+}
+
+/// A crate as listed by `/api/v1/crates`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Crate {
+    pub name: String,
+    /// Downloads of every version, which registries other than crates.io
+    /// may not count.
+    #[serde(default)]
+    pub downloads: u64,
+    /// The newest version, prereleases aside.
+    #[serde(default)]
+    pub max_stable_version: Option<String>,
+    #[serde(default)]
+    pub max_version: Option<String>,
+}
+
+/// A user or team that owns a crate, as `/api/v1/crates/<name>/owners`
+/// lists them.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Owner {
+    /// The login of a user, or `github:<org>:<team>` for a team.
+    pub login: String,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Owners {
+    users: Vec<Owner>,
+}
+
+#[derive(Deserialize)]
+struct CrateBody {
+    #[serde(rename = "crate")]
+    krate: Crate,
+}
+
+#[derive(Deserialize)]
+struct CratesPage {
+    crates: Vec<Crate>,
+    #[serde(default)]
+    meta: PageMeta,
+}
+
+#[derive(Deserialize, Default)]
+struct PageMeta {
+    total: Option<usize>,
+    next_page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Id {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct User {
+    user: Id,
+}
+
+#[derive(Deserialize)]
+struct Team {
+    team: Id,
+}
+
+/// How many crates to ask for per page of a listing.
+const PER_PAGE: usize = 100;
+
+/// Returns the `/api/v1/crates` filter for crates owned by a user, or by a
+/// team given as `github:<org>:<team>`.
+pub fn owner_filter(config: &Config, api: &str, owner: &str) -> CargoResult<String> {
+    let api = api.trim_end_matches('/');
+    if owner.contains(':') {
+        let url = format!("{}/api/v1/teams/{}", api, encode(owner));
+        match get_json::<Team>(config, &url)? {
+            Some(team) => Ok(format!("team_id={}", team.team.id)),
+            None => bail!("no team named '{}' was found", owner),
+        }
+    } else {
+        let url = format!("{}/api/v1/users/{}", api, encode(owner));
+        match get_json::<User>(config, &url)? {
+            Some(user) => Ok(format!("user_id={}", user.user.id)),
+            None => bail!("no user named '{}' was found", owner),
+        }
+    }
+}
+
+/// Returns an `/api/v1/crates` filter such as `keyword=http`.
+pub fn filter(key: &str, value: &str) -> String {
+    format!("{}={}", key, encode(value))
+}
+
+/// Lists the crates that pass every one of `filters`, following the
+/// pagination until `limit` crates are found, if there is a limit.
+pub fn crates(
+    config: &Config,
+    api: &str,
+    filters: &[String],
+    limit: Option<usize>,
+) -> CargoResult<Vec<Crate>> {
+    let base = format!("{}/api/v1/crates", api.trim_end_matches('/'));
+    let params = filters.join("&");
+    let per_page = limit.map_or(PER_PAGE, |limit| limit.min(PER_PAGE));
+    let mut all = Vec::new();
+    let mut page = 1;
+    let mut url = format!("{}?per_page={}&page={}&{}", base, per_page, page, params);
+    loop {
+        let body: CratesPage = match get_json(config, &url)? {
+            Some(body) => body,
+            None => bail!("`{}` was not found", url),
+        };
+        let found = body.crates.len();
+        all.extend(body.crates);
+        if let Some(limit) = limit {
+            if all.len() >= limit {
+                all.truncate(limit);
+                return Ok(all);
+            }
+        }
+
+        // Newer registries link to the next page; older ones may only give
+        // the total, or nothing but full pages.
+        url = match body.meta.next_page {
+            Some(next) => format!("{}{}", base, next),
+            None if found > 0 && body.meta.total.map_or(found == per_page, |t| all.len() < t) => {
+                page += 1;
+                format!("{}?per_page={}&page={}&{}", base, per_page, page, params)
+            }
+            None => return Ok(all),
+        };
+    }
+}
+
+/// Fetches what `/api/v1/crates/<name>` says about a crate, or `None` if the
+/// registry does not know it.
+pub fn krate(config: &Config, api: &str, name: &str) -> CargoResult<Option<Crate>> {
+    let url = format!("{}/api/v1/crates/{}", api.trim_end_matches('/'), name);
+    let body: Option<CrateBody> = get_json(config, &url)?;
+    Ok(body.map(|b| b.krate))
+}
+
+/// Fetches the owners of `name`, which is empty if the registry does not
+/// know the crate.
+pub fn owners(config: &Config, api: &str, name: &str) -> CargoResult<Vec<Owner>> {
+    let url = format!(
+        "{}/api/v1/crates/{}/owners",
+        api.trim_end_matches('/'),
+        name
+    );
+    let body: Option<Owners> = get_json(config, &url)?;
+    Ok(body.map(|b| b.users).unwrap_or_default())
+}
+
+/// Percent-encodes `s` for use in a URL.
+fn encode(s: &str) -> String {
+    curl::easy::Easy::new().url_encode(s.as_bytes())
+}
+
+/// Fetches every published version of `name`, including yanked ones.
+///
+/// Returns an empty list if the registry does not know the crate.
+pub fn versions(config: &Config, api: &str, name: &str) -> CargoResult<Vec<Version>> {
+    let url = format!(
+        "{}/api/v1/crates/{}/versions",
+        api.trim_end_matches('/'),
+        name
+    );
+    let body: Option<Versions> = get_json(config, &url)?;
+    Ok(body.map(|b| b.versions).unwrap_or_default())
+}
+
+/// Performs a GET request with cargo's HTTP configuration and decodes the
+/// JSON response, or returns `None` if the resource does not exist.
+///
+/// Failed requests are retried as `request_retrying` describes. A cached
+/// response is used if it is still fresh, if cargo is offline, or if the
+/// server says it has not changed.
+fn get_json<T: DeserializeOwned>(config: &Config, url: &str) -> CargoResult<Option<T>> {
+    let cached = read_cached(config, url)?;
+    if let Some((_, body)) = &cached {
+        if config.offline() || is_fresh(config, url) {
+            return decode(url, body).map(Some);
+        }
+    }
+    if config.offline() {
+        bail!(
+            "`{}` is not in the API cache, and cannot be requested \
+             while offline",
+            url
+        );
+    }
+    let etag = cached
+        .as_ref()
+        .map(|(etag, _)| etag.as_str())
+        .filter(|etag| !etag.is_empty());
+    let response = request_retrying(config, url, etag, None)?;
+    let (code, mut body, new_etag) = (response.code, response.body, response.etag);
+
+    match code {
+        200 => {
+            let keep_all = CACHE.lock().unwrap().is_some();
+            if new_etag.is_some() || keep_all {
+                write_cached(config, url, new_etag.as_deref().unwrap_or(""), &body)?;
+            }
+        }
+        // Writing the response again restarts its TTL.
+        304 if cached.is_some() => {
+            let (etag, cached) = cached.unwrap();
+            write_cached(config, url, &etag, &cached)?;
+            body = cached;
+        }
+        404 => return Ok(None),
+        401 | 403 => bail!(
+            "`{}` requires authentication (HTTP status {}); \
+             check the registry's token",
+            url,
+            code
+        ),
+        code => {
+            return Err(exit::error(
+                Failure::Network,
+                format!("failed to get `{}` (HTTP status {})", url, code),
+            ))
+        }
+    }
+    decode(url, &body).map(Some)
+}
+
+fn decode<T: DeserializeOwned>(url: &str, body: &[u8]) -> CargoResult<T> {
+    let value = serde_json::from_slice(body)
+        .chain_err(|| format!("invalid JSON response from `{}`", url))?;
+    Ok(value)
+}
+
+/// Waits until the next request to crates.io is allowed.
+fn throttle() {
+    let mut last = LAST_CRATES_IO_REQUEST.lock().unwrap();
+    if let Some(last) = *last {
+        let elapsed = last.elapsed();
+        if elapsed < CRATES_IO_INTERVAL {
+            thread::sleep(CRATES_IO_INTERVAL - elapsed);
+        }
+    }
+    *last = Some(Instant::now());
+}
+
+/// Where the cached response for `url` is kept: its ETag (or an empty line)
+/// on the first line, then the body.
+fn cache_path(config: &Config, url: &str) -> PathBuf {
+    let dir = match &*CACHE.lock().unwrap() {
+        Some((dir, _)) => dir.clone(),
+        None => config
+            .home()
+            .join("cargo-clone")
+            .join("api")
+            .into_path_unlocked(),
+    };
+    dir.join(short_hash(&url))
+}
+
+/// Returns whether the cached response for `url` is younger than the TTL of
+/// `--api-cache`. Responses are never fresh without it.
+fn is_fresh(config: &Config, url: &str) -> bool {
+    let ttl = match &*CACHE.lock().unwrap() {
+        Some((_, ttl)) => *ttl,
+        None => return false,
+    };
+    fs::metadata(cache_path(config, url))
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < ttl)
+}
+
+/// Returns the ETag and body of the cached response for `url`, if any.
+fn read_cached(config: &Config, url: &str) -> CargoResult<Option<(String, Vec<u8>)>> {
+    let contents = match fs::read(cache_path(config, url)) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(contents.iter().position(|&b| b == b'\n').map(|newline| {
+        let etag = String::from_utf8_lossy(&contents[..newline]).into_owned();
+        (etag, contents[newline + 1..].to_vec())
+    }))
+}
+
+fn write_cached(config: &Config, url: &str, etag: &str, body: &[u8]) -> CargoResult<()> {
+    let path = cache_path(config, url);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut contents = format!("{}\n", etag).into_bytes();
+    contents.extend_from_slice(body);
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// A response to a GET request.
+struct Response {
+    code: u32,
+    body: Vec<u8>,
+    /// The `Retry-After` delay, if the response has one in seconds.
+    retry_after: Option<Duration>,
+    etag: Option<String>,
+}
+
+/// Performs a GET request, returning the status code, the body, and the
+/// `Retry-After` delay if the response has one in seconds.
+///
+/// Failed requests are retried as `request_retrying` describes.
+pub fn get(config: &Config, url: &str) -> CargoResult<(u32, Vec<u8>, Option<Duration>)> {
+    let response = request_retrying(config, url, None, None)?;
+    Ok((response.code, response.body, response.retry_after))
+}
+
+/// Downloads `url` into `part`, resuming from whatever an interrupted
+/// download left there, if the server supports ranges. Returns the status
+/// code, and on success the complete file, which is left at `part` for the
+/// caller to verify and move into place.
+///
+/// If the download fails, `part` keeps what was received, so that the next
+/// try resumes where this one stopped.
+pub fn download(config: &Config, url: &str, part: &Path) -> CargoResult<(u32, Vec<u8>)> {
+    fs::create_dir_all(part.parent().unwrap())?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(part)?;
+    let mut response = request_retrying(config, url, None, Some(&mut file))?;
+    // The part is longer than the file, or the file changed under it.
+    if response.code == 416 {
+        file.set_len(0)?;
+        response = request_retrying(config, url, None, Some(&mut file))?;
+    }
+    match response.code {
+        200 | 206 => Ok((200, fs::read(part)?)),
+        code => Ok((code, response.body)),
+    }
+}
+
+/// Performs a GET request, retrying it after network errors, server errors,
+/// and rate limiting, up to `net.retry` times.
+///
+/// Retries wait with jittered exponential backoff, or for the `Retry-After`
+/// delay the server asks for. The response of the last try is returned,
+/// whatever its status. A download into `file` resumes with each retry.
+fn request_retrying(
+    config: &Config,
+    url: &str,
+    etag: Option<&str>,
+    mut file: Option<&mut fs::File>,
+) -> CargoResult<Response> {
+    let max_retries = if NO_RETRIES.with(Cell::get) {
+        0
+    } else {
+        config
+            .get::<Option<u32>>("net.retry")?
+            .unwrap_or(MAX_RETRIES)
+    };
+    let mut backoff = FIRST_BACKOFF;
+    let mut retries = 0;
+    loop {
+        if url.starts_with(CRATES_IO_API) {
+            throttle();
+        }
+        let (reason, retry_after) = match request(config, url, etag, file.as_deref_mut()) {
+            Ok(response) => {
+                let transient = response.code == 429 || response.code >= 500;
+                if !transient || retries == max_retries {
+                    return Ok(response);
+                }
+                let reason = if response.code == 429 {
+                    format!("is rate limited (HTTP status {})", response.code)
+                } else {
+                    format!("failed (HTTP status {})", response.code)
+                };
+                (reason, response.retry_after)
+            }
+            Err(e) if is_spurious(&e) && retries < max_retries => {
+                (format!("failed ({})", e.find_root_cause()), None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let wait = match retry_after {
+            Some(wait) if wait > MAX_RETRY_AFTER => {
+                return Err(exit::error(
+                    Failure::Network,
+                    format!(
+                        "`{}` {}, and asked to retry after {} seconds",
+                        url,
+                        reason,
+                        wait.as_secs()
+                    ),
+                ))
+            }
+            Some(wait) => wait,
+            None => jitter(backoff),
+        };
+        config.shell().warn(format!(
+            "`{}` {}, retrying in {:.1} seconds ({} tries remaining)",
+            url,
+            reason,
+            wait.as_secs_f64(),
+            max_retries - retries
+        ))?;
+        thread::sleep(wait);
+        backoff *= 2;
+        retries += 1;
+    }
+}
+
+/// Scales `backoff` by a random factor between 0.5 and 1.5, so that clients
+/// that failed together do not all retry at once.
+fn jitter(backoff: Duration) -> Duration {
+    backoff.mul_f64(rand::thread_rng().gen_range(0.5, 1.5))
+}
+
+/// Returns whether `err` is a network error that may not happen again.
+fn is_spurious(err: &failure::Error) -> bool {
+    err.iter_chain().any(|e| {
+        e.downcast_ref::<curl::Error>().is_some_and(|e| {
+            e.is_couldnt_connect()
+                || e.is_couldnt_resolve_proxy()
+                || e.is_couldnt_resolve_host()
+                || e.is_operation_timedout()
+                || e.is_recv_error()
+                || e.is_send_error()
+                || e.is_got_nothing()
+                || e.is_partial_file()
+                || e.is_http2_stream_error()
+                || e.is_ssl_connect_error()
+        })
+    })
+}
+
+/// Performs a single GET request, which is conditional if an `etag` is given.
+///
+/// With a `file`, the body of a successful response goes there instead: it
+/// is appended to what the file holds if the server sends the range asked
+/// for, and replaces it if the server sends the whole file.
+fn request(
+    config: &Config,
+    url: &str,
+    etag: Option<&str>,
+    mut file: Option<&mut fs::File>,
+) -> CargoResult<Response> {
+    let mut handle = cargo::ops::http_handle(config)?;
+    handle.get(true)?;
+    handle.url(url)?;
+    if !has_configured_proxy(config)? {
+        if let Some(proxy) = env_proxy(url) {
+            handle.proxy(&proxy)?;
+        }
+    }
+    if let Some(no_proxy) = env_var(&["no_proxy", "NO_PROXY"]) {
+        handle.noproxy(&no_proxy)?;
+    }
+    if let Some(rate) = rate_limit() {
+        handle.max_recv_speed(rate)?;
+    }
+    let mut headers = List::new();
+    headers.append("Accept: application/json")?;
+    if let Some(token) = auth::token_for(url) {
+        headers.append(&format!("Authorization: {}", token))?;
+    }
+    if let Some(etag) = etag {
+        headers.append(&format!("If-None-Match: {}", etag))?;
+    }
+    let offset = match &file {
+        Some(file) => file.metadata()?.len(),
+        None => 0,
+    };
+    if offset > 0 {
+        headers.append(&format!("Range: bytes={}-", offset))?;
+    }
+    handle.http_headers(headers)?;
+
+    let mut body = Vec::new();
+    let mut retry_after = None;
+    let mut new_etag = None;
+    // The status of the response being received, read from its status line
+    // so that the body can be sent to the right place as it arrives.
+    let status = Cell::new(0);
+    let mut restarted = false;
+    let mut write_error = None;
+    // Downloads into a file are of `.crate` files, which may be large.
+    let mut progress = match file {
+        Some(_) => {
+            handle.progress(true)?;
+            Some(Progress::new(
+                "Downloading",
+                ProgressStyle::Percentage,
+                config,
+            ))
+        }
+        None => None,
+    };
+    let result;
+    {
+        let mut transfer = handle.transfer();
+        if let Some(progress) = &mut progress {
+            transfer.progress_function(move |total, now, _, _| {
+                let (total, now) = (total as u64 + offset, now as u64 + offset);
+                if total > offset {
+                    let msg = format!(": {} of {}", human_size(now), human_size(total));
+                    // Progress is not worth failing the download over.
+                    let _ = progress.tick(now as usize, total as usize, &msg);
+                }
+                true
+            })?;
+        }
+        transfer.write_function(|data| {
+            let file = match file.as_deref_mut() {
+                Some(file) if status.get() == 200 || status.get() == 206 => file,
+                _ => {
+                    body.extend_from_slice(data);
+                    return Ok(data.len());
+                }
+            };
+            let mut written = Ok(());
+            if status.get() == 200 && offset > 0 && !restarted {
+                restarted = true;
+                written = file.set_len(0);
+            }
+            match written.and_then(|()| file.write_all(data)) {
+                Ok(()) => Ok(data.len()),
+                Err(e) => {
+                    write_error = Some(e);
+                    Ok(0)
+                }
+            }
+        })?;
+        transfer.header_function(|header| {
+            let header = String::from_utf8_lossy(header);
+            if header.starts_with("HTTP/") {
+                let code = header.split_whitespace().nth(1);
+                status.set(code.and_then(|code| code.parse().ok()).unwrap_or(0));
+            }
+            let mut parts = header.splitn(2, ':');
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                if name.trim().eq_ignore_ascii_case("retry-after") {
+                    // The HTTP-date form is left to the usual backoff.
+                    retry_after = value.trim().parse().ok().map(Duration::from_secs);
+                } else if name.trim().eq_ignore_ascii_case("etag") {
+                    new_etag = Some(value.trim().to_string());
+                }
+            }
+            true
+        })?;
+        result = transfer.perform();
+    }
+    if let Some(e) = write_error {
+        Err(e).chain_err(|| format!("failed to write the download of `{}`", url))?;
+    }
+    result.chain_err(|| format!("failed to request `{}`", url))?;
+
+    Ok(Response {
+        code: handle.response_code()?,
+        body,
+        retry_after,
+        etag: new_etag,
+    })
+}
+
+/// Returns whether cargo's or git's config sets `http.proxy`, which cargo's
+/// HTTP handle already uses.
+fn has_configured_proxy(config: &Config) -> CargoResult<bool> {
+    if config.get_string("http.proxy")?.is_some() {
+        return Ok(true);
+    }
+    Ok(git2::Config::open_default()
+        .map(|git| git.get_str("http.proxy").is_ok())
+        .unwrap_or(false))
+}
+
+/// Returns the proxy the usual environment variables give for `url`.
+///
+/// libcurl reads most of these itself, but not `HTTP_PROXY` in upper case.
+fn env_proxy(url: &str) -> Option<String> {
+    let scheme_vars: &[&str] = if url.starts_with("https:") {
+        &["https_proxy", "HTTPS_PROXY"]
+    } else {
+        &["http_proxy", "HTTP_PROXY"]
+    };
+    env_var(scheme_vars).or_else(|| env_var(&["all_proxy", "ALL_PROXY"]))
+}
+
+/// Returns the first of the environment variables `names` that is set and
+/// not empty.
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
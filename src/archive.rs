@@ -0,0 +1,132 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writing a package's files as an archive instead of a directory, and
+//! reading `.crate` files.
+//!
+//! Archives contain the same files `clone` would copy, under a single
+//! top-level directory, like the `.crate` files registries serve.
+
+use std::fs::{self, File};
+use std::io::{self, Seek, Write};
+use std::path::{Component, Path};
+
+use cargo::util::{CargoResult, CargoResultExt};
+
+use failure::bail;
+
+use crate::walk::{self, Filter, Kind};
+
+/// Writes the files under `from` as a tar stream, each prefixed by `prefix`.
+///
+/// Symlinks are stored as links unless `dereference` is set.
+pub fn write_tar<W: Write>(
+    from: &Path,
+    prefix: &str,
+    filter: &Filter,
+    dereference: bool,
+    w: W,
+) -> CargoResult<W> {
+    let mut tar = tar::Builder::new(w);
+    tar.follow_symlinks(dereference);
+    for entry in walk::entries(from, filter, dereference)? {
+        let name = Path::new(prefix).join(&entry.rel);
+        match entry.kind {
+            Kind::Dir => tar.append_dir(name, &entry.path)?,
+            Kind::File | Kind::Symlink => tar.append_path_with_name(&entry.path, name)?,
+        }
+    }
+    Ok(tar.into_inner()?)
+}
+
+/// Writes the files under `from` as a zip archive, each prefixed by `prefix`.
+///
+/// Zip archives cannot hold symlinks, so they are always followed.
+pub fn write_zip<W: Write + Seek>(
+    from: &Path,
+    prefix: &str,
+    filter: &Filter,
+    w: W,
+) -> CargoResult<W> {
+    let mut zip = zip::ZipWriter::new(w);
+    for entry in walk::entries(from, filter, true)? {
+        // Zip entry names always use forward slashes.
+        let mut name = prefix.to_string();
+        for component in entry.rel.components() {
+            name.push('/');
+            name.push_str(&component.as_os_str().to_string_lossy());
+        }
+
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(mode(&fs::metadata(&entry.path)?));
+        if entry.kind == Kind::Dir {
+            zip.add_directory(name, options)?;
+        } else {
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(&entry.path)?, &mut zip)?;
+        }
+    }
+    Ok(zip.finish()?)
+}
+
+/// Unpacks the `.crate` file at `file` into `into`, returning the name of
+/// its single top-level directory, which is where the package is.
+///
+/// Every entry must be a relative path under that directory.
+pub fn unpack_crate(file: &Path, into: &Path) -> CargoResult<String> {
+    let gz = File::open(file).chain_err(|| format!("failed to open `{}`", file.display()))?;
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(gz));
+    let mut top: Option<String> = None;
+    let entries = tar
+        .entries()
+        .chain_err(|| format!("`{}` is not a gzipped tarball", file.display()))?;
+    for entry in entries {
+        let mut entry = entry.chain_err(|| format!("failed to read `{}`", file.display()))?;
+        let path = entry.path()?.into_owned();
+        let relative = path.components().all(|c| matches!(c, Component::Normal(_)));
+        let first = match path.components().next() {
+            Some(first) if relative => first.as_os_str().to_string_lossy().into_owned(),
+            _ => bail!(
+                "invalid .crate file `{}`: `{}` is not a relative path",
+                file.display(),
+                path.display()
+            ),
+        };
+        match &top {
+            Some(top) if *top != first => bail!(
+                "invalid .crate file `{}`: `{}` is not under `{}`",
+                file.display(),
+                path.display(),
+                top
+            ),
+            Some(_) => {}
+            None => top = Some(first),
+        }
+        entry.unpack_in(into)?;
+    }
+    match top {
+        Some(top) => Ok(top),
+        None => bail!("invalid .crate file `{}`: it is empty", file.display()),
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o755
+    } else {
+        0o644
+    }
+}
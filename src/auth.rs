@@ -0,0 +1,120 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tokens for registries that require authentication to download crates or
+//! use their API.
+//!
+//! Tokens come from cargo's credentials, as saved by `cargo login`, or from
+//! `CARGO_REGISTRIES_<NAME>_TOKEN`. Once a registry's token is known, it is
+//! sent with every request to the registry's index, download, and API URLs.
+
+use std::sync::Mutex;
+
+use cargo::core::SourceId;
+use cargo::util::config::ConfigValue;
+use cargo::util::into_url::IntoUrl;
+use cargo::util::{CargoResult, Config};
+
+use failure::bail;
+
+use crate::{index, sparse};
+
+/// URL prefixes and the tokens to send to them.
+static TOKENS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Returns the name cargo's config gives the registry behind `srcid`, or
+/// `None` for crates.io and for registries only given by URL.
+fn registry_name(config: &Config, srcid: SourceId) -> CargoResult<Option<String>> {
+    if sparse::is_crates_io(srcid) {
+        return Ok(None);
+    }
+    let registries = match config.get_table("registries")? {
+        Some(registries) => registries.val,
+        None => return Ok(None),
+    };
+    for (name, registry) in registries {
+        if let ConfigValue::Table(table, _) = registry {
+            if let Some(ConfigValue::String(index, _)) = table.get("index") {
+                if index.as_str().into_url().ok().as_ref() == Some(srcid.url()) {
+                    return Ok(Some(name));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Looks up the token for the registry behind `srcid` in cargo's
+/// credentials, failing with a message naming the registry if there is none.
+pub fn token(config: &Config, srcid: SourceId) -> CargoResult<String> {
+    if sparse::is_crates_io(srcid) {
+        return match config.get_string("registry.token")? {
+            Some(token) => Ok(token.val),
+            None => bail!("crates.io requires a token; run `cargo login` first"),
+        };
+    }
+    let name = match registry_name(config, srcid)? {
+        Some(name) => name,
+        None => bail!(
+            "{} requires a token, but only registries named in cargo's config \
+             can be given one; add it to [registries] and use --registry",
+            srcid
+        ),
+    };
+    match config.get_string(&format!("registries.{}.token", name))? {
+        Some(token) => Ok(token.val),
+        None => bail!(
+            "registry `{}` requires a token; run `cargo login --registry {}` \
+             or set CARGO_REGISTRIES_{}_TOKEN",
+            name,
+            name,
+            name.to_uppercase().replace('-', "_")
+        ),
+    }
+}
+
+/// Sends `token` with every request to a URL starting with `prefix`.
+pub fn authorize(prefix: &str, token: &str) {
+    let mut tokens = TOKENS.lock().unwrap();
+    if !tokens.iter().any(|(p, _)| p == prefix) {
+        tokens.push((prefix.to_string(), token.to_string()));
+    }
+}
+
+/// Returns the token to send with a request to `url`, if any.
+pub fn token_for(url: &str) -> Option<String> {
+    TOKENS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(prefix, _)| url.starts_with(prefix.as_str()))
+        .map(|(_, token)| token.clone())
+}
+
+/// Reads whether the registry behind `srcid` requires authentication from
+/// its `config.json`, and if so, authorizes requests to its index, download,
+/// and API URLs with its token.
+///
+/// The index must already be up to date.
+pub fn setup(config: &Config, srcid: SourceId) -> CargoResult<()> {
+    let registry = match index::read_auth_config(config, srcid)? {
+        Some(registry) if registry.auth_required => registry,
+        _ => return Ok(()),
+    };
+    let token = token(config, srcid)?;
+    if sparse::is_sparse(srcid) {
+        authorize(&sparse::index_url(srcid), &token);
+    }
+    // Everything before the first placeholder is the same for every crate.
+    let dl = registry.dl.split('{').next().unwrap();
+    authorize(dl, &token);
+    if let Some(api) = &registry.api {
+        authorize(api, &token);
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The clones made so far, recorded in `cargo-clone/clones.json` in the
+//! cargo home so that they can be found again.
+//!
+//! The file is locked while it is read and rewritten, so that clones made at
+//! the same time, by one run or several, are all recorded.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cargo::util::{CargoResult, CargoResultExt, Config, FileLock};
+
+use serde::{Deserialize, Serialize};
+
+const FILE: &str = "clones.json";
+
+/// One clone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub name: String,
+    pub version: String,
+    /// The source the package was cloned from, as a URL such as
+    /// `registry+https://github.com/rust-lang/crates.io-index`, or `None`
+    /// for a `.crate` file.
+    pub source: Option<String>,
+    /// The absolute path of the clone's directory or archive.
+    pub path: PathBuf,
+    /// The SHA-256 of the `.crate` file the clone was made from, as the
+    /// registry's index records it and the download was checked against.
+    pub checksum: Option<String>,
+    /// When the clone was made, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub cloned_at: String,
+}
+
+/// Locks the database and reads every record in it.
+fn open(config: &Config) -> CargoResult<(FileLock, Vec<Record>)> {
+    let mut file = config
+        .home()
+        .join("cargo-clone")
+        .open_rw(FILE, config, "the clone database")?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let records = if contents.trim().is_empty() {
+        Vec::new()
+    } else {
+        serde_json::from_str(&contents)
+            .chain_err(|| format!("failed to parse `{}`", file.path().display()))?
+    };
+    Ok((file, records))
+}
+
+/// Replaces what is in the locked database with `records`.
+fn save(mut file: FileLock, records: &[Record]) -> CargoResult<()> {
+    file.seek(SeekFrom::Start(0))?;
+    file.file().set_len(0)?;
+    file.write_all(serde_json::to_string_pretty(records)?.as_bytes())?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Returns every record, oldest first.
+pub fn load(config: &Config) -> CargoResult<Vec<Record>> {
+    Ok(open(config)?.1)
+}
+
+/// Records a clone, replacing any earlier record of a clone at its path.
+pub fn add(config: &Config, record: Record) -> CargoResult<()> {
+    let (file, mut records) = open(config)?;
+    records.retain(|r| r.path != record.path);
+    records.push(record);
+    save(file, &records)
+}
+
+/// Forgets the clones at `paths`.
+pub fn remove(config: &Config, paths: &[PathBuf]) -> CargoResult<()> {
+    let (file, mut records) = open(config)?;
+    records.retain(|r| !paths.contains(&r.path));
+    save(file, &records)
+}
+
+/// Returns the current time as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn now() -> String {
+    timestamp(SystemTime::now())
+}
+
+/// Returns `time` as `YYYY-MM-DDTHH:MM:SSZ`, which sorts like the times do.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+
+    // From days since 1970-01-01 to a date in the proleptic Gregorian
+    // calendar, counting years from March so leap days come last.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
@@ -0,0 +1,178 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Defaults for command-line options, from the user's
+//! `~/.config/cargo-clone/config.toml` and from the `[clone]` table of
+//! cargo's own configuration, such as `.cargo/config.toml`. For example:
+//!
+//! ```toml
+//! store = "/srv/crates"
+//! jobs = 8
+//! keep-archive = true
+//! api = "https://crates.example.com"
+//! ```
+//!
+//! Cargo's configuration wins over the user's file, as it may be specific to
+//! a project, and flags given on the command line win over both. Like other
+//! cargo settings, the `[clone]` keys can also be set in the environment, as
+//! `CARGO_CLONE_JOBS` and so on.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use cargo::util::{CargoResult, CargoResultExt, Config};
+
+use failure::bail;
+
+use serde::Deserialize;
+
+/// The defaults that were configured. Paths are absolute.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Defaults {
+    /// The store for `--into-store`.
+    pub store: Option<PathBuf>,
+    /// How many crates to clone at once, as with `--jobs`.
+    pub jobs: Option<usize>,
+    /// Keep the downloaded `.crate` file next to each clone.
+    #[serde(default)]
+    pub keep_archive: bool,
+    /// Keep the downloaded `.crate` files in this directory.
+    pub keep_archive_in: Option<PathBuf>,
+    /// The web API to use, as with `--api`.
+    pub api: Option<String>,
+}
+
+/// Returns where the user's configuration file is, whether or not it exists.
+fn user_file() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("cargo-clone").join("config.toml"))
+}
+
+/// Reads the defaults from the user's configuration file, then overrides
+/// them with those in cargo's configuration.
+pub fn load(config: &Config) -> CargoResult<Defaults> {
+    let mut defaults = match user_file() {
+        Some(path) => read_file(&path)?,
+        None => Defaults::default(),
+    };
+
+    if let Some(store) = config.get_path("clone.store")? {
+        defaults.store = Some(store.val);
+    }
+    if let Some(jobs) = config.get_i64("clone.jobs")? {
+        if jobs.val < 1 {
+            bail!("`clone.jobs` must be at least 1, found {}", jobs.val);
+        }
+        defaults.jobs = Some(jobs.val as usize);
+    }
+    if let Some(keep) = config.get_bool("clone.keep-archive")? {
+        defaults.keep_archive = keep.val;
+    }
+    if let Some(dir) = config.get_path("clone.keep-archive-in")? {
+        defaults.keep_archive_in = Some(dir.val);
+    }
+    if let Some(api) = config.get_string("clone.api")? {
+        defaults.api = Some(api.val);
+    }
+    Ok(defaults)
+}
+
+/// Reads the defaults in the file at `path`, if there is one. Relative
+/// paths in it are taken to be relative to the file's directory.
+fn read_file(path: &Path) -> CargoResult<Defaults> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Defaults::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut defaults: Defaults =
+        toml::from_str(&contents).chain_err(|| format!("failed to parse `{}`", path.display()))?;
+    let dir = path.parent().expect("the file is in a directory");
+    defaults.store = defaults.store.map(|store| dir.join(store));
+    defaults.keep_archive_in = defaults.keep_archive_in.map(|keep| dir.join(keep));
+    if defaults.jobs == Some(0) {
+        bail!("`jobs` in `{}` must be at least 1", path.display());
+    }
+    Ok(defaults)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cargo::core::Shell;
+
+    #[test]
+    fn read_file_resolves_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "store = \"crates\"\nkeep-archive-in = \"/srv/archives\"\njobs = 3\n",
+        )
+        .unwrap();
+        let defaults = read_file(&path).unwrap();
+        assert_eq!(defaults.store, Some(dir.path().join("crates")));
+        assert_eq!(
+            defaults.keep_archive_in,
+            Some(PathBuf::from("/srv/archives"))
+        );
+        assert_eq!(defaults.jobs, Some(3));
+        assert!(!defaults.keep_archive);
+    }
+
+    #[test]
+    fn read_file_without_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let defaults = read_file(&dir.path().join("config.toml")).unwrap();
+        assert!(defaults.store.is_none() && defaults.jobs.is_none());
+    }
+
+    #[test]
+    fn read_file_rejects_bad_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "jobs = 0\n").unwrap();
+        assert!(read_file(&path).is_err());
+        fs::write(&path, "job = 2\n").unwrap();
+        assert!(read_file(&path).is_err());
+    }
+
+    #[test]
+    fn load_prefers_cargo_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("xdg").join("cargo-clone");
+        fs::create_dir_all(&user).unwrap();
+        fs::write(
+            user.join("config.toml"),
+            "store = \"crates\"\njobs = 2\napi = \"https://crates.example.com\"\n",
+        )
+        .unwrap();
+        let project = dir.path().join("project");
+        fs::create_dir_all(project.join(".cargo")).unwrap();
+        fs::write(
+            project.join(".cargo").join("config"),
+            "[clone]\njobs = 4\nkeep-archive = true\n",
+        )
+        .unwrap();
+
+        env::set_var("XDG_CONFIG_HOME", dir.path().join("xdg"));
+        let config = Config::new(Shell::new(), project, dir.path().join("cargo-home"));
+        let defaults = load(&config).unwrap();
+        assert_eq!(defaults.store, Some(user.join("crates")));
+        assert_eq!(defaults.jobs, Some(4));
+        assert!(defaults.keep_archive);
+        assert_eq!(defaults.api.as_deref(), Some("https://crates.example.com"));
+    }
+}
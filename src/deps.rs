@@ -0,0 +1,182 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Walking a package's dependencies through the registry index.
+//!
+//! This activates optional dependencies through features the way cargo does,
+//! but picks the newest version matching each requirement instead of
+//! unifying them, so it may find more versions than a real build would use.
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use cargo::core::source::Source;
+use cargo::core::{FeatureValue, InternedString, PackageId, Summary};
+use cargo::util::CargoResult;
+
+use failure::bail;
+
+/// The packages reached from some roots.
+pub struct Closure {
+    /// Every package reached, roots included.
+    pub pkgs: BTreeSet<PackageId>,
+    /// Names of the dependencies skipped because they come from another
+    /// source than the roots.
+    pub other: BTreeSet<InternedString>,
+}
+
+/// Walks the normal and build dependencies of `roots`, which must come from
+/// `src`, with their default features.
+///
+/// With `max_depth`, dependencies more than that many levels below a root are
+/// not followed; a depth of 0 only returns the roots.
+pub fn closure<T: Source + ?Sized>(
+    src: &mut T,
+    roots: &[Summary],
+    max_depth: Option<usize>,
+) -> CargoResult<Closure> {
+    let srcid = src.source_id();
+    let mut summaries = HashMap::new();
+    let mut features: HashMap<PackageId, BTreeSet<InternedString>> = HashMap::new();
+    let mut depths = HashMap::new();
+    let mut other = BTreeSet::new();
+    let mut queue = VecDeque::new();
+
+    let default = BTreeSet::from([InternedString::new("default")]);
+    for root in roots {
+        summaries.insert(root.package_id(), root.clone());
+        activate(
+            root.package_id(),
+            &default,
+            0,
+            &mut features,
+            &mut depths,
+            &mut queue,
+        );
+    }
+
+    while let Some(id) = queue.pop_front() {
+        let depth = depths[&id];
+        if max_depth.is_some_and(|max| depth >= max) {
+            continue;
+        }
+        let summary: Summary = summaries[&id].clone();
+        let (enabled, dep_features) = enabled_deps(&summary, &features[&id]);
+
+        for dep in summary.dependencies() {
+            if !dep.is_transitive() || dep.is_optional() && !enabled.contains(&dep.name_in_toml()) {
+                continue;
+            }
+            if dep.source_id() != srcid {
+                other.insert(dep.package_name());
+                continue;
+            }
+
+            let pick = src
+                .query_vec(dep)?
+                .into_iter()
+                .max_by(|a, b| a.version().cmp(b.version()));
+            let pick = match pick {
+                Some(pick) => pick,
+                None => bail!(
+                    "no version of package '{}' matches '{}', as required by {}",
+                    dep.package_name(),
+                    dep.version_req(),
+                    id
+                ),
+            };
+
+            let mut wanted: BTreeSet<InternedString> = dep.features().iter().cloned().collect();
+            if let Some(extra) = dep_features.get(&dep.name_in_toml()) {
+                wanted.extend(extra);
+            }
+            if dep.uses_default_features() {
+                wanted.insert(InternedString::new("default"));
+            }
+            let pick_id = pick.package_id();
+            summaries.entry(pick_id).or_insert(pick);
+            activate(
+                pick_id,
+                &wanted,
+                depth + 1,
+                &mut features,
+                &mut depths,
+                &mut queue,
+            );
+        }
+    }
+
+    Ok(Closure {
+        pkgs: summaries.keys().cloned().collect(),
+        other,
+    })
+}
+
+/// Queues `id` to be walked again if `wanted` turns on features it did not
+/// have yet, or if it is now reached at a shallower depth.
+fn activate(
+    id: PackageId,
+    wanted: &BTreeSet<InternedString>,
+    depth: usize,
+    features: &mut HashMap<PackageId, BTreeSet<InternedString>>,
+    depths: &mut HashMap<PackageId, usize>,
+    queue: &mut VecDeque<PackageId>,
+) {
+    let first = !features.contains_key(&id);
+    let have = features.entry(id).or_default();
+    let new_features = !wanted.is_subset(have);
+    have.extend(wanted);
+
+    let shallower = depths.get(&id).is_none_or(|&d| depth < d);
+    if shallower {
+        depths.insert(id, depth);
+    }
+    if first || new_features || shallower {
+        queue.push_back(id);
+    }
+}
+
+/// Expands `features` of `summary` into the optional dependencies they turn
+/// on and the features they ask of each dependency.
+fn enabled_deps(
+    summary: &Summary,
+    features: &BTreeSet<InternedString>,
+) -> (
+    HashSet<InternedString>,
+    HashMap<InternedString, BTreeSet<InternedString>>,
+) {
+    let mut enabled = HashSet::new();
+    let mut dep_features: HashMap<_, BTreeSet<_>> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<InternedString> = features.iter().cloned().collect();
+    while let Some(feature) = pending.pop() {
+        if !seen.insert(feature) {
+            continue;
+        }
+        match summary.features().get(&feature) {
+            Some(values) => {
+                for value in values {
+                    match value {
+                        FeatureValue::Feature(f) => pending.push(*f),
+                        FeatureValue::Crate(dep) => {
+                            enabled.insert(*dep);
+                        }
+                        FeatureValue::CrateFeature(dep, f) => {
+                            enabled.insert(*dep);
+                            dep_features.entry(*dep).or_default().insert(*f);
+                        }
+                    }
+                }
+            }
+            // An optional dependency is also a feature of its own name.
+            None => {
+                enabled.insert(feature);
+            }
+        }
+    }
+    (enabled, dep_features)
+}
@@ -0,0 +1,91 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Exit codes for the failures that scripts are likely to handle, so that
+//! they need not look at the messages to tell them apart:
+//!
+//! - 2: a crate was not found.
+//! - 3: no version of a crate matches the one asked for.
+//! - 4: a destination already exists and is not empty.
+//! - 5: a registry or repository could not be reached, or failed a request.
+//! - 6: some or all of several crates failed to clone, each for its own
+//!   reason, which is in the summary.
+//!
+//! An interrupted run exits with 128 plus the number of the signal, such as
+//! 130 for Ctrl-C, as shells do; see `interrupt`. Any other failure exits
+//! with 101, as with cargo.
+
+use std::fmt;
+
+use cargo::util::errors::HttpNot200;
+
+/// The failures that have an exit code of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    NotFound,
+    NoVersion,
+    Exists,
+    Network,
+    Partial,
+}
+
+impl Failure {
+    fn code(self) -> i32 {
+        match self {
+            Failure::NotFound => 2,
+            Failure::NoVersion => 3,
+            Failure::Exists => 4,
+            Failure::Network => 5,
+            Failure::Partial => 6,
+        }
+    }
+}
+
+/// An error that is one of the failures in `Failure`. It shows as its message
+/// only.
+#[derive(Debug)]
+struct Error {
+    kind: Failure,
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns an error for `kind` of failure, like `format_err!` would with the
+/// message.
+pub fn error(kind: Failure, message: String) -> failure::Error {
+    Error { kind, message }.into()
+}
+
+/// Returns the exit code for `err`, by the first error in its chain that
+/// has one.
+pub fn code(err: &failure::Error) -> i32 {
+    for e in err.iter_chain() {
+        if let Some(failure) = e.downcast_ref::<Error>() {
+            return failure.kind.code();
+        }
+        if e.downcast_ref::<curl::Error>().is_some() || e.downcast_ref::<HttpNot200>().is_some() {
+            return Failure::Network.code();
+        }
+        if let Some(e) = e.downcast_ref::<git2::Error>() {
+            match e.class() {
+                git2::ErrorClass::Net | git2::ErrorClass::Ssl | git2::ErrorClass::Ssh => {
+                    return Failure::Network.code()
+                }
+                _ => {}
+            }
+        }
+    }
+    101
+}
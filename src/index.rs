@@ -0,0 +1,263 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Direct access to registry index entries.
+//!
+//! Cargo's `Source::query` hides some of what the index records, such as
+//! yanked versions, so the few places that need it read the entries here.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use cargo::core::SourceId;
+use cargo::sources::registry::RegistryConfig;
+use cargo::util::{short_hash, CargoResult, CargoResultExt, Config};
+
+use failure::bail;
+
+use serde::Deserialize;
+
+use crate::sparse;
+
+/// One line of a crate's index file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    pub name: String,
+    pub vers: String,
+    #[serde(default)]
+    pub yanked: bool,
+    /// The minimum supported Rust version, if the crate declares one.
+    pub rust_version: Option<String>,
+    /// The SHA-256 checksum of the `.crate` file.
+    pub cksum: String,
+}
+
+/// The parts of a registry's `config.json` that cargo's `RegistryConfig`
+/// does not know about yet.
+#[derive(Deserialize, Debug)]
+pub struct AuthConfig {
+    pub dl: String,
+    pub api: Option<String>,
+    /// Whether downloads and API requests need a token.
+    #[serde(rename = "auth-required", default)]
+    pub auth_required: bool,
+}
+
+/// Reads all index entries for `name` from the registry behind `srcid`.
+///
+/// The index must already be up to date (`Source::update` has been called)
+/// and the package cache lock must be held. Sources that have no index, such
+/// as path, git, and directory sources, yield no entries.
+pub fn read_entries(config: &Config, srcid: SourceId, name: &str) -> CargoResult<Vec<IndexEntry>> {
+    let contents = match read_file(config, srcid, &index_path(name))? {
+        Some(contents) => contents,
+        None => return Ok(Vec::new()),
+    };
+    parse_entries(&contents, name)
+}
+
+/// Lists the paths, relative to the index root, of every crate's file in the
+/// registry behind `srcid`.
+///
+/// The same preconditions as for `read_entries` apply.
+pub fn crate_paths(config: &Config, srcid: SourceId) -> CargoResult<Vec<String>> {
+    let mut paths = Vec::new();
+    if sparse::is_sparse(srcid) {
+        bail!("a sparse index cannot list its crates; try --protocol git");
+    } else if srcid.is_remote_registry() {
+        let repo = open_git_index(config, srcid)?;
+        let head = repo.refname_to_id("refs/remotes/origin/master")?;
+        let tree = repo.find_commit(head)?.tree()?;
+        tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    paths.push(format!("{}{}", dir, name));
+                }
+            }
+            git2::TreeWalkResult::Ok
+        })?;
+    } else if srcid.is_registry() {
+        let root = srcid.url().to_file_path().expect("path must be valid");
+        let index = root.join("index");
+        for entry in walkdir::WalkDir::new(&index).min_depth(1) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let rel = entry.path().strip_prefix(&index).unwrap();
+                let rel: Vec<_> = rel.iter().map(|c| c.to_string_lossy()).collect();
+                paths.push(rel.join("/"));
+            }
+        }
+    }
+
+    // Crates live in subdirectories; anything else, such as `config.json` or
+    // a `.github` directory, is not a crate.
+    paths.retain(|path| path.contains('/') && !path.split('/').any(|c| c.starts_with('.')));
+    Ok(paths)
+}
+
+/// Returns the name of the crate whose index file is at `path`, as given by
+/// `crate_paths`.
+pub fn crate_name(config: &Config, srcid: SourceId, path: &str) -> CargoResult<Option<String>> {
+    let contents = match read_file(config, srcid, path)? {
+        Some(contents) => contents,
+        None => return Ok(None),
+    };
+    Ok(parse_entries(&contents, path)?
+        .into_iter()
+        .next()
+        .map(|entry| entry.name))
+}
+
+fn parse_entries(contents: &[u8], name: &str) -> CargoResult<Vec<IndexEntry>> {
+    let mut entries = Vec::new();
+    for line in contents.split(|&b| b == b'\n') {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let entry: IndexEntry = serde_json::from_slice(line)
+            .chain_err(|| format!("failed to parse index entry for '{}'", name))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Reads the registry's `config.json`, if it has one.
+///
+/// The same preconditions as for `read_entries` apply.
+pub fn read_config(config: &Config, srcid: SourceId) -> CargoResult<Option<RegistryConfig>> {
+    match read_file(config, srcid, "config.json")? {
+        Some(contents) => Ok(Some(
+            serde_json::from_slice(&contents)
+                .chain_err(|| "failed to parse registry config.json")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Reads the registry's `config.json` for whether it requires
+/// authentication, if it has one.
+///
+/// The same preconditions as for `read_entries` apply.
+pub fn read_auth_config(config: &Config, srcid: SourceId) -> CargoResult<Option<AuthConfig>> {
+    match read_file(config, srcid, "config.json")? {
+        Some(contents) => Ok(Some(
+            serde_json::from_slice(&contents)
+                .chain_err(|| "failed to parse registry config.json")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn read_file(config: &Config, srcid: SourceId, rel: &str) -> CargoResult<Option<Vec<u8>>> {
+    if sparse::is_sparse(srcid) {
+        sparse::fetch(config, srcid, rel)
+    } else if srcid.is_remote_registry() {
+        read_git_index(config, srcid, rel)
+    } else if srcid.is_registry() {
+        let root = srcid.url().to_file_path().expect("path must be valid");
+        match fs::read(root.join("index").join(rel)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the path of a crate's file relative to the index root.
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[0..2], &name[2..4], name),
+    }
+}
+
+/// Returns the `.crate` file that was downloaded for `name` at `vers`, if the
+/// registry behind `srcid` keeps one.
+///
+/// The package must already have been downloaded and the package cache lock
+/// must be held.
+pub fn crate_file(config: &Config, srcid: SourceId, name: &str, vers: &str) -> Option<PathBuf> {
+    crate_path(config, srcid, name, vers).filter(|path| path.is_file())
+}
+
+/// Returns where the registry behind `srcid` keeps the `.crate` file of
+/// `name` at `vers` once it is downloaded, if it keeps one.
+pub fn crate_path(config: &Config, srcid: SourceId, name: &str, vers: &str) -> Option<PathBuf> {
+    let file = format!("{}-{}.crate", name, vers);
+    if sparse::is_sparse(srcid) {
+        Some(sparse::cache_root(config, srcid).join(file))
+    } else if srcid.is_remote_registry() {
+        Some(
+            config
+                .registry_cache_path()
+                .join(registry_dir(srcid))
+                .into_path_unlocked()
+                .join(file),
+        )
+    } else if srcid.is_registry() {
+        let root = srcid.url().to_file_path().expect("path must be valid");
+        Some(root.join(file))
+    } else {
+        None
+    }
+}
+
+/// Returns where the `.crate` file at `path` is kept while it is being
+/// downloaded.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Returns where cargo unpacks `name` at `vers` from the registry behind
+/// `srcid`.
+pub fn unpacked_path(config: &Config, srcid: SourceId, name: &str, vers: &str) -> PathBuf {
+    config
+        .registry_source_path()
+        .join(registry_dir(srcid))
+        .into_path_unlocked()
+        .join(format!("{}-{}", name, vers))
+}
+
+/// Mirrors the directory naming of cargo's `RemoteRegistry`.
+pub fn registry_dir(srcid: SourceId) -> String {
+    format!(
+        "{}-{}",
+        srcid.url().host_str().unwrap_or(""),
+        short_hash(&srcid)
+    )
+}
+
+fn open_git_index(config: &Config, srcid: SourceId) -> CargoResult<git2::Repository> {
+    let path = config
+        .registry_index_path()
+        .join(registry_dir(srcid))
+        .into_path_unlocked();
+    Ok(git2::Repository::open(&path)
+        .chain_err(|| format!("failed to open registry index at {}", path.display()))?)
+}
+
+fn read_git_index(config: &Config, srcid: SourceId, rel: &str) -> CargoResult<Option<Vec<u8>>> {
+    let repo = open_git_index(config, srcid)?;
+    let head = repo.refname_to_id("refs/remotes/origin/master")?;
+    let tree = repo.find_commit(head)?.tree()?;
+
+    let entry = match tree.get_path(Path::new(rel)) {
+        Ok(entry) => entry,
+        Err(_) => return Ok(None),
+    };
+    let blob = entry.to_object(&repo)?.peel_to_blob()?;
+    Ok(Some(blob.content().to_vec()))
+}
@@ -0,0 +1,208 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Cleaning up when a run is interrupted with Ctrl-C, or terminated.
+//!
+//! Clones are written to temporary directories and files beside their
+//! destinations and moved into place once complete, so an interrupted run
+//! leaves no partial clone, only those. They are removed here before the
+//! process exits, and for a run that clones several crates, a hint on how
+//! to resume it is printed. The package cache lock is a file lock, which
+//! the system releases when the process exits. Partial downloads are kept,
+//! so that the next run resumes them.
+//!
+//! The signal handler only wakes a thread that does the work, as hardly
+//! anything is safe to do in a handler. That thread prints through a shell
+//! of its own, set up like cargo's, and with `--message-format json` ends
+//! with an `interrupted` event. Elsewhere than on Unix, nothing is cleaned
+//! up.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use cargo::core::shell::{ColorChoice, Shell, Verbosity};
+use cargo::util::Config;
+
+/// The paths to remove if the run is interrupted.
+static PARTIAL: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// How to resume the run, if it can be resumed.
+static HINT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Removes `path` if the run is interrupted before the returned guard is
+/// dropped.
+pub fn remove_on_interrupt(path: &Path) -> Guard {
+    let path = path.to_path_buf();
+    PARTIAL.lock().unwrap().push(path.clone());
+    Guard { path }
+}
+
+/// Keeps a path from `remove_on_interrupt` removed on interrupt for as long
+/// as it lives.
+pub struct Guard {
+    path: PathBuf,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut partial = PARTIAL.lock().unwrap();
+        if let Some(i) = partial.iter().position(|path| *path == self.path) {
+            partial.swap_remove(i);
+        }
+    }
+}
+
+/// A temporary directory that is removed when it is dropped, like
+/// `tempfile::TempDir`, and also when the run is interrupted.
+pub struct TempDir {
+    // The directory is removed before it is forgotten.
+    dir: tempfile::TempDir,
+    _guard: Guard,
+}
+
+impl TempDir {
+    pub fn new(dir: tempfile::TempDir) -> TempDir {
+        let guard = remove_on_interrupt(dir.path());
+        TempDir { dir, _guard: guard }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Keeps the directory, returning its path.
+    pub fn into_path(self) -> PathBuf {
+        self.dir.into_path()
+    }
+}
+
+/// Has `hint` printed if the run is interrupted, or nothing if it is
+/// `None`.
+pub fn set_hint(hint: Option<String>) {
+    *HINT.lock().unwrap() = hint;
+}
+
+/// How the shell of `config` prints: its verbosity and `--color` setting.
+/// A shell cannot be sent to another thread, but these can, to make one
+/// there with `new_shell`.
+fn shell_settings(config: &Config) -> (Verbosity, &'static str) {
+    let shell = config.shell();
+    let color = match shell.color_choice() {
+        ColorChoice::Always => "always",
+        ColorChoice::Never => "never",
+        ColorChoice::CargoAuto => "auto",
+    };
+    (shell.verbosity(), color)
+}
+
+/// Returns a shell that prints as `shell_settings` says.
+fn new_shell((verbosity, color): (Verbosity, &str)) -> Shell {
+    let mut shell = Shell::new();
+    shell.set_verbosity(verbosity);
+    let _ = shell.set_color_choice(Some(color));
+    shell
+}
+
+/// Removes what `remove_on_interrupt` was given, and prints the hint.
+fn clean_up(shell: &mut Shell) {
+    let partial = std::mem::take(&mut *PARTIAL.lock().unwrap());
+    for path in &partial {
+        let removed = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+            Ok(..) => fs::remove_file(path),
+            Err(..) => continue,
+        };
+        if let Err(e) = removed {
+            let _ = shell.warn(format!("failed to remove `{}`: {}", path.display(), e));
+        }
+    }
+    if let Some(hint) = HINT.lock().unwrap().as_ref() {
+        if shell.verbosity() != Verbosity::Quiet {
+            let _ = writeln!(shell.err(), "note: {}", hint);
+        }
+    }
+}
+
+/// Has an interrupted run clean up after itself before it exits, with 128
+/// plus the number of the signal, as shells do. Messages are printed as
+/// `config`'s shell would print them, so it should be configured first.
+#[cfg(unix)]
+pub fn install(config: &Config) {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+    use std::process;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::thread;
+
+    use serde_json::json;
+
+    use crate::message;
+
+    /// Where the handler writes the number of the signal.
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        let byte = signal as u8;
+        unsafe {
+            libc::write(
+                PIPE.load(Ordering::Relaxed),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            );
+        }
+    }
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return;
+    }
+    // Processes the run starts, such as git's, must not inherit the pipe.
+    for &fd in &fds {
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+    PIPE.store(fds[1], Ordering::Relaxed);
+    let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+    let settings = shell_settings(config);
+    thread::spawn(move || {
+        let mut signal = [0];
+        if pipe.read_exact(&mut signal).is_err() {
+            return;
+        }
+        let mut shell = new_shell(settings);
+        // Pressing Ctrl-C again stops the cleanup too.
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+        }
+        // The line may hold a progress bar.
+        if shell.is_err_tty() {
+            let _ = writeln!(shell.err());
+        }
+        let _ = shell.error("interrupted");
+        clean_up(&mut shell);
+        message::emit("interrupted", json!({ "signal": signal[0] }));
+        process::exit(128 + i32::from(signal[0]));
+    });
+
+    for &signal in &[libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install(_config: &Config) {}
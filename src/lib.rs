@@ -6,145 +6,6024 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+mod api;
+mod archive;
+mod auth;
+mod db;
+mod defaults;
+mod deps;
+mod exit;
+mod index;
+mod interrupt;
+mod license;
+mod lockfile;
+mod message;
+mod options;
+mod progress;
+mod sbom;
+mod security;
+mod sparse;
+mod walk;
+
+pub use crate::options::{clone_one, clone_one_with, CloneOptions};
+
 pub mod ops {
+    use std::collections::{BTreeSet, HashMap, HashSet};
     use std::env;
+    use std::ffi::OsStr;
     use std::fs;
-    use std::path::{Path, PathBuf};
+    use std::io::{self, Write};
+    use std::path::{Component, Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
 
     use cargo::core::dependency::Dependency;
-    use cargo::core::source::{Source, SourceId};
-    use cargo::core::Package;
-    use cargo::sources::{GitSource, PathSource, SourceConfigMap};
+    use cargo::core::package::PackageSet;
+    use cargo::core::shell::ColorChoice;
+    use cargo::core::source::{GitReference, Source, SourceId, SourceMap};
+    use cargo::core::{Package, PackageId, Summary, Workspace};
+    use cargo::sources::{
+        DirectorySource, GitSource, PathSource, RegistrySource, ReplacedSource, SourceConfigMap,
+    };
+    use cargo::util::config::ConfigValue;
+    use cargo::util::important_paths::find_root_manifest_for_wd;
+    use cargo::util::into_url::IntoUrl;
     use cargo::util::to_semver::ToSemver;
-    use cargo::util::{CargoResult, Config};
+    use cargo::util::{
+        paths, process, CargoResult, CargoResultExt, Config, ProcessError, ProgressStyle, Sha256,
+    };
 
     use failure::bail;
 
+    use filetime::FileTime;
+
+    use glob::Pattern;
+
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use semver::{Version, VersionReq};
+
+    use serde::{Deserialize, Serialize};
+
     use walkdir::WalkDir;
 
+    pub use crate::api::set_cache as set_api_cache;
+    pub use crate::api::{limit_rate, rate_limit};
+    pub use crate::defaults::{load as load_defaults, Defaults};
+    pub use crate::exit::code as exit_code;
+    pub use crate::interrupt::install as handle_interrupts;
+    pub use crate::license::report as license_report;
+    pub use crate::message::enable as enable_json_messages;
+    pub use crate::sbom::{write as write_sbom, Format as SbomFormat};
+    pub use crate::security::report as security_report;
+    pub use crate::sparse::CRATES_IO_INDEX as CRATES_IO_SPARSE_INDEX;
+
+    use crate::exit::{self, Failure};
+    use crate::index::IndexEntry;
+    use crate::interrupt::{self, TempDir};
+    use crate::message;
+    use crate::progress::{self, human_size, Progress};
+    use crate::sparse::{self, SparseSource};
+    use crate::walk::{self, Filter, Kind};
+    use crate::{api, archive, auth, db, deps, index, lockfile, security};
+
+    /// Options controlling which packages `clone` selects and where it puts them.
+    #[derive(Default)]
+    pub struct CloneOpts<'a> {
+        /// Directory to clone into. Defaults to `./<name>`. When cloning several
+        /// crates or versions, or with `dir_format`, this is the parent
+        /// directory instead.
+        pub prefix: Option<&'a str>,
+        /// Parent directory for clones, named according to `dir_format`.
+        pub out_dir: Option<&'a str>,
+        /// Template for the name of each clone's directory; see
+        /// `render_dir_format`.
+        pub dir_format: Option<&'a str>,
+        /// What to do when the destination already exists and is not empty.
+        pub on_exists: OnExists,
+        /// Version or semver requirement to select.
+        pub vers: Option<&'a str>,
+        /// Clone every matching version into `<name>-<version>/` under the prefix.
+        pub all_versions: bool,
+        /// Include yanked versions when selecting from a registry.
+        pub allow_yanked: bool,
+        /// Allow prerelease versions to be selected.
+        pub pre: bool,
+        /// Ask on the terminal which version to clone when several match.
+        pub interactive: bool,
+        /// Skip versions whose `rust-version` is newer than this. Only
+        /// registry indexes record it.
+        pub rust_version: Option<Version>,
+        /// Only consider versions published before this `YYYY-MM-DD` date.
+        pub published_before: Option<&'a str>,
+        /// Print the path of each finished clone to stdout.
+        pub print_path: bool,
+        /// Write an archive of this format instead of a directory.
+        pub archive: Option<ArchiveFormat>,
+        /// Write an uncompressed tar of the package to stdout instead of
+        /// touching the filesystem.
+        pub stdout: bool,
+        /// Keep a copy of the downloaded `.crate` file next to each clone.
+        pub keep_archive: bool,
+        /// Where to keep `.crate` files instead of next to each clone.
+        pub keep_archive_in: Option<&'a str>,
+        /// Only clone files matching one of these globs, if any are given.
+        pub include: &'a [String],
+        /// Skip files and directories matching any of these globs.
+        pub exclude: &'a [String],
+        /// Clone the whole git repository a `--git` package comes from, with
+        /// its history, instead of copying the package's files.
+        pub keep_git: bool,
+        /// Leave out the git submodules of packages from git sources, which
+        /// are otherwise cloned with them.
+        pub skip_submodules: bool,
+        /// Clone the repository a package's manifest links to, instead of
+        /// copying the published files.
+        pub from_repo: bool,
+        /// With `from_repo`, check out the commit recorded in the package's
+        /// `.cargo_vcs_info.json` rather than the default branch.
+        pub vcs_commit: bool,
+        /// With `from_repo`, check out the release's tag and commit the
+        /// published files on top, to show how they differ.
+        pub diff_upstream: bool,
+        /// Add the repository a package's manifest links to as a submodule of
+        /// the git repository around the current directory.
+        pub as_submodule: bool,
+        /// Make each cloned directory a git repository with the package as its
+        /// first commit.
+        pub git_init: bool,
+        /// Keep modification times and directory permissions of cloned files.
+        pub preserve: bool,
+        /// Copy what symlinks point to instead of recreating the links.
+        pub dereference: bool,
+        /// How many crates to clone at once when cloning several.
+        pub jobs: usize,
+        /// A file recording which crate specs have been cloned, so that an
+        /// interrupted run can be resumed.
+        pub state: Option<&'a str>,
+        /// The web API to look up publish dates with, instead of the
+        /// registry's own.
+        pub api: Option<&'a str>,
+        /// Add each clone to the `[patch]` table with this key, such as
+        /// `crates-io`, in the manifest of the workspace around the current
+        /// directory; see `patch_key`.
+        pub patch: Option<&'a str>,
+        /// Add each clone to the members of the workspace around the current
+        /// directory.
+        pub add_member: bool,
+        /// Lay clones out like `cargo vendor` does, as `<name>-<version>/`
+        /// under `vendor/` or the prefix, each with a `.cargo-checksum.json`.
+        pub vendor_layout: bool,
+        /// A shared store to clone into as `<store>/<name>/<version>/`,
+        /// instead of the prefix. A version already in the store is reused,
+        /// and the path of each is printed either way.
+        pub store: Option<&'a str>,
+        /// Start a new project from the package under this name: its
+        /// manifest is rewritten to name it so, at version 0.1.0, without
+        /// the original's publishing metadata, in a new git repository.
+        pub fork: Option<&'a str>,
+        /// Leave the clones out of the clone database, as for clones that
+        /// are only needed for a moment.
+        pub untracked: bool,
+        /// Print a provenance report of each clone to stdout, as a line of
+        /// JSON; see `provenance`.
+        pub provenance: bool,
+        /// Look for prebuilt binaries among the files of each clone, for
+        /// `security_report`.
+        pub find_binaries: bool,
+        /// Check that each clone is usable once it is made; see
+        /// `verify_clones`.
+        pub verify: bool,
+        /// Only print what each clone would download and where it would go,
+        /// without writing anything; see `plan_clone`.
+        pub dry_run: bool,
+    }
+
+    /// A package cloned during this run, for the reports made at the end of
+    /// it; see `take_cloned`.
+    #[derive(Clone, Debug)]
+    pub struct Cloned {
+        pub record: db::Record,
+        /// The SPDX license expression of the package's manifest.
+        pub license: Option<String>,
+        /// The `license-file` of the package's manifest.
+        pub license_file: Option<String>,
+        /// The package's build script, relative to its root.
+        pub build_script: Option<PathBuf>,
+        /// Whether the package is a procedural macro crate.
+        pub proc_macro: bool,
+        /// The prebuilt binaries among the package's files, relative to its
+        /// root, if `find_binaries` was set.
+        pub binaries: Vec<PathBuf>,
+        /// What makes the clone unusable, if `verify` was set.
+        pub problems: Option<Vec<String>>,
+    }
+
+    /// The packages cloned so far during this run, by every thread.
+    static CLONED: Mutex<Vec<Cloned>> = Mutex::new(Vec::new());
+
+    /// Returns the packages cloned since the last call, in the order they
+    /// were cloned.
+    pub fn take_cloned() -> Vec<Cloned> {
+        std::mem::take(&mut *CLONED.lock().unwrap())
+    }
+
+    /// What `clone` does when a destination directory is already in use.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum OnExists {
+        /// Fail with an error.
+        #[default]
+        Error,
+        /// Leave the existing directory alone and move on.
+        Skip,
+        /// Clone into `<dest>-1`, `<dest>-2`, ... instead.
+        Suffix,
+        /// Replace the existing directory.
+        Overwrite,
+    }
+
+    impl std::str::FromStr for OnExists {
+        type Err = failure::Error;
+
+        fn from_str(s: &str) -> CargoResult<OnExists> {
+            match s {
+                "error" => Ok(OnExists::Error),
+                "skip" => Ok(OnExists::Skip),
+                "suffix" => Ok(OnExists::Suffix),
+                "overwrite" => Ok(OnExists::Overwrite),
+                _ => bail!(
+                    "invalid value '{}', expected one of error, skip, suffix, overwrite",
+                    s
+                ),
+            }
+        }
+    }
+
+    /// Archive formats `clone` can write instead of a directory.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ArchiveFormat {
+        TarGz,
+        Zip,
+    }
+
+    impl ArchiveFormat {
+        /// The file extension, without the leading dot.
+        pub fn extension(self) -> &'static str {
+            match self {
+                ArchiveFormat::TarGz => "tar.gz",
+                ArchiveFormat::Zip => "zip",
+            }
+        }
+    }
+
+    impl std::str::FromStr for ArchiveFormat {
+        type Err = failure::Error;
+
+        fn from_str(s: &str) -> CargoResult<ArchiveFormat> {
+            match s {
+                "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+                "zip" => Ok(ArchiveFormat::Zip),
+                _ => bail!("invalid archive format '{}', expected tar.gz or zip", s),
+            }
+        }
+    }
+
+    /// Clones each of the given crate specs, or the package found at `srcid`
+    /// when `krates` is empty.
+    ///
+    /// When several crates are given, a failure to clone one of them does not
+    /// stop the others; errors are reported as they happen and summarized at
+    /// the end.
+    ///
+    /// Returns the paths of the clones made, in order, or with `dry_run` of
+    /// those that would be made.
     pub fn clone(
-        krate: Option<&str>,
+        krates: &[&str],
         srcid: &SourceId,
-        prefix: Option<&str>,
-        vers: Option<&str>,
+        opts: &CloneOpts<'_>,
         config: &Config,
-    ) -> CargoResult<()> {
+    ) -> CargoResult<Vec<PathBuf>> {
+        // A dry run clones nothing, so it has nothing to record.
+        let state = match opts.state.filter(|_| !opts.dry_run) {
+            Some(path) => Some(State::load(&config.cwd().join(path))?),
+            None => None,
+        };
+
+        match krates {
+            [] => {
+                let result = clone_one(None, srcid, opts, false, config);
+                emit_summary("", &result);
+                return result;
+            }
+            [krate] => {
+                if let Some(state) = &state {
+                    if state.is_done(krate) {
+                        config
+                            .shell()
+                            .status("Skipping", format!("{} (already cloned)", krate))?;
+                        emit_summary(krate, &Ok(Vec::new()));
+                        return Ok(Vec::new());
+                    }
+                }
+                let result = clone_one(Some(krate), srcid, opts, false, config);
+                emit_summary(krate, &result);
+                let paths = result?;
+                if let Some(state) = &state {
+                    state.record(krate)?;
+                }
+                return Ok(paths);
+            }
+            _ => {}
+        }
+
+        let mut outcomes = vec![None; krates.len()];
+        let mut pending = Vec::new();
+        // The same crate twice would only fail on its own destination.
+        let mut seen = HashSet::new();
+        for (i, &krate) in krates.iter().enumerate() {
+            if state.as_ref().is_some_and(|state| state.is_done(krate)) {
+                outcomes[i] = Some(Outcome::Skipped("already cloned".to_string()));
+                continue;
+            }
+            if let Ok((name, vers)) = parse_spec(krate) {
+                if !seen.insert((name.to_lowercase(), vers)) {
+                    outcomes[i] = Some(Outcome::Skipped("duplicate".to_string()));
+                    continue;
+                }
+            }
+            pending.push(i);
+        }
+
+        let pending_krates: Vec<&str> = pending.iter().map(|&i| krates[i]).collect();
+        let hint = if opts.state.is_some() || opts.on_exists == OnExists::Skip {
+            "run the same command again to clone the rest"
+        } else {
+            "run the same command again with --on-exists skip to clone the rest"
+        };
+        interrupt::set_hint(Some(hint.to_string()));
+        let results = if pending_krates.is_empty() {
+            Vec::new()
+        } else if opts.jobs > 1 {
+            clone_parallel(&pending_krates, srcid, opts, state.as_ref(), config)?
+        } else {
+            clone_batch(&pending_krates, srcid, opts, state.as_ref(), config)?
+        };
+        interrupt::set_hint(None);
+        for (i, outcome) in pending.into_iter().zip(results) {
+            outcomes[i] = Some(outcome);
+        }
+
+        let mut cloned = vec![];
+        let mut paths = Vec::new();
+        let mut skipped = 0;
+        let mut failed = 0;
+        let mut shell = config.shell();
+        for (&krate, outcome) in krates.iter().zip(outcomes) {
+            match outcome.expect("every crate has an outcome") {
+                Outcome::Cloned(mut cloned_to) => {
+                    cloned.push(krate);
+                    paths.append(&mut cloned_to);
+                }
+                Outcome::Skipped(why) => {
+                    skipped += 1;
+                    shell.status("Skipped", format!("{} ({})", krate, why))?;
+                }
+                Outcome::Failed(why) => {
+                    failed += 1;
+                    shell.status("Failed", format!("{} ({})", krate, why))?;
+                    message::emit(
+                        "failed",
+                        serde_json::json!({ "crate": krate, "error": why }),
+                    );
+                }
+            }
+        }
+        let verb = if opts.dry_run {
+            "would be cloned"
+        } else {
+            "cloned"
+        };
+        if !cloned.is_empty() && !opts.dry_run {
+            shell.status("Cloned", cloned.join(", "))?;
+        }
+        shell.status(
+            "Summary",
+            format!(
+                "{} {}, {} skipped, {} failed",
+                cloned.len(),
+                verb,
+                skipped,
+                failed
+            ),
+        )?;
+        message::emit(
+            "summary",
+            serde_json::json!({ "cloned": cloned.len(), "skipped": skipped, "failed": failed }),
+        );
+
+        if failed > 0 {
+            return Err(exit::error(
+                Failure::Partial,
+                format!("{} of {} crates failed to clone", failed, krates.len()),
+            ));
+        }
+        Ok(paths)
+    }
+
+    /// Emits the events that end the run of a single crate, `krate`.
+    fn emit_summary(krate: &str, result: &CargoResult<Vec<PathBuf>>) {
+        let (cloned, skipped, failed) = match result {
+            Ok(paths) if paths.is_empty() => (0, 1, 0),
+            Ok(_) => (1, 0, 0),
+            Err(e) => {
+                message::emit(
+                    "failed",
+                    serde_json::json!({ "crate": krate, "error": e.to_string() }),
+                );
+                (0, 0, 1)
+            }
+        };
+        message::emit(
+            "summary",
+            serde_json::json!({ "cloned": cloned, "skipped": skipped, "failed": failed }),
+        );
+    }
+
+    /// What became of one crate when cloning several.
+    #[derive(Clone)]
+    enum Outcome {
+        /// Cloned, to these paths.
+        Cloned(Vec<PathBuf>),
+        Skipped(String),
+        /// Failed, with the error that was reported.
+        Failed(String),
+    }
+
+    impl Outcome {
+        /// Reports an error straight away, and keeps it for the summary.
+        fn from_result(result: CargoResult<Vec<PathBuf>>, config: &Config) -> Outcome {
+            match result {
+                Ok(paths) if paths.is_empty() => Outcome::Skipped("destination exists".to_string()),
+                Ok(paths) => Outcome::Cloned(paths),
+                Err(e) => {
+                    cargo::handle_error(&e, &mut config.shell());
+                    Outcome::Failed(e.to_string())
+                }
+            }
+        }
+    }
+
+    /// The crate specs a run has cloned so far, kept in a file with one spec
+    /// per line.
+    struct State {
+        done: HashSet<String>,
+        file: Mutex<fs::File>,
+    }
+
+    impl State {
+        fn load(path: &Path) -> CargoResult<State> {
+            let done = match fs::read_to_string(path) {
+                Ok(contents) => contents.lines().map(str::to_string).collect(),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+                Err(e) => return Err(e.into()),
+            };
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            Ok(State {
+                done,
+                file: Mutex::new(file),
+            })
+        }
+
+        fn is_done(&self, krate: &str) -> bool {
+            self.done.contains(krate)
+        }
+
+        /// Records that `krate` was cloned, straight away so that it survives
+        /// the run being interrupted.
+        fn record(&self, krate: &str) -> CargoResult<()> {
+            let mut file = self.file.lock().unwrap();
+            writeln!(file, "{}", krate)?;
+            file.flush()?;
+            Ok(())
+        }
+    }
+
+    /// Clones `krates` on `opts.jobs` threads, returning what became of each.
+    /// Errors are reported as they happen.
+    ///
+    /// `Config` cannot be shared between threads, so each worker gets its own
+    /// with the same shell settings. The package cache lock is a file lock, so
+    /// the workers still take turns downloading and unpacking.
+    fn clone_parallel(
+        krates: &[&str],
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        state: Option<&State>,
+        config: &Config,
+    ) -> CargoResult<Vec<Outcome>> {
+        let color = match config.shell().color_choice() {
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+            ColorChoice::CargoAuto => "auto",
+        };
+        let verbosity = config.shell().verbosity();
+        let offline = config.offline();
+
+        // Update a registry's index once, rather than once per worker.
+        let updated = if srcid.is_path() || srcid.is_git() {
+            None
+        } else {
+            let _lock = config.acquire_package_cache_lock()?;
+            let mut src = load_source(config, *srcid, &Default::default())?;
+            src.update()?;
+            Some(src.replaced_source_id())
+        };
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; krates.len()]);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..opts.jobs.min(krates.len()))
+                .map(|_| {
+                    scope.spawn(|| -> CargoResult<()> {
+                        progress::lines_only();
+                        let mut config = Config::default()?;
+                        config.configure(0, None, &None, false, false, offline, &None, &[])?;
+                        config.shell().set_color_choice(Some(color))?;
+                        config.shell().set_verbosity(verbosity);
+                        config.updated_sources().extend(updated);
+
+                        loop {
+                            let i = next.fetch_add(1, Ordering::SeqCst);
+                            let krate = match krates.get(i) {
+                                Some(&krate) => krate,
+                                None => return Ok(()),
+                            };
+                            let result = clone_one(Some(krate), srcid, opts, true, &config);
+                            let outcome = Outcome::from_result(result, &config);
+                            if let (Outcome::Cloned(_), Some(state)) = (&outcome, state) {
+                                state.record(krate)?;
+                            }
+                            results.lock().unwrap()[i] = Some(outcome);
+                        }
+                    })
+                })
+                .collect();
+            let mut progress = Progress::new("Cloning", ProgressStyle::Ratio, config).without_bar();
+            while !workers.iter().all(|worker| worker.is_finished()) {
+                let done = results.lock().unwrap().iter().flatten().count();
+                progress.tick(done, krates.len(), " crates")?;
+                thread::sleep(Duration::from_millis(100));
+            }
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap())
+        })?;
+        Ok(results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|outcome| outcome.expect("every crate was cloned"))
+            .collect())
+    }
+
+    /// Clones several crates from one source, returning what became of each.
+    /// Errors are reported as they happen.
+    ///
+    /// The source is loaded and updated only once, and the selected packages
+    /// are all downloaded together.
+    fn clone_batch(
+        krates: &[&str],
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        state: Option<&State>,
+        config: &Config,
+    ) -> CargoResult<Vec<Outcome>> {
+        let mut results = vec![None; krates.len()];
         let _lock = config.acquire_package_cache_lock()?;
+        let mut progress = Progress::new("Cloning", ProgressStyle::Ratio, config);
+
+        // Offline, crates in the local cache need no source at all.
+        let mut pending = Vec::new();
+        for (i, &krate) in krates.iter().enumerate() {
+            progress.tick(i, krates.len(), &format!(": {}", krate))?;
+            let result = match clone_cached(krate, srcid, opts, true, config) {
+                Ok(None) => {
+                    pending.push(i);
+                    continue;
+                }
+                Ok(Some(cloned)) => Ok(cloned),
+                Err(e) => Err(e),
+            };
+            let outcome = Outcome::from_result(result, config);
+            if let (Outcome::Cloned(_), Some(state)) = (&outcome, state) {
+                state.record(krate)?;
+            }
+            results[i] = Some(outcome);
+        }
+        if pending.is_empty() {
+            return Ok(results.into_iter().map(Option::unwrap).collect());
+        }
+
+        let (mut src, registry_srcid) = open_source(srcid, config)?;
+        src.update()?;
+
+        let mut selected = Vec::new();
+        for i in pending {
+            let ids = parse_krate(krates[i], opts)
+                .and_then(|(name, vers)| select_ids(config, &mut src, name, vers, opts));
+            match ids {
+                Ok(ids) => selected.push((i, ids)),
+                Err(e) => results[i] = Some(Outcome::from_result(Err(e), config)),
+            }
+        }
+        if opts.dry_run {
+            for (i, ids) in selected {
+                let result = with_origins(&ids, registry_srcid, config)
+                    .and_then(|planned| plan_clone(&planned, opts, true, config));
+                results[i] = Some(Outcome::from_result(result, config));
+            }
+            return Ok(results.into_iter().map(Option::unwrap).collect());
+        }
+
+        let scratch = scratch_dir(opts, config)?;
+        let mut all: Vec<PackageId> = selected.iter().flat_map(|(_, ids)| ids).cloned().collect();
+        let selections = all.len();
+        all.sort();
+        all.dedup();
+        // A package two specs selected cannot be moved into both clones.
+        let scratch = Some(scratch.path()).filter(|_| all.len() == selections);
+        let pkgs = match download_pkgs(config, src, &all, scratch) {
+            Ok(pkgs) => pkgs,
+            Err(e) => {
+                let outcome = Outcome::from_result(Err(e), config);
+                for (i, _) in selected {
+                    results[i] = Some(outcome.clone());
+                }
+                return Ok(results.into_iter().map(Option::unwrap).collect());
+            }
+        };
+
+        let done = krates.len() - selected.len();
+        for (n, (i, ids)) in selected.into_iter().enumerate() {
+            progress.tick(done + n, krates.len(), &format!(": {}", krates[i]))?;
+            let pkgs: Vec<Package> = pkgs
+                .iter()
+                // Packages unpacked into the scratch directory have a path
+                // source, so only their names and versions match.
+                .filter(|pkg| {
+                    ids.iter()
+                        .any(|id| id.name() == pkg.name() && id.version() == pkg.version())
+                })
+                .cloned()
+                .collect();
+            let result = finish_clone(
+                &pkgs,
+                registry_srcid,
+                Some(*srcid),
+                scratch,
+                opts,
+                true,
+                config,
+            );
+            let outcome = Outcome::from_result(result, config);
+            if let (Outcome::Cloned(_), Some(state)) = (&outcome, state) {
+                state.record(krates[i])?;
+            }
+            results[i] = Some(outcome);
+        }
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Loads the source behind `srcid`, without updating it. Also returns
+    /// the registry whose cache holds its `.crate` files, if it is one.
+    fn open_source<'a>(
+        srcid: &SourceId,
+        config: &'a Config,
+    ) -> CargoResult<(Box<dyn Source + 'a>, Option<SourceId>)> {
+        if srcid.is_path() {
+            let path = srcid.url().to_file_path().expect("path must be valid");
+            Ok((
+                Box::new(PathSource::new_recursive(&path, *srcid, config)),
+                None,
+            ))
+        } else if srcid.is_git() {
+            Ok((Box::new(GitSource::new(*srcid, config)?), None))
+        } else {
+            let src = load_source(config, *srcid, &Default::default())?;
+            let id = src.replaced_source_id();
+            Ok((src, Some(id)))
+        }
+    }
+
+    /// Splits a crate spec into its name and version requirement, which may
+    /// also come from `--vers`.
+    fn parse_krate<'a>(
+        spec: &'a str,
+        opts: &CloneOpts<'a>,
+    ) -> CargoResult<(&'a str, Option<&'a str>)> {
+        let (name, spec_vers) = parse_spec(spec)?;
+        if spec_vers.is_some() && opts.vers.is_some() {
+            bail!("cannot specify both a version in '{}' and --vers", spec);
+        }
+        Ok((name, spec_vers.or(opts.vers)))
+    }
+
+    /// Clones a single crate. With `nested`, the prefix is treated as a parent
+    /// directory to clone `<name>/` into, as when cloning several crates.
+    /// Returns the paths of the clones, as `finish_clone` does.
+    fn clone_one(
+        krate: Option<&str>,
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        nested: bool,
+        config: &Config,
+    ) -> CargoResult<Vec<PathBuf>> {
+        if let Some(cloned) = krate
+            .map(|spec| clone_cached(spec, srcid, opts, nested, config))
+            .transpose()?
+            .flatten()
+        {
+            return Ok(cloned);
+        }
+
+        let lock = config.acquire_package_cache_lock()?;
+
+        let (krate, vers) = match krate {
+            Some(spec) => {
+                let (name, vers) = parse_krate(spec, opts)?;
+                (Some(name), vers)
+            }
+            None => (None, opts.vers),
+        };
+
+        if let (Some(name), true) = (krate, opts.dry_run) {
+            let (mut src, registry_srcid) = open_source(srcid, config)?;
+            src.update()?;
+            let ids = select_ids(config, &mut src, name, vers, opts)?;
+            let planned = with_origins(&ids, registry_srcid, config)?;
+            return plan_clone(&planned, opts, nested, config);
+        }
+
+        // The registry whose cache holds the downloaded `.crate` files.
+        let mut registry_srcid = None;
+        let scratch = scratch_dir(opts, config)?;
 
-        let map = SourceConfigMap::new(config)?;
-        let pkg = if srcid.is_path() {
-            let path = srcid.url().to_file_path().ok().expect("path must be valid");
-            let mut src = PathSource::new(&path, srcid.clone(), config);
+        let pkgs = if srcid.is_path() {
+            let path = srcid.url().to_file_path().expect("path must be valid");
+            // Walked recursively so that workspace members can be picked.
+            let mut src = PathSource::new_recursive(&path, *srcid, config);
             src.update()?;
 
-            select_pkg(config, src, krate, vers, &mut |path| path.read_packages())?
+            select_pkgs(config, src, krate, vers, None, opts, &mut |src| {
+                // A package at the path itself wins over the ones below it.
+                let pkgs = src.read_packages()?;
+                match pkgs.iter().find(|pkg| pkg.root() == path) {
+                    Some(pkg) => Ok(vec![pkg.clone()]),
+                    None => Ok(pkgs),
+                }
+            })?
         } else if srcid.is_git() {
-            select_pkg(
+            select_pkgs(
                 config,
-                GitSource::new(srcid.clone(), config)?,
+                GitSource::new(*srcid, config)?,
                 krate,
                 vers,
+                None,
+                opts,
                 &mut |git| git.read_packages(),
             )?
         } else {
-            select_pkg(
-                config,
-                map.load(srcid.clone(), &Default::default())?,
-                krate,
-                vers,
-                &mut |_| {
-                    bail!(
-                        "must specify a crate to clone from \
+            let src = load_source(config, *srcid, &Default::default())?;
+            registry_srcid = Some(src.replaced_source_id());
+            let scratch = Some(scratch.path());
+            select_pkgs(config, src, krate, vers, scratch, opts, &mut |_| {
+                bail!(
+                    "must specify a crate to clone from \
                          crates.io, or use --path or --git to \
                          specify alternate source"
-                    )
-                },
-            )?
+                )
+            })?
         };
 
-        // If prefix was not supplied, clone into current dir
-        let dest_path = match prefix {
-            Some(path) => PathBuf::from(path),
-            None => {
-            	let mut dest = env::current_dir()?;
-            	dest.push(format!("{}", pkg.name()));
-            	dest
-            },
-        };
+        if krate.is_none() {
+            for pkg in &pkgs {
+                emit_resolved(pkg.package_id());
+            }
+        }
+        // Without a crate name, the package is local or in a git checkout.
+        if opts.dry_run {
+            let ids: Vec<PackageId> = pkgs.iter().map(Package::package_id).collect();
+            let planned = with_origins(&ids, None, config)?;
+            return plan_clone(&planned, opts, nested, config);
+        }
 
-        // Cloning into an existing directory is only allowed if the directory is empty.
-        if !dest_path.exists() {
-        	fs::create_dir_all(&dest_path)?;
+        // The rest works on copies of the unpacked packages, so other clones
+        // may use the cache in the meantime. `.crate` files still need the
+        // lock, as they live in the cache itself.
+        let _lock = if opts.keep_archive {
+            Some(lock)
         } else {
-        	let is_empty = dest_path.read_dir()?.next().is_none();
-        	if !is_empty {
-        		bail!("destination path '{}' already exists and is not an empty directory.", dest_path.display());
-        	}
-        }
+            drop(lock);
+            None
+        };
 
-        clone_directory(&pkg.root(), &dest_path)?;
+        finish_clone(
+            &pkgs,
+            registry_srcid,
+            Some(*srcid),
+            Some(scratch.path()),
+            opts,
+            nested,
+            config,
+        )
+    }
 
+    /// Clones the package in the `.crate` file at `file`, without touching
+    /// the network or any registry. The file must hold a single
+    /// `<name>-<version>/` directory with that package in it.
+    pub fn clone_crate_file(file: &Path, opts: &CloneOpts<'_>, config: &Config) -> CargoResult<()> {
+        let tmp = scratch_dir(opts, config)?;
+        let pkg = unpack_crate_file(file, tmp.path(), config)?;
+        message::emit(
+            "resolved",
+            serde_json::json!({
+                "name": pkg.name().as_str(),
+                "version": pkg.version().to_string(),
+                "source": null,
+            }),
+        );
+        if opts.dry_run {
+            let id = PackageId::new(pkg.name(), pkg.version(), SourceId::for_path(file)?)?;
+            let origin = format!("from `{}`", file.display());
+            plan_clone(&[(id, origin)], opts, false, config)?;
+            return Ok(());
+        }
+        finish_clone(&[pkg], None, None, Some(tmp.path()), opts, false, config)?;
         Ok(())
     }
 
-    fn select_pkg<'a, T>(
+    /// Downloads the `.crate` file at `url` and clones the package in it.
+    ///
+    /// If the URL is one of the registry behind `srcid`'s downloads, the file
+    /// is checked against the checksum in its index before it is unpacked.
+    pub fn clone_crate_url(
+        url: &str,
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
         config: &Config,
-        mut src: T,
-        name: Option<&str>,
-        vers: Option<&str>,
-        list_all: &mut dyn FnMut(&mut T) -> CargoResult<Vec<Package>>,
-    ) -> CargoResult<Package>
-    where
-        T: Source + 'a,
-    {
+    ) -> CargoResult<()> {
+        let tmp = scratch_dir(opts, config)?;
+        config.shell().status("Downloading", url)?;
+        message::emit("downloading", serde_json::json!({ "url": url }));
+        let (code, body, _) = api::get(config, url)?;
+        if code != 200 {
+            bail!("failed to download `{}` (HTTP status {})", url, code);
+        }
+
+        let verified = match known_checksum(url, srcid, config)? {
+            Some(cksum) => {
+                if cksum != Sha256::new().update(&body).finish_hex() {
+                    bail!("failed to verify the checksum of `{}`", url);
+                }
+                true
+            }
+            None => {
+                config.shell().warn(format!(
+                    "{} is not a download of {}, so its checksum is not verified",
+                    url, srcid
+                ))?;
+                false
+            }
+        };
+        let file = tmp.path().join("download.crate");
+        fs::write(&file, &body)?;
+        let pkg = unpack_crate_file(&file, tmp.path(), config)?;
+        message::emit(
+            "resolved",
+            serde_json::json!({
+                "name": pkg.name().as_str(),
+                "version": pkg.version().to_string(),
+                "source": if verified { Some(srcid.into_url().to_string()) } else { None },
+            }),
+        );
+        finish_clone(&[pkg], None, None, Some(tmp.path()), opts, false, config)?;
+        Ok(())
+    }
+
+    /// Unpacks the `.crate` file at `file` into `into` and reads the package
+    /// in it, which must be in a `<name>-<version>/` directory.
+    fn unpack_crate_file(file: &Path, into: &Path, config: &Config) -> CargoResult<Package> {
+        let top = archive::unpack_crate(file, into)?;
+        let root = into.join(&top);
+        if !root.join("Cargo.toml").is_file() {
+            bail!(
+                "invalid .crate file `{}`: there is no `{}/Cargo.toml`",
+                file.display(),
+                top
+            );
+        }
+        let pkg = PathSource::new(&root, SourceId::for_path(&root)?, config).root_package()?;
+        if top != format!("{}-{}", pkg.name(), pkg.version()) {
+            bail!(
+                "invalid .crate file `{}`: {} v{} is in `{}/`",
+                file.display(),
+                pkg.name(),
+                pkg.version(),
+                top
+            );
+        }
+        Ok(pkg)
+    }
+
+    /// Returns the checksum the index of the registry behind `srcid` records
+    /// for the `.crate` file at `url`, if that is where the registry serves
+    /// one of its `.crate` files.
+    fn known_checksum(url: &str, srcid: &SourceId, config: &Config) -> CargoResult<Option<String>> {
+        if !srcid.is_registry() {
+            return Ok(None);
+        }
+        let (name, vers) = match url_package(url) {
+            Some(package) => package,
+            None => return Ok(None),
+        };
+        let _lock = config.acquire_package_cache_lock()?;
+        let mut src = load_source(config, *srcid, &HashSet::new())?;
         src.update()?;
+        let registry = src.replaced_source_id();
+        let dl = match index::read_config(config, registry)? {
+            Some(registry) => registry.dl,
+            None => return Ok(None),
+        };
+        // Everything before the first placeholder is the same for every crate.
+        if !url.starts_with(dl.split('{').next().unwrap()) {
+            return Ok(None);
+        }
+        Ok(index::read_entries(config, registry, &name)?
+            .into_iter()
+            .find(|entry| entry.vers == vers)
+            .map(|entry| entry.cksum))
+    }
 
-        match name {
-            Some(name) => {
-                let vers = match vers {
-                    Some(v) => match v.to_semver() {
-                        Ok(v) => Some(v.to_string()),
-                        Err(e) => bail!("{}", e),
-                    },
-                    None => None,
-                };
-                let vers = vers.as_ref().map(|s| &**s);
-                let dep = Dependency::parse_no_deprecated(name, vers, src.source_id())?;
-                let mut summaries = vec![];
-                src.query(&dep, &mut |summary| summaries.push(summary.clone()))?;
+    /// Returns the name and version of the package that `url` downloads,
+    /// going by a `<name>-<version>.crate` file name or a path that has the
+    /// name and then the version in it, like `<name>/<version>/download`.
+    fn url_package(url: &str) -> Option<(String, String)> {
+        let path = url.split(['?', '#']).next().unwrap();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if let Some(stem) = segments.last().and_then(|s| s.strip_suffix(".crate")) {
+            // Names may have dashes in them, but versions start with a digit.
+            let found = stem.match_indices('-').find_map(|(i, _)| {
+                let (name, vers) = (&stem[..i], &stem[i + 1..]);
+                Version::parse(vers).ok().map(|_| (name, vers))
+            });
+            if let Some((name, vers)) = found {
+                return Some((name.to_string(), vers.to_string()));
+            }
+        }
+        segments
+            .windows(2)
+            .rev()
+            .find(|pair| Version::parse(pair[1]).is_ok())
+            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+    }
+
+    /// When cargo is offline, clones `spec` from cargo's local cache if the
+    /// registry behind `srcid` has a matching version there, without
+    /// reading its index. Returns `None` if it does not.
+    fn clone_cached(
+        spec: &str,
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        nested: bool,
+        config: &Config,
+    ) -> CargoResult<Option<Vec<PathBuf>>> {
+        if !config.offline() || !srcid.is_remote_registry() {
+            return Ok(None);
+        }
+        let (name, vers) = parse_krate(spec, opts)?;
+        let req = match vers {
+            Some(v) => Some(parse_version_req(v)?),
+            None => None,
+        };
+
+        let _lock = config.acquire_package_cache_lock()?;
+        let mut found = cached_versions(config, *srcid, name)?;
+        found.retain(|(version, _)| match &req {
+            Some(req) if opts.pre => matches_pre(req, version),
+            Some(req) => req.matches(version),
+            None => opts.pre || !version.is_prerelease(),
+        });
+        if found.is_empty() {
+            return Ok(None);
+        }
+        found.sort_by(|a, b| b.0.cmp(&a.0));
+        if !opts.all_versions {
+            found.truncate(1);
+        }
+        for (version, _) in &found {
+            emit_resolved(PackageId::new(name, version, *srcid)?);
+        }
+        if opts.dry_run {
+            let mut planned = Vec::new();
+            for (version, path) in &found {
+                planned.push((
+                    PackageId::new(name, version, *srcid)?,
+                    format!("from the local cache, `{}`", path.display()),
+                ));
+            }
+            return plan_clone(&planned, opts, nested, config).map(Some);
+        }
 
-                let latest = summaries.iter().max_by_key(|s| s.version());
+        let tmp = scratch_dir(opts, config)?;
+        let mut pkgs = Vec::new();
+        for (version, path) in found {
+            config.shell().status(
+                "Using",
+                format!("{} v{} from the local cache", name, version),
+            )?;
+            pkgs.push(if path.is_file() {
+                unpack_crate_file(&path, tmp.path(), config)?
+            } else {
+                PathSource::new(&path, SourceId::for_path(&path)?, config).root_package()?
+            });
+        }
+        finish_clone(
+            &pkgs,
+            None,
+            Some(*srcid),
+            Some(tmp.path()),
+            opts,
+            nested,
+            config,
+        )
+        .map(Some)
+    }
 
-                match latest {
-                    Some(l) => {
-                        let pkg = Box::new(src).download_now(l.package_id(), config)?;
-                        Ok(pkg)
+    /// Lists the versions of `name` in cargo's local cache for the registry
+    /// behind `srcid`, each with its `.crate` file or, if that is gone, its
+    /// unpacked sources.
+    fn cached_versions(
+        config: &Config,
+        srcid: SourceId,
+        name: &str,
+    ) -> CargoResult<Vec<(Version, PathBuf)>> {
+        let mut found: Vec<(Version, PathBuf)> = Vec::new();
+        for &kind in &["cache", "src"] {
+            for dir in cache_dirs(config, srcid, kind)? {
+                for entry in fs::read_dir(dir)? {
+                    let entry = entry?;
+                    let file_name = entry.file_name().to_string_lossy().into_owned();
+                    let stem = match (kind, file_name.strip_suffix(".crate")) {
+                        ("cache", Some(stem)) => stem,
+                        ("src", None) => &file_name[..],
+                        _ => continue,
+                    };
+                    let version = stem
+                        .strip_prefix(name)
+                        .and_then(|rest| rest.strip_prefix('-'))
+                        .and_then(|vers| vers.to_semver().ok());
+                    if let Some(version) = version {
+                        if !found.iter().any(|(v, _)| *v == version) {
+                            found.push((version, entry.path()));
+                        }
                     }
-                    None => bail!("package '{}' not found", name),
                 }
             }
-            None => {
-                let candidates = list_all(&mut src)?;
-                Ok(candidates[0].clone())
+        }
+        Ok(found)
+    }
+
+    /// Returns the directories under `$CARGO_HOME/registry/<kind>` that hold
+    /// the `.crate` files (`cache`) or unpacked crates (`src`) of the
+    /// registry behind `srcid`.
+    fn cache_dirs(config: &Config, srcid: SourceId, kind: &str) -> CargoResult<Vec<PathBuf>> {
+        let root = config
+            .home()
+            .join("registry")
+            .join(kind)
+            .into_path_unlocked();
+        let dirs = match fs::read_dir(&root) {
+            Ok(dirs) => dirs,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut found = Vec::new();
+        for dir in dirs {
+            let dir = dir?;
+            if is_cache_of(&dir.file_name().to_string_lossy(), srcid) {
+                found.push(dir.path());
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns whether the cache directory `dir` belongs to the registry
+    /// behind `srcid`. Cargo names these `<host>-<hash>`, but the hash
+    /// differs between versions of cargo, so only the host is compared.
+    fn is_cache_of(dir: &str, srcid: SourceId) -> bool {
+        // crates.io's git index is on github.com, like other registries'.
+        const CRATES_IO_GIT: &str = "github.com-1ecc6299db9ec823";
+        if sparse::is_crates_io(srcid) {
+            return dir == CRATES_IO_GIT || dir.starts_with("index.crates.io-");
+        }
+        dir != CRATES_IO_GIT && dir.rsplit_once('-').map(|(host, _)| host) == srcid.url().host_str()
+    }
+
+    /// Pairs each of `ids` with where its files would come from, for
+    /// `plan_clone`: the `.crate` file in the cache of the registry behind
+    /// `registry_srcid` if it is there already, or else the URL it would be
+    /// downloaded from. Packages from other sources come from the source.
+    fn with_origins(
+        ids: &[PackageId],
+        registry_srcid: Option<SourceId>,
+        config: &Config,
+    ) -> CargoResult<Vec<(PackageId, String)>> {
+        let mut planned = Vec::new();
+        for &id in ids {
+            let registry = match registry_srcid {
+                Some(registry) => registry,
+                None => {
+                    planned.push((id, format!("from {}", id.source_id())));
+                    continue;
+                }
+            };
+            let (name, vers) = (id.name(), id.version().to_string());
+            let origin = match index::crate_file(config, registry, &name, &vers) {
+                Some(file) => format!("from the local cache, `{}`", file.display()),
+                None => match index::read_config(config, registry)? {
+                    Some(config) => {
+                        format!("download from {}", sparse::download_url(&config.dl, id))
+                    }
+                    None => format!("download from {}", registry),
+                },
+            };
+            planned.push((id, origin));
+        }
+        Ok(planned)
+    }
+
+    /// Prints what cloning each of `planned` from where it comes from would
+    /// do, without downloading or writing anything. Returns the paths of the
+    /// clones that would be made, leaving out those skipped because their
+    /// destination exists.
+    fn plan_clone(
+        planned: &[(PackageId, String)],
+        opts: &CloneOpts<'_>,
+        nested: bool,
+        config: &Config,
+    ) -> CargoResult<Vec<PathBuf>> {
+        let mut cloned = Vec::new();
+        for (id, origin) in planned {
+            let dest = dest_path(*id, opts, nested)?;
+            if opts.store.is_some() && is_occupied(&dest)? {
+                config.shell().status(
+                    "Fresh",
+                    format!("{} v{} (in the store)", id.name(), id.version()),
+                )?;
+                continue;
             }
+            let dest = match resolve_dest(dest, opts, config)? {
+                Some(dest) => dest,
+                None => continue,
+            };
+            config.shell().status(
+                "Would clone",
+                format!(
+                    "{} v{} into {} ({})",
+                    id.name(),
+                    id.version(),
+                    dest.display(),
+                    origin
+                ),
+            )?;
+            if let Some(key) = opts.patch {
+                patch_workspace(key, id.name().as_str(), &dest, true, config)?;
+            }
+            cloned.push(config.cwd().join(dest));
         }
+        Ok(cloned)
     }
 
-    fn clone_directory(from: &Path, to: &Path) -> CargoResult<()> {
-        for entry in WalkDir::new(from) {
-            let entry = entry.unwrap();
-            let file_type = entry.file_type();
-            let mut to = to.to_owned();
-            to.push(entry.path().strip_prefix(from).unwrap());
+    /// Copies the selected packages to their destinations, or writes them out
+    /// in whatever form `opts` asks for. Returns the paths of the clones,
+    /// leaving out packages skipped because their destination exists; there
+    /// are none for a package written to stdout.
+    ///
+    /// Packages unpacked into `scratch` just for this clone may be moved to
+    /// their destination instead of copied. Each clone is recorded in the
+    /// clone database as coming from `srcid`.
+    fn finish_clone(
+        pkgs: &[Package],
+        registry_srcid: Option<SourceId>,
+        srcid: Option<SourceId>,
+        scratch: Option<&Path>,
+        opts: &CloneOpts<'_>,
+        nested: bool,
+        config: &Config,
+    ) -> CargoResult<Vec<PathBuf>> {
+        if opts.stdout {
+            if pkgs.len() > 1 {
+                bail!("only one package can be written to stdout");
+            }
+            let pkg = &pkgs[0];
+            let filter = package_filter(pkg, registry_srcid, opts)?;
+            let prefix = format!("{}-{}", pkg.name(), pkg.version());
+            let stdout = io::stdout();
+            archive::write_tar(
+                pkg.root(),
+                &prefix,
+                &filter,
+                opts.dereference,
+                stdout.lock(),
+            )?
+            .flush()?;
+            return Ok(Vec::new());
+        }
+
+        let mut cloned = Vec::new();
+        for pkg in pkgs {
+            let filter = package_filter(pkg, registry_srcid, opts)?;
+            let dest_path = dest_path(pkg.package_id(), opts, nested)?;
 
-            if file_type.is_file() && entry.file_name() != ".cargo-ok" {
-                // .cargo-ok is not wanted in this context
-                fs::copy(&entry.path(), &to)?;
-            } else if file_type.is_dir() {
-                fs::create_dir(&to)?;
+            // What is in the store stays there, for whoever else uses it.
+            if opts.store.is_some() && is_occupied(&dest_path)? {
+                config.shell().status(
+                    "Fresh",
+                    format!("{} v{} (in the store)", pkg.name(), pkg.version()),
+                )?;
+                println!("{}", dest_path.display());
+                continue;
             }
+            let dest_path = match resolve_dest(dest_path, opts, config)? {
+                Some(dest) => dest,
+                None => continue,
+            };
+            config.shell().verbose(|shell| {
+                shell.status(
+                    "Cloning",
+                    format!(
+                        "{} v{} from {} into `{}`",
+                        pkg.name(),
+                        pkg.version(),
+                        match srcid {
+                            Some(srcid) => srcid.to_string(),
+                            None => format!("`{}`", pkg.root().display()),
+                        },
+                        dest_path.display()
+                    ),
+                )
+            })?;
+            // The package may be moved into place, so it is read first.
+            let mut report = match srcid {
+                Some(srcid) if opts.provenance => Some(provenance(pkg, srcid, opts, config)?),
+                _ => None,
+            };
+            let binaries = if opts.find_binaries {
+                security::find_binaries(pkg.root(), &filter)?
+            } else {
+                Vec::new()
+            };
+            // Only plain copies of the files are checked against them.
+            let files = if opts.verify
+                && !opts.keep_git
+                && !opts.as_submodule
+                && !opts.from_repo
+                && opts.fork.is_none()
+            {
+                Some(file_list(pkg.root(), &filter, opts.dereference, &[])?)
+            } else {
+                None
+            };
+            let pkg_path = match opts.archive {
+                Some(format) => {
+                    archive_atomically(pkg, format, &filter, opts, &dest_path, config)?;
+                    dest_path.clone()
+                }
+                None if opts.keep_git => git_clone_atomically(pkg, &dest_path, opts, config)?,
+                None if opts.as_submodule => submodule_add(pkg, &dest_path, config)?,
+                None if opts.from_repo => repo_clone_atomically(pkg, &dest_path, opts, config)?,
+                None => {
+                    let movable = scratch.is_some_and(|scratch| pkg.root().starts_with(scratch));
+                    clone_atomically(pkg, &dest_path, &filter, movable, opts, config)?;
+                    if opts.vendor_layout {
+                        let cksum = package_checksum(pkg, registry_srcid, config)?;
+                        write_checksums(&dest_path, cksum.as_deref())?;
+                    }
+                    dest_path.clone()
+                }
+            };
+            message::emit(
+                "extracted",
+                serde_json::json!({
+                    "name": pkg.name().as_str(),
+                    "version": pkg.version().to_string(),
+                    "path": config.cwd().join(&pkg_path),
+                }),
+            );
+            if opts.keep_archive {
+                keep_archive(pkg, registry_srcid, &dest_path, opts, config)?;
+            }
+            if opts.add_member {
+                add_member(pkg, &pkg_path, config)?;
+            }
+            if let Some(key) = opts.patch {
+                patch_workspace(key, pkg.name().as_str(), &pkg_path, false, config)?;
+            }
+            // The rest is checked once the package cache is unlocked, as
+            // `cargo metadata` needs it; see `verify_clones`.
+            let problems = match opts.archive {
+                None if opts.verify => Some(match &files {
+                    Some(files) => missing_files(&pkg_path, files, opts)?,
+                    None => Vec::new(),
+                }),
+                _ => None,
+            };
+            let record = db::Record {
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                source: srcid.map(|id| id.into_url().to_string()),
+                path: config.cwd().join(&pkg_path),
+                checksum: package_checksum(pkg, registry_srcid, config).ok().flatten(),
+                cloned_at: db::now(),
+            };
+            CLONED.lock().unwrap().push(Cloned {
+                record: record.clone(),
+                license: pkg.manifest().metadata().license.clone(),
+                license_file: pkg.manifest().metadata().license_file.clone(),
+                build_script: pkg
+                    .targets()
+                    .iter()
+                    .find(|target| target.is_custom_build())
+                    .and_then(|target| target.src_path().path())
+                    .map(|path| path.strip_prefix(pkg.root()).unwrap_or(path).to_owned()),
+                proc_macro: pkg.targets().iter().any(|target| target.proc_macro()),
+                binaries,
+                problems,
+            });
+            // A fork is a project of its own, which updating or cleaning
+            // clones must not touch.
+            if opts.fork.is_none() && !opts.untracked {
+                // The clone is done either way.
+                if let Err(e) = db::add(config, record) {
+                    config.shell().warn(format!(
+                        "failed to record the clone of {}: {}",
+                        pkg.name(),
+                        e
+                    ))?;
+                }
+            }
+            if let Some(report) = &mut report {
+                report["path"] = config.cwd().join(&pkg_path).display().to_string().into();
+                println!("{}", report);
+            }
+            if opts.print_path {
+                // Status messages go to stderr, so this is all stdout gets.
+                println!("{}", pkg_path.display());
+            }
+            cloned.push(config.cwd().join(pkg_path));
         }
 
-        Ok(())
+        Ok(cloned)
+    }
+
+    /// Returns the paths of the files and symlinks under `dir` that pass
+    /// `filter` and none of the `exclude` globs, relative to `dir`.
+    fn file_list(
+        dir: &Path,
+        filter: &Filter,
+        follow_links: bool,
+        exclude: &[String],
+    ) -> CargoResult<BTreeSet<PathBuf>> {
+        let exclude = Filter::new(&[], exclude)?;
+        let mut files = BTreeSet::new();
+        for entry in walk::entries(dir, filter, follow_links)? {
+            if entry.kind != Kind::Dir && walk::passes(&exclude, &entry.rel) {
+                files.insert(entry.rel);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Returns what is wrong with the files of the clone at `dir`, which
+    /// should be exactly `files`. Files that `opts` asked to add are left
+    /// out.
+    fn missing_files(
+        dir: &Path,
+        files: &BTreeSet<PathBuf>,
+        opts: &CloneOpts<'_>,
+    ) -> CargoResult<Vec<String>> {
+        let mut exclude = Vec::new();
+        if opts.git_init {
+            exclude.push(".git/**".to_string());
+        }
+        if opts.vendor_layout {
+            exclude.push(".cargo-checksum.json".to_string());
+        }
+        let cloned = file_list(dir, &Filter::new(&[], &[])?, false, &exclude)?;
+        let mut problems = Vec::new();
+        let missing: Vec<_> = files.difference(&cloned).collect();
+        if let Some(first) = missing.first() {
+            problems.push(format!(
+                "{} of the package's files are missing, such as `{}`",
+                missing.len(),
+                first.display()
+            ));
+        }
+        let extra: Vec<_> = cloned.difference(files).collect();
+        if let Some(first) = extra.first() {
+            problems.push(format!(
+                "{} files are not in the package, such as `{}`",
+                extra.len(),
+                first.display()
+            ));
+        }
+        Ok(problems)
+    }
+
+    /// Finishes checking that each of `cloned` that was cloned with `verify`
+    /// is usable: its manifest must parse and `cargo metadata` must resolve
+    /// it offline. What is wrong is added to its `problems` and reported.
+    ///
+    /// `cargo metadata` takes the package cache lock, so this must not be
+    /// called while it is held. A `Cargo.lock` it writes is removed again.
+    pub fn verify_clones(cloned: &mut [Cloned], config: &Config) -> CargoResult<()> {
+        for cloned in cloned {
+            let problems = match &mut cloned.problems {
+                Some(problems) => problems,
+                None => continue,
+            };
+            let dir = &cloned.record.path;
+            let manifest = dir.join("Cargo.toml");
+            match fs::read_to_string(&manifest) {
+                Ok(contents) => {
+                    if let Err(e) = parse_manifest(&manifest, &contents) {
+                        problems.push(format!("its manifest does not parse ({})", e));
+                    }
+                }
+                Err(e) => problems.push(format!("its manifest cannot be read ({})", e)),
+            }
+            if problems.is_empty() {
+                let lockfile = dir.join("Cargo.lock");
+                let had_lockfile = lockfile.exists();
+                let output = process("cargo")
+                    .args(&["metadata", "--offline", "--format-version", "1"])
+                    .arg("--manifest-path")
+                    .arg(&manifest)
+                    .exec_with_output();
+                if !had_lockfile && lockfile.exists() {
+                    fs::remove_file(&lockfile)?;
+                }
+                if let Err(e) = output {
+                    let stderr = e
+                        .downcast_ref::<ProcessError>()
+                        .and_then(|e| e.output.as_ref())
+                        .map(|output| String::from_utf8_lossy(&output.stderr).into_owned())
+                        .unwrap_or_else(|| e.to_string());
+                    problems.push(metadata_problem(&stderr));
+                }
+            }
+
+            let what = format!("{} v{}", cloned.record.name, cloned.record.version);
+            if problems.is_empty() {
+                config
+                    .shell()
+                    .status("Checked", format!("{} (usable)", what))?;
+            } else {
+                config.shell().warn(format!(
+                    "{} may not be usable: {}",
+                    what,
+                    problems.join("; ")
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sums up why `cargo metadata` failed, from what it printed.
+    fn metadata_problem(stderr: &str) -> String {
+        let reason = if stderr.contains("believes it's in a workspace") {
+            "it is inside another workspace"
+        } else if stderr.contains("failed to read") && stderr.contains("Cargo.toml") {
+            "a path dependency cannot be found"
+        } else if stderr.contains("failed to select a version") {
+            "a dependency has no version that fits, or only yanked ones"
+        } else if stderr.contains("--offline")
+            || stderr.contains("no matching package")
+            || stderr.contains("failed to download")
+        {
+            "its dependencies are not in the local cache"
+        } else if stderr.contains("is required") && stderr.contains("feature `") {
+            "it needs a newer version of cargo"
+        } else if stderr.contains("failed to parse manifest") {
+            "cargo cannot load its manifest"
+        } else {
+            let first = stderr.lines().find(|line| !line.trim().is_empty());
+            let first = first.unwrap_or("unknown error").trim();
+            return format!(
+                "cargo metadata failed: {}",
+                first.trim_start_matches("error: ")
+            );
+        };
+        format!("cargo metadata failed: {}", reason)
+    }
+
+    /// Returns the include and exclude filter for `pkg`'s files. With
+    /// `skip_submodules`, the git submodules inside the package are excluded
+    /// too.
+    fn package_filter(
+        pkg: &Package,
+        registry_srcid: Option<SourceId>,
+        opts: &CloneOpts<'_>,
+    ) -> CargoResult<Filter> {
+        let mut exclude = opts.exclude.to_vec();
+        // `cargo vendor` adds its checksums to each crate; they are not part
+        // of the package.
+        if registry_srcid.is_some_and(is_directory) {
+            exclude.push(".cargo-checksum.json".to_string());
+        }
+        // `cargo vendor` leaves these out too.
+        if opts.vendor_layout {
+            exclude.extend(
+                [".gitattributes", ".gitignore", ".git"]
+                    .iter()
+                    .map(|name| name.to_string()),
+            );
+        }
+        if opts.skip_submodules && pkg.package_id().source_id().is_git() {
+            let repo = git2::Repository::discover(pkg.root())?;
+            if let Some(workdir) = repo.workdir() {
+                let workdir = workdir.canonicalize()?;
+                let root = pkg.root().canonicalize()?;
+                for submodule in repo.submodules()? {
+                    if let Ok(rel) = workdir.join(submodule.path()).strip_prefix(&root) {
+                        let rel = Pattern::escape(&rel.to_string_lossy());
+                        exclude.push(format!("{}/**", rel));
+                    }
+                }
+            }
+        }
+        Filter::new(opts.include, &exclude)
+    }
+
+    /// Returns the checksum of the `.crate` file `pkg` comes from, which a
+    /// directory source checks against the lock file. Only registries, and
+    /// `cargo vendor` directories that copied it from one, know it.
+    fn package_checksum(
+        pkg: &Package,
+        registry_srcid: Option<SourceId>,
+        config: &Config,
+    ) -> CargoResult<Option<String>> {
+        let srcid = match registry_srcid {
+            Some(srcid) => srcid,
+            None => return Ok(None),
+        };
+        if is_directory(srcid) {
+            let path = pkg.root().join(".cargo-checksum.json");
+            let checksums: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)
+                .chain_err(|| format!("failed to parse `{}`", path.display()))?;
+            return Ok(checksums["package"].as_str().map(str::to_string));
+        }
+        let vers = pkg.version().to_string();
+        Ok(index::read_entries(config, srcid, &pkg.name())?
+            .into_iter()
+            .find(|entry| entry.vers == vers)
+            .map(|entry| entry.cksum))
+    }
+
+    /// Writes the `.cargo-checksum.json` of a directory source into `dir`,
+    /// with the SHA-256 of every file under it.
+    fn write_checksums(dir: &Path, package: Option<&str>) -> CargoResult<()> {
+        let mut files = serde_json::Map::new();
+        for entry in walk::entries(dir, &Filter::new(&[], &[])?, false)? {
+            if entry.kind != Kind::File {
+                continue;
+            }
+            let rel = entry.rel.to_string_lossy().replace('\\', "/");
+            let hash = Sha256::new().update(&fs::read(&entry.path)?).finish_hex();
+            files.insert(rel, serde_json::Value::String(hash));
+        }
+        let checksums = serde_json::json!({
+            "files": files,
+            "package": package,
+        });
+        fs::write(dir.join(".cargo-checksum.json"), checksums.to_string())?;
+        Ok(())
+    }
+
+    /// Copies the `.crate` file `pkg` was unpacked from next to `dest`, or
+    /// into `--keep-archive-in`.
+    fn keep_archive(
+        pkg: &Package,
+        registry_srcid: Option<SourceId>,
+        dest: &Path,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let name = pkg.name();
+        let vers = pkg.version().to_string();
+        let file = match registry_srcid.and_then(|id| index::crate_file(config, id, &name, &vers)) {
+            Some(file) => file,
+            None => {
+                config.shell().warn(format!(
+                    "no .crate file to keep for {} v{}, as it was not downloaded from a registry",
+                    name, vers
+                ))?;
+                return Ok(());
+            }
+        };
+
+        let dir = match opts.keep_archive_in {
+            Some(dir) => config.cwd().join(dir),
+            None => match dest.parent() {
+                Some(parent) => parent.to_owned(),
+                None => config.cwd().to_owned(),
+            },
+        };
+        fs::create_dir_all(&dir)?;
+        let to = dir.join(file.file_name().unwrap());
+        fs::copy(&file, &to)?;
+        config
+            .shell()
+            .verbose(|shell| shell.status("Keeping", to.display()))?;
+        Ok(())
+    }
+
+    /// A set of crates to look up with the registry's web API, to clone them
+    /// all at once. Crates must match every filter that is given.
+    #[derive(Default)]
+    pub struct CrateQuery<'a> {
+        /// A user, or a team given as `github:<org>:<team>`, owning the crates.
+        pub owner: Option<&'a str>,
+        pub keyword: Option<&'a str>,
+        pub category: Option<&'a str>,
+        /// Only the given number of the most downloaded crates.
+        pub top_downloads: Option<usize>,
+        /// Only the given number of the most recently updated crates.
+        pub top_updated: Option<usize>,
+        /// Only crates downloaded at least this many times.
+        pub min_downloads: Option<u64>,
+        /// The web API to use instead of the registry's own.
+        pub api: Option<&'a str>,
+    }
+
+    /// Returns the names of the crates matching `query` in the registry
+    /// behind `srcid`.
+    pub fn find_crates(
+        query: &CrateQuery<'_>,
+        srcid: &SourceId,
+        config: &Config,
+    ) -> CargoResult<Vec<String>> {
+        if srcid.is_path() || srcid.is_git() {
+            bail!("crates can only be looked up in a registry");
+        }
+        let api = {
+            let _lock = config.acquire_package_cache_lock()?;
+            let mut src = load_source(config, *srcid, &Default::default())?;
+            if query.api.is_none() && (src.is_replaced() || !sparse::is_crates_io(*srcid)) {
+                // The API is named in the index's config.json.
+                src.update()?;
+            }
+            registry_api(config, &src, query.api)?
+        };
+        let api = match api {
+            Some(api) => api,
+            None => bail!("the registry has no web API to look up crates with"),
+        };
+
+        let mut filters = Vec::new();
+        let mut what = Vec::new();
+        if let Some(owner) = query.owner {
+            filters.push(api::owner_filter(config, &api, owner)?);
+            what.push(format!("owned by {}", owner));
+        }
+        if let Some(keyword) = query.keyword {
+            filters.push(api::filter("keyword", keyword));
+            what.push(format!("with keyword {}", keyword));
+        }
+        if let Some(category) = query.category {
+            filters.push(api::filter("category", category));
+            what.push(format!("in category {}", category));
+        }
+        if query.top_downloads.is_some() && query.top_updated.is_some() {
+            bail!("crates can only be ranked by downloads or by updates, not both");
+        }
+        if query.top_downloads.is_some() {
+            filters.push(api::filter("sort", "downloads"));
+            what.insert(0, "among the most downloaded".to_string());
+        }
+        if query.top_updated.is_some() {
+            filters.push(api::filter("sort", "recent-updates"));
+            what.insert(0, "among the most recently updated".to_string());
+        }
+        if let Some(min) = query.min_downloads {
+            what.push(format!("with at least {} downloads", min));
+        }
+        let what = what.join(" ");
+
+        // The most downloaded crates come first, so the few with enough
+        // downloads are among the first pages; otherwise they may be anywhere.
+        let limit = match query.min_downloads {
+            Some(_) => query.top_downloads,
+            None => query.top_downloads.or(query.top_updated),
+        };
+        let mut crates = api::crates(config, &api, &filters, limit)?;
+        if let Some(min) = query.min_downloads {
+            crates.retain(|c| c.downloads >= min);
+        }
+        if let Some(n) = query.top_updated {
+            crates.truncate(n);
+        }
+        if crates.is_empty() {
+            bail!("no crates {} were found", what);
+        }
+        let noun = if crates.len() == 1 { "crate" } else { "crates" };
+        config
+            .shell()
+            .status("Found", format!("{} {} {}", crates.len(), noun, what))?;
+        Ok(crates.into_iter().map(|c| c.name).collect())
+    }
+
+    /// Picks `n` crates at random from the index of the registry behind
+    /// `srcid`. The same seed picks the same crates from the same index.
+    pub fn sample_crates(
+        n: usize,
+        seed: Option<u64>,
+        srcid: &SourceId,
+        config: &Config,
+    ) -> CargoResult<Vec<String>> {
+        if srcid.is_path() || srcid.is_git() {
+            bail!("crates can only be sampled from a registry");
+        }
+        let _lock = config.acquire_package_cache_lock()?;
+        let mut src = load_source(config, *srcid, &Default::default())?;
+        src.update()?;
+        let index_srcid = src.replaced_source_id();
+
+        let mut paths = index::crate_paths(config, index_srcid)?;
+        if paths.is_empty() {
+            bail!("the registry index lists no crates to sample");
+        }
+        // Walking order is not guaranteed, so sort before shuffling.
+        paths.sort();
+
+        // Print the seed, so that a random sample can be repeated.
+        let seed = seed.unwrap_or_else(rand::random);
+        config.shell().status(
+            "Sampling",
+            format!(
+                "{} of {} crates (seed {})",
+                n.min(paths.len()),
+                paths.len(),
+                seed
+            ),
+        )?;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (sample, _) = paths.partial_shuffle(&mut rng, n);
+
+        let mut names = Vec::new();
+        for path in sample.iter() {
+            if let Some(name) = index::crate_name(config, index_srcid, path)? {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Returns `name@version` specs for the packages pinned in the lockfile at
+    /// `path` that come from the registry behind `srcid`.
+    pub fn lockfile_crates(
+        path: &Path,
+        srcid: &SourceId,
+        config: &Config,
+    ) -> CargoResult<Vec<String>> {
+        let (pkgs, other): (Vec<_>, Vec<_>) = lockfile::read(path)?
+            .into_iter()
+            .partition(|pkg| pkg.is_from(*srcid));
+        if !other.is_empty() {
+            config.shell().warn(format!(
+                "skipping {} packages of `{}` that are not from {}",
+                other.len(),
+                path.display(),
+                srcid
+            ))?;
+        }
+        Ok(pkgs
+            .into_iter()
+            .map(|pkg| format!("{}@{}", pkg.name, pkg.version))
+            .collect())
+    }
+
+    /// Returns the source of crates.io, read over `protocol` or the one set in
+    /// cargo's config. Like recent versions of cargo, this is the sparse index,
+    /// unless crates.io is replaced by a mirror.
+    pub fn crates_io(protocol: Option<&str>, config: &Config) -> CargoResult<SourceId> {
+        let protocol = match protocol {
+            Some(protocol) => protocol.to_string(),
+            None => match config.get_string("registries.crates-io.protocol")? {
+                Some(protocol) => protocol.val,
+                None if config
+                    .get_string("source.crates-io.replace-with")?
+                    .is_some() =>
+                {
+                    "git".to_string()
+                }
+                None => "sparse".to_string(),
+            },
+        };
+        match &protocol[..] {
+            "git" => SourceId::crates_io(config),
+            "sparse" => SourceId::for_registry(&sparse::CRATES_IO_INDEX.into_url()?),
+            other => bail!(
+                "invalid `registries.crates-io.protocol` '{}', expected git or sparse",
+                other
+            ),
+        }
+    }
+
+    /// Returns the key of the `[patch]` table that overrides packages from
+    /// `srcid`: `crates-io` for crates.io, and otherwise the URL of the
+    /// registry index or git repository.
+    pub fn patch_key(srcid: &SourceId) -> CargoResult<String> {
+        if sparse::is_crates_io(*srcid) {
+            Ok("crates-io".to_string())
+        } else if srcid.is_remote_registry() || srcid.is_git() {
+            Ok(srcid.url().to_string())
+        } else {
+            bail!("only packages from a remote registry or git can be patched")
+        }
+    }
+
+    /// Points `[patch.<key>]` in the manifest of the workspace around the
+    /// current directory at the clone of the crate `name` in `dir`. With
+    /// `dry_run`, the entry and the manifest it would go in are printed, and
+    /// `dir` need not exist yet.
+    ///
+    /// Nothing changes if the entry is already there. An entry for the crate
+    /// that points elsewhere is left alone, with a warning.
+    fn patch_workspace(
+        key: &str,
+        name: &str,
+        dir: &Path,
+        dry_run: bool,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let root = workspace_root(config)?;
+        let manifest = root.join("Cargo.toml");
+        let contents = fs::read_to_string(&manifest)?;
+        let parsed = parse_manifest(&manifest, &contents)?;
+
+        let dir = if dry_run {
+            paths::normalize_path(&config.cwd().canonicalize()?.join(dir))
+        } else {
+            dir.canonicalize()?
+        };
+        let path = relative_path(&dir, &root);
+        let path = path.to_string_lossy().replace('\\', "/");
+        let table = parsed.get("patch").and_then(|patch| patch.get(key));
+        if let Some(existing) = table.and_then(|table| table.get(name)) {
+            if existing.get("path").and_then(toml::Value::as_str) != Some(path.as_str()) {
+                config.shell().warn(format!(
+                    "`{}` already patches {} with something else; leaving it",
+                    manifest.display(),
+                    name
+                ))?;
+            }
+            return Ok(());
+        }
+
+        let header = if key.contains(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_') {
+            format!("[patch.{}]", toml::Value::String(key.to_string()))
+        } else {
+            format!("[patch.{}]", key)
+        };
+        let entry = format!(
+            "{} = {{ path = {} }}",
+            name,
+            toml::Value::String(path.clone())
+        );
+        if dry_run {
+            return config.shell().status(
+                "Would patch",
+                format!("`{}` under `{}` in `{}`", entry, header, manifest.display()),
+            );
+        }
+        let mut lines: Vec<&str> = contents.lines().collect();
+        match lines.iter().position(|line| line.trim() == header) {
+            Some(i) => lines.insert(i + 1, &entry),
+            // The table is written some other way, such as inline.
+            None if table.is_some() => bail!(
+                "cannot find `{}` in `{}`; add `{}` to it by hand",
+                header,
+                manifest.display(),
+                entry
+            ),
+            None => {
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push("");
+                }
+                lines.push(&header);
+                lines.push(&entry);
+            }
+        }
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        fs::write(&manifest, contents)?;
+        config
+            .shell()
+            .status("Patching", format!("{} in `{}`", name, manifest.display()))?;
+        Ok(())
+    }
+
+    /// Adds the clone of `pkg` in `dir` to the members of the workspace around
+    /// the current directory.
+    ///
+    /// Nothing changes if a member path or glob already covers it. A clone
+    /// that cannot join the workspace, or a manifest whose members cannot be
+    /// found to edit, is reported rather than guessed at.
+    fn add_member(pkg: &Package, dir: &Path, config: &Config) -> CargoResult<()> {
+        let root = workspace_root(config)?;
+        let manifest = root.join("Cargo.toml");
+        let dir = dir.canonicalize()?;
+        let rel = match dir.strip_prefix(&root) {
+            Ok(rel) => rel.to_string_lossy().replace('\\', "/"),
+            Err(_) => bail!(
+                "`{}` is outside the workspace at `{}`, so it cannot be a member; \
+                 clone it somewhere under the workspace",
+                dir.display(),
+                root.display()
+            ),
+        };
+        check_joinable(pkg, &dir)?;
+
+        let contents = fs::read_to_string(&manifest)?;
+        let parsed = parse_manifest(&manifest, &contents)?;
+        let workspace = match parsed.get("workspace") {
+            Some(workspace) => workspace,
+            None => bail!(
+                "`{}` has no [workspace] table, so there are no members to add to",
+                manifest.display()
+            ),
+        };
+        let paths = |key: &str| -> Vec<String> {
+            workspace
+                .get(key)
+                .and_then(toml::Value::as_array)
+                .map_or(Vec::new(), |paths| {
+                    paths
+                        .iter()
+                        .filter_map(toml::Value::as_str)
+                        .map(|path| path.trim_end_matches('/').to_string())
+                        .collect()
+                })
+        };
+        if paths("exclude")
+            .iter()
+            .any(|excluded| Path::new(&rel).starts_with(excluded))
+        {
+            bail!(
+                "`{}` is excluded from the workspace by `{}`",
+                rel,
+                manifest.display()
+            );
+        }
+        if paths("members")
+            .iter()
+            .any(|member| Pattern::new(member).is_ok_and(|pattern| pattern.matches(&rel)))
+        {
+            config.shell().verbose(|shell| {
+                shell.status("Fresh", format!("{} is already a workspace member", rel))
+            })?;
+            return Ok(());
+        }
+
+        let edited = match insert_member(&contents, &rel) {
+            Some(edited) => edited,
+            None => bail!(
+                "cannot edit the workspace members in `{}` safely; \
+                 add \"{}\" to them by hand",
+                manifest.display(),
+                rel
+            ),
+        };
+        // Only write what reads back as the same manifest plus the member.
+        let check = parse_manifest(&manifest, &edited)?;
+        let members = check
+            .get("workspace")
+            .and_then(|workspace| workspace.get("members"))
+            .and_then(toml::Value::as_array);
+        if members.is_none_or(|members| !members.contains(&toml::Value::String(rel.clone()))) {
+            bail!(
+                "cannot edit the workspace members in `{}` safely; \
+                 add \"{}\" to them by hand",
+                manifest.display(),
+                rel
+            );
+        }
+        fs::write(&manifest, edited)?;
+        config.shell().status(
+            "Adding",
+            format!("{} to the members in `{}`", rel, manifest.display()),
+        )?;
+        Ok(())
+    }
+
+    /// Fails if the package cloned to `dir` cannot be a member of another
+    /// workspace: if it is a workspace root itself, or inherits settings
+    /// with `workspace = true` from the workspace it came from.
+    fn check_joinable(pkg: &Package, dir: &Path) -> CargoResult<()> {
+        let manifest = dir.join("Cargo.toml");
+        let parsed = parse_manifest(&manifest, &fs::read_to_string(&manifest)?)?;
+        if parsed.get("workspace").is_some() {
+            bail!(
+                "{} is a workspace root itself, so it cannot be a member of another",
+                pkg.name()
+            );
+        }
+        fn inherits(value: &toml::Value) -> bool {
+            match value {
+                toml::Value::Table(table) => {
+                    table.get("workspace") == Some(&toml::Value::Boolean(true))
+                        || table.values().any(inherits)
+                }
+                _ => false,
+            }
+        }
+        if inherits(&parsed) {
+            bail!(
+                "{} inherits settings from the workspace it was published from \
+                 (`workspace = true`), so it cannot be a member of this one",
+                pkg.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns `contents` with `member` added to `[workspace] members`, or
+    /// `None` if the array is not written in a way that can be edited line
+    /// by line.
+    fn insert_member(contents: &str, member: &str) -> Option<String> {
+        let quoted = toml::Value::String(member.to_string()).to_string();
+        let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        let header = lines.iter().position(|line| line.trim() == "[workspace]")?;
+        let end = lines[header + 1..]
+            .iter()
+            .position(|line| line.trim_start().starts_with('['))
+            .map_or(lines.len(), |i| header + 1 + i);
+        let is_members = |line: &String| {
+            let line = line.trim_start();
+            line.starts_with("members") && line["members".len()..].trim_start().starts_with('=')
+        };
+        let start = match (header + 1..end).find(|&i| is_members(&lines[i])) {
+            Some(start) => start,
+            None => {
+                lines.insert(header + 1, format!("members = [{}]", quoted));
+                return Some(lines.join("\n") + "\n");
+            }
+        };
+
+        let line = &lines[start];
+        let open = line.find('[')?;
+        if let Some(close) = line.rfind(']').filter(|&close| close > open) {
+            // All on one line.
+            if line[close..].contains('#') || line[..close].contains('#') {
+                return None;
+            }
+            let inner = line[open + 1..close].trim_end();
+            let insert = if inner.trim().is_empty() {
+                quoted
+            } else if inner.ends_with(',') {
+                format!(" {}", quoted)
+            } else {
+                format!(", {}", quoted)
+            };
+            let mut line = line.clone();
+            line.insert_str(open + 1 + inner.len(), &insert);
+            lines[start] = line;
+        } else {
+            let close = (start + 1..end).find(|&i| lines[i].trim_start().starts_with(']'))?;
+            let last = (start + 1..close)
+                .rev()
+                .find(|&i| !lines[i].trim().is_empty() && !lines[i].trim().starts_with('#'));
+            let indent = match last {
+                Some(last) => {
+                    // A comment may follow the last member.
+                    let (code, comment) =
+                        lines[last].split_at(lines[last].find('#').unwrap_or(lines[last].len()));
+                    let code = code.trim_end();
+                    if !code.ends_with(',') {
+                        let spacing = if comment.is_empty() { "" } else { " " };
+                        lines[last] = format!("{},{}{}", code, spacing, comment);
+                    }
+                    let line = &lines[last];
+                    line[..line.len() - line.trim_start().len()].to_string()
+                }
+                None => "    ".to_string(),
+            };
+            lines.insert(close, format!("{}{},", indent, quoted));
+        }
+        Some(lines.join("\n") + "\n")
+    }
+
+    /// The first line of a workspace manifest written by `write_workspace`,
+    /// which marks it as safe to replace.
+    const WORKSPACE_MARKER: &str = "# Generated by cargo-clone over the packages cloned here.";
+
+    /// Writes a `Cargo.toml` into `dir` that makes every package cloned under
+    /// it a member of one workspace, so that a single `cargo check` covers
+    /// them all. A manifest in `dir` is only replaced if it was written here
+    /// before.
+    ///
+    /// Of several packages with the same name, only the newest is a member.
+    /// With `exclude_broken`, packages whose manifest the `cargo` on the
+    /// `PATH` cannot load are excluded too.
+    pub fn write_workspace(dir: &Path, exclude_broken: bool, config: &Config) -> CargoResult<()> {
+        let manifest = dir.join("Cargo.toml");
+        if let Ok(contents) = fs::read_to_string(&manifest) {
+            if contents.lines().next() != Some(WORKSPACE_MARKER) {
+                bail!(
+                    "`{}` was not written by cargo-clone, so it is not replaced",
+                    manifest.display()
+                );
+            }
+            // Packages it does not list yet would not load under it.
+            fs::remove_file(&manifest)?;
+        }
+
+        fs::create_dir_all(dir)?;
+        let pkgs = cloned_packages(dir)?;
+
+        let mut members = Vec::new();
+        let mut exclude = Vec::new();
+        for (rel, name, version) in &pkgs {
+            let newer = pkgs.iter().find(|(other, other_name, other_version)| {
+                other != rel && other_name == name && (other_version, other) > (version, rel)
+            });
+            if name.is_empty() {
+                config.shell().warn(format!(
+                    "`{}` has no package name and version; leaving it out",
+                    rel
+                ))?;
+                exclude.push(rel);
+            } else if let Some((other, _, _)) = newer {
+                config.shell().warn(format!(
+                    "leaving `{}` out of the workspace, in favor of `{}`",
+                    rel, other
+                ))?;
+                exclude.push(rel);
+            } else if exclude_broken && !manifest_loads(&dir.join(rel).join("Cargo.toml"))? {
+                config.shell().warn(format!(
+                    "leaving `{}` out of the workspace, as cargo cannot load its manifest",
+                    rel
+                ))?;
+                exclude.push(rel);
+            } else {
+                members.push(rel);
+            }
+        }
+
+        let list = |paths: &[&String]| -> String {
+            paths
+                .iter()
+                .map(|path| format!("    {},\n", toml::Value::String(path.to_string())))
+                .collect()
+        };
+        let mut contents = format!(
+            "{}\n[workspace]\nresolver = \"2\"\nmembers = [\n{}]\n",
+            WORKSPACE_MARKER,
+            list(&members)
+        );
+        if !exclude.is_empty() {
+            contents.push_str(&format!("exclude = [\n{}]\n", list(&exclude)));
+        }
+        fs::write(&manifest, contents)?;
+        config.shell().status(
+            "Writing",
+            format!(
+                "a workspace of {} packages to `{}`",
+                members.len(),
+                manifest.display()
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// The names a dependency table can have in a manifest.
+    const DEPENDENCY_TABLES: &[&str] = &[
+        "dependencies",
+        "dev-dependencies",
+        "dev_dependencies",
+        "build-dependencies",
+        "build_dependencies",
+    ];
+
+    /// Points the registry dependencies of each package under `dir` at the
+    /// other packages there, with a `path` next to their version, so that
+    /// changes to one are picked up by the others.
+    ///
+    /// A dependency is only linked to a package whose version meets its
+    /// requirement. Dependencies written in a way that cannot be edited line
+    /// by line are left as they are, with a warning.
+    pub fn link_local(dir: &Path, config: &Config) -> CargoResult<()> {
+        let pkgs = cloned_packages(dir)?;
+        for (rel, name, _) in pkgs.iter().filter(|(_, name, _)| !name.is_empty()) {
+            let manifest = dir.join(rel).join("Cargo.toml");
+            let contents = fs::read_to_string(&manifest)?;
+            let parsed = parse_manifest(&manifest, &contents)?;
+
+            // Returns the path to link the dependency `key` in the table at
+            // `table` to, if any.
+            let link_for = |table: &[String], key: &str| -> Option<String> {
+                let mut dep = &parsed;
+                for part in table.iter().map(String::as_str).chain(Some(key)) {
+                    dep = dep.get(part)?;
+                }
+                let (package, req) = match dep {
+                    toml::Value::String(req) => (key, req.as_str()),
+                    toml::Value::Table(dep) => {
+                        if dep.contains_key("path") || dep.contains_key("git") {
+                            return None;
+                        }
+                        let package = dep.get("package").and_then(toml::Value::as_str);
+                        let req = dep.get("version").and_then(toml::Value::as_str);
+                        (package.unwrap_or(key), req.unwrap_or("*"))
+                    }
+                    _ => return None,
+                };
+                let req = VersionReq::parse(req).ok()?;
+                let (target, _, _) = pkgs
+                    .iter()
+                    .filter(|(other, other_name, version)| {
+                        other != rel && other_name == package && req.matches(version)
+                    })
+                    .max_by(|a, b| a.2.cmp(&b.2))?;
+                let path = relative_path(&dir.join(target), &dir.join(rel));
+                Some(path.to_string_lossy().replace('\\', "/"))
+            };
+
+            let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+            let mut linked = Vec::new();
+            let mut section: Vec<String> = Vec::new();
+            let mut i = 0;
+            while i < lines.len() {
+                let line = lines[i].trim().to_string();
+                if line.starts_with('[') {
+                    section = line
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .split('.')
+                        .map(|part| {
+                            part.trim()
+                                .trim_matches(|c| c == '"' || c == '\'')
+                                .to_string()
+                        })
+                        .collect();
+                    // A table of its own, such as `[dependencies.serde]`.
+                    let n = section.len();
+                    if n >= 2 && DEPENDENCY_TABLES.contains(&section[n - 2].as_str()) {
+                        if let Some(path) = link_for(&section[..n - 1], &section[n - 1]) {
+                            let entry = format!("path = {}", toml::Value::String(path));
+                            lines.insert(i + 1, entry);
+                            linked.push(section[n - 1].clone());
+                            i += 1;
+                        }
+                    }
+                } else if section
+                    .last()
+                    .is_some_and(|table| DEPENDENCY_TABLES.contains(&table.as_str()))
+                {
+                    if let Some((key, value)) = line.split_once('=') {
+                        let key = key.trim().trim_matches(|c| c == '"' || c == '\'');
+                        if let Some(path) = link_for(&section, key) {
+                            let path = toml::Value::String(path);
+                            let value = value.trim();
+                            let edited = if value.starts_with('"') && value.ends_with('"') {
+                                Some(format!("{{ version = {}, path = {} }}", value, path))
+                            } else if value.starts_with('{') && value.ends_with('}') {
+                                Some(format!("{{ path = {}, {}", path, value[1..].trim_start()))
+                            } else {
+                                None
+                            };
+                            match edited {
+                                Some(edited) => {
+                                    let start = lines[i].find('=').unwrap();
+                                    lines[i] = format!("{}= {}", &lines[i][..start], edited);
+                                    linked.push(key.to_string());
+                                }
+                                None => config.shell().warn(format!(
+                                    "cannot link {}'s dependency on {} in `{}`; \
+                                     add `path = {}` by hand",
+                                    name,
+                                    key,
+                                    manifest.display(),
+                                    path
+                                ))?,
+                            }
+                        }
+                    }
+                }
+                i += 1;
+            }
+            if linked.is_empty() {
+                continue;
+            }
+
+            let mut edited = lines.join("\n");
+            edited.push('\n');
+            if let Err(e) = parse_manifest(&manifest, &edited) {
+                config.shell().warn(format!(
+                    "leaving `{}` unchanged, as linking its dependencies broke it: {}",
+                    manifest.display(),
+                    e
+                ))?;
+                continue;
+            }
+            fs::write(&manifest, edited)?;
+            linked.sort();
+            linked.dedup();
+            config
+                .shell()
+                .status("Linking", format!("{} to {}", name, linked.join(", ")))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the directory of each package under `dir`, relative to it,
+    /// with the package's name and version. Both are empty if its manifest
+    /// cannot be read.
+    fn cloned_packages(dir: &Path) -> CargoResult<Vec<(String, String, Version)>> {
+        let mut dirs = Vec::new();
+        find_packages(dir, &mut dirs)?;
+        let mut pkgs: Vec<(String, String, Version)> = Vec::new();
+        for pkg_dir in dirs {
+            let rel = pkg_dir
+                .strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            let pkg_manifest = pkg_dir.join("Cargo.toml");
+            let parsed = fs::read_to_string(&pkg_manifest)
+                .map_err(failure::Error::from)
+                .and_then(|contents| parse_manifest(&pkg_manifest, &contents));
+            let package = parsed.ok().and_then(|parsed| {
+                let package = parsed.get("package")?;
+                let name = package.get("name")?.as_str()?.to_string();
+                let version = package.get("version")?.as_str()?.parse().ok()?;
+                Some((name, version))
+            });
+            match package {
+                Some((name, version)) => pkgs.push((rel, name, version)),
+                None => pkgs.push((rel, String::new(), Version::new(0, 0, 0))),
+            }
+        }
+        Ok(pkgs)
+    }
+
+    /// Collects the directories under `dir` that hold a `Cargo.toml`, in
+    /// order, without looking inside them or into hidden and `target`
+    /// directories.
+    fn find_packages(dir: &Path, found: &mut Vec<PathBuf>) -> CargoResult<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !entry.file_type()?.is_dir() || name.starts_with('.') || name == "target" {
+                continue;
+            }
+            if entry.path().join("Cargo.toml").is_file() {
+                found.push(entry.path());
+            } else {
+                find_packages(&entry.path(), found)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the `cargo` on the `PATH` can load the manifest at
+    /// `path` on its own.
+    fn manifest_loads(path: &Path) -> CargoResult<bool> {
+        let output = process("cargo")
+            .args(&[
+                "metadata",
+                "--no-deps",
+                "--offline",
+                "--format-version",
+                "1",
+            ])
+            .arg("--manifest-path")
+            .arg(path)
+            .exec_with_output();
+        Ok(output.is_ok())
+    }
+
+    /// Returns the root directory of the workspace around the current
+    /// directory.
+    fn workspace_root(config: &Config) -> CargoResult<PathBuf> {
+        let ws = Workspace::new(&find_root_manifest_for_wd(config.cwd())?, config)?;
+        Ok(ws.root().canonicalize()?)
+    }
+
+    fn parse_manifest(path: &Path, contents: &str) -> CargoResult<toml::Value> {
+        let value = toml::from_str(contents)
+            .chain_err(|| format!("failed to parse `{}`", path.display()))?;
+        Ok(value)
+    }
+
+    /// Returns `path` relative to the directory `base`. Both must be absolute.
+    fn relative_path(path: &Path, base: &Path) -> PathBuf {
+        let path: Vec<Component<'_>> = path.components().collect();
+        let base: Vec<Component<'_>> = base.components().collect();
+        let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+        let mut rel = PathBuf::new();
+        for _ in common..base.len() {
+            rel.push("..");
+        }
+        for component in &path[common..] {
+            rel.push(component);
+        }
+        if rel.as_os_str().is_empty() {
+            rel.push(".");
+        }
+        rel
+    }
+
+    /// Returns a spec for the version of `spec`'s crate that the workspace
+    /// around the current directory has locked.
+    ///
+    /// A version requirement in `spec` picks among several locked versions.
+    pub fn locked_dep(spec: &str, srcid: &SourceId, config: &Config) -> CargoResult<String> {
+        let (name, vers) = parse_spec(spec)?;
+        let req = match vers {
+            Some(vers) => Some(parse_version_req(vers)?),
+            None => None,
+        };
+
+        let manifest = find_root_manifest_for_wd(config.cwd())?;
+        let ws = Workspace::new(&manifest, config)?;
+        let path = ws.root().join("Cargo.lock");
+        if !path.exists() {
+            bail!(
+                "`{}` does not exist; run `cargo generate-lockfile` first",
+                path.display()
+            );
+        }
+
+        let locked: Vec<_> = lockfile::read(&path)?
+            .into_iter()
+            .filter(|pkg| pkg.name == name)
+            .collect();
+        if locked.is_empty() {
+            bail!("package '{}' is not in `{}`", name, path.display());
+        }
+        let mut versions: Vec<Version> = locked
+            .iter()
+            .filter(|pkg| pkg.is_from(*srcid))
+            .filter_map(|pkg| pkg.version.to_semver().ok())
+            .filter(|v| req.as_ref().is_none_or(|req| req.matches(v)))
+            .collect();
+        versions.sort();
+        match &versions[..] {
+            [] => bail!(
+                "no locked version of package '{}' matching '{}' is from {}",
+                name,
+                vers.unwrap_or("*"),
+                srcid
+            ),
+            [version] => Ok(format!("{}@{}", name, version)),
+            _ => {
+                let versions: Vec<String> = versions.iter().map(Version::to_string).collect();
+                bail!(
+                    "several versions of package '{}' are locked: {}; \
+                     pick one with {}@<version>",
+                    name,
+                    versions.join(", "),
+                    name
+                )
+            }
+        }
+    }
+
+    /// Returns a spec for every crate in the dependency graph of each of
+    /// `krates`, including the crates themselves, with default features.
+    /// With `max_depth`, only dependencies up to that many levels down are
+    /// included.
+    ///
+    /// Dependencies from other registries are skipped with a warning.
+    pub fn dependency_closure(
+        krates: &[&str],
+        srcid: &SourceId,
+        max_depth: Option<usize>,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<Vec<String>> {
+        if !srcid.is_registry() {
+            bail!("--recursive can only be used with a registry");
+        }
+        let _lock = config.acquire_package_cache_lock()?;
+        let mut src = load_source(config, *srcid, &Default::default())?;
+        src.update()?;
+
+        let mut roots = Vec::new();
+        for &krate in krates {
+            let (name, vers) = parse_krate(krate, opts)?;
+            for id in select_ids(config, &mut src, name, vers, opts)? {
+                let exact = format!("={}", id.version());
+                let dep = Dependency::parse_no_deprecated(name, Some(&exact), id.source_id())?;
+                roots.extend(src.query_vec(&dep)?);
+            }
+        }
+
+        let closure = deps::closure(&mut src, &roots, max_depth)?;
+        if !closure.other.is_empty() {
+            let names: Vec<&str> = closure.other.iter().map(|name| name.as_str()).collect();
+            config.shell().warn(format!(
+                "skipping dependencies that are not from {}: {}",
+                srcid,
+                names.join(", ")
+            ))?;
+        }
+        config.shell().status(
+            "Found",
+            format!("{} crates in the dependency graph", closure.pkgs.len()),
+        )?;
+        Ok(closure
+            .pkgs
+            .iter()
+            .map(|id| format!("{}@{}", id.name(), id.version()))
+            .collect())
+    }
+
+    /// Fetches only the commit `reference` points to from the git repository
+    /// at `url`, defaulting to its `HEAD`, and checks it out in a temporary
+    /// directory that can be cloned from as a path source.
+    ///
+    /// This uses the `git` command line, as cargo's own git support always
+    /// fetches the whole history. Cargo's proxy, certificate, and timeout
+    /// settings are passed on to it.
+    pub fn shallow_checkout(
+        url: &str,
+        reference: Option<&GitReference>,
+        submodules: bool,
+        config: &Config,
+    ) -> CargoResult<tempfile::TempDir> {
+        let refspec = match reference {
+            None => "HEAD".to_string(),
+            Some(GitReference::Branch(branch)) => format!("refs/heads/{}", branch),
+            Some(GitReference::Tag(tag)) => format!("refs/tags/{}", tag),
+            Some(GitReference::Rev(rev)) => rev.clone(),
+        };
+        config
+            .shell()
+            .status("Fetching", format!("{} {} (shallow)", url, refspec))?;
+
+        // The `git` command does not read cargo's config, so the settings
+        // that matter for reaching the repository are passed on to it.
+        let mut settings = Vec::new();
+        if let Some(proxy) = config.get_string("http.proxy")? {
+            settings.push(format!("http.proxy={}", proxy.val));
+        }
+        if let Some(cainfo) = config.get_path("http.cainfo")? {
+            settings.push(format!("http.sslCAInfo={}", cainfo.val.display()));
+        }
+        if let Some(timeout) = config.get_i64("http.timeout")? {
+            let limit = config
+                .get_i64("http.low-speed-limit")?
+                .map_or(10, |l| l.val);
+            settings.push(format!("http.lowSpeedLimit={}", limit));
+            settings.push(format!("http.lowSpeedTime={}", timeout.val));
+        }
+        let settings: Vec<String> = settings
+            .into_iter()
+            .flat_map(|setting| vec!["-c".to_string(), setting])
+            .collect();
+
+        let tmp = tempfile::Builder::new().prefix("cargo-clone-").tempdir()?;
+        let git = |args: &[&str]| {
+            process("git")
+                .args(&settings)
+                .args(args)
+                .cwd(tmp.path())
+                .exec_with_output()
+                .map(drop)
+        };
+        git(&["init", "--quiet"])?;
+        git(&["fetch", "--quiet", "--depth", "1", url, &refspec])?;
+        git(&["checkout", "--quiet", "FETCH_HEAD"])?;
+        if submodules && tmp.path().join(".gitmodules").exists() {
+            config.shell().status("Updating", "submodules (shallow)")?;
+            git(&[
+                "submodule",
+                "update",
+                "--quiet",
+                "--init",
+                "--recursive",
+                "--depth",
+                "1",
+            ])?;
+        }
+
+        // Only the files are cloned, and a one-commit history is no use.
+        let gits: Vec<PathBuf> = WalkDir::new(tmp.path())
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name() == ".git")
+            .map(|entry| entry.path().to_owned())
+            .collect();
+        for git in gits {
+            if git.is_dir() {
+                paths::remove_dir_all(&git)?;
+            } else if git.exists() {
+                paths::remove_file(&git)?;
+            }
+        }
+        Ok(tmp)
+    }
+
+    /// A published version, as shown by `list_versions`.
+    #[derive(Serialize)]
+    struct VersionInfo {
+        version: Version,
+        yanked: bool,
+        published: Option<String>,
+    }
+
+    /// Prints the available versions of each crate, newest first, without
+    /// downloading anything. A version in the spec filters the listing.
+    ///
+    /// Versions are looked up with the web API `api` if one is given.
+    pub fn list_versions(
+        krates: &[&str],
+        srcid: &SourceId,
+        json: bool,
+        api: Option<&str>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        for &spec in krates {
+            let (name, vers) = parse_spec(spec)?;
+            let mut versions = query_versions(name, srcid, api, config)?;
+            if versions.is_empty() {
+                let _lock = config.acquire_package_cache_lock()?;
+                let mut src = load_source(config, *srcid, &HashSet::new())?;
+                let suggestion = suggest_names(config, &mut *src, name, api)?;
+                return Err(exit::error(
+                    Failure::NotFound,
+                    format!("package '{}' not found{}", name, suggestion),
+                ));
+            }
+            if let Some(v) = vers {
+                let req = parse_version_req(v)?;
+                versions.retain(|info| matches_pre(&req, &info.version));
+            }
+            versions.sort_by(|a, b| b.version.cmp(&a.version));
+
+            if json {
+                let listing = serde_json::json!({ "name": name, "versions": versions });
+                println!("{}", listing);
+                continue;
+            }
+
+            let width = versions
+                .iter()
+                .map(|info| info.version.to_string().len())
+                .max()
+                .unwrap_or(0);
+            for info in &versions {
+                let date = info
+                    .published
+                    .as_ref()
+                    .map_or("-", |d| &d[..10.min(d.len())]);
+                let line = format!(
+                    "{} {:<width$}  {:<10}  {}",
+                    name,
+                    info.version.to_string(),
+                    date,
+                    if info.yanked { "yanked" } else { "" },
+                    width = width
+                );
+                println!("{}", line.trim_end());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the clones recorded in the clone database, oldest first. With
+    /// `specs`, only clones of crates matching one of them are returned; a
+    /// spec's name may be a glob, and its version a semver requirement, which
+    /// may also come from `vers`.
+    fn find_clones(
+        specs: &[&str],
+        vers: Option<&str>,
+        config: &Config,
+    ) -> CargoResult<Vec<db::Record>> {
+        let mut filters = Vec::new();
+        for &spec in specs {
+            let (name, spec_vers) = parse_spec(spec)?;
+            let name =
+                Pattern::new(name).chain_err(|| format!("invalid glob pattern '{}'", name))?;
+            let req = match spec_vers.or(vers) {
+                Some(v) => Some(parse_version_req(v)?),
+                None => None,
+            };
+            filters.push((name, req));
+        }
+        if filters.is_empty() {
+            if let Some(v) = vers {
+                filters.push((Pattern::new("*")?, Some(parse_version_req(v)?)));
+            }
+        }
+
+        let mut records = db::load(config)?;
+        records.retain(|record| {
+            let version = record.version.parse::<Version>().ok();
+            filters.is_empty()
+                || filters.iter().any(|(name, req)| {
+                    name.matches(&record.name)
+                        && match (req, &version) {
+                            (Some(req), Some(version)) => matches_pre(req, version),
+                            (Some(_), None) => false,
+                            (None, _) => true,
+                        }
+                })
+        });
+        Ok(records)
+    }
+
+    /// Prints the clones recorded in the clone database that match `specs`
+    /// and `vers`, as `find_clones` picks them.
+    pub fn list_clones(
+        specs: &[&str],
+        vers: Option<&str>,
+        json: bool,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let records = find_clones(specs, vers, config)?;
+
+        if json {
+            for record in &records {
+                println!("{}", serde_json::to_string(record)?);
+            }
+            return Ok(());
+        }
+        let name_width = records.iter().map(|r| r.name.len()).max().unwrap_or(0);
+        let vers_width = records.iter().map(|r| r.version.len()).max().unwrap_or(0);
+        for record in &records {
+            println!(
+                "{:<name_width$}  {:<vers_width$}  {:<10}  {}{}",
+                record.name,
+                record.version,
+                &record.cloned_at[..10.min(record.cloned_at.len())],
+                record.path.display(),
+                if record.path.exists() {
+                    ""
+                } else {
+                    " (missing)"
+                },
+                name_width = name_width,
+                vers_width = vers_width
+            );
+        }
+        Ok(())
+    }
+
+    /// Deletes the clones recorded in the clone database that match `specs`
+    /// and `vers`, as `find_clones` picks them, and forgets them. With
+    /// `older_than`, only clones made longer ago than that are deleted, and
+    /// with `dry_run`, nothing is. Unless `yes`, it asks before deleting
+    /// anything.
+    ///
+    /// Clones that are already gone are just forgotten.
+    pub fn clean_clones(
+        specs: &[&str],
+        vers: Option<&str>,
+        older_than: Option<Duration>,
+        dry_run: bool,
+        yes: bool,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let mut records = find_clones(specs, vers, config)?;
+        if let Some(age) = older_than {
+            let cutoff = SystemTime::now()
+                .checked_sub(age)
+                .map_or_else(String::new, db::timestamp);
+            records.retain(|record| record.cloned_at < cutoff);
+        }
+        let existing = records.iter().filter(|r| r.path.exists()).count();
+        if !dry_run
+            && !yes
+            && existing > 0
+            && !confirm(&format!("Delete {} recorded clones?", existing), config)?
+        {
+            bail!("nothing was deleted; pass --yes to delete clones without asking");
+        }
+
+        // Records are dropped one at a time, so that a clone that cannot be
+        // deleted leaves only its own record and those after it behind.
+        let (mut removed, mut forgotten, mut freed) = (0, 0, 0);
+        for record in records {
+            let what = format!("{} v{}", record.name, record.version);
+            if !record.path.exists() {
+                config.shell().status(
+                    "Forgetting",
+                    format!("{} (`{}` is gone)", what, record.path.display()),
+                )?;
+                if !dry_run {
+                    db::remove(config, &[record.path])?;
+                }
+                forgotten += 1;
+                continue;
+            }
+            let size = disk_usage(&record.path);
+            config.shell().status(
+                "Removing",
+                format!(
+                    "`{}` ({}, {})",
+                    record.path.display(),
+                    what,
+                    human_size(size)
+                ),
+            )?;
+            if !dry_run {
+                if record.path.is_dir() {
+                    check_safe_to_clear(&record.path, config)?;
+                    paths::remove_dir_all(&record.path)?;
+                } else {
+                    paths::remove_file(&record.path)?;
+                }
+                db::remove(config, &[record.path])?;
+            }
+            freed += size;
+            removed += 1;
+        }
+
+        let mut summary = format!(
+            "{} {} clones, {}",
+            if dry_run { "would remove" } else { "removed" },
+            removed,
+            human_size(freed)
+        );
+        if forgotten > 0 {
+            summary.push_str(&format!(
+                ", and {} {} clones that are already gone",
+                if dry_run { "would forget" } else { "forgot" },
+                forgotten
+            ));
+        }
+        config.shell().status("Summary", summary)?;
+        if dry_run {
+            config.shell().warn("no files deleted due to --dry-run")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the files under `path`, or of the file at `path`.
+    fn disk_usage(path: &Path) -> u64 {
+        WalkDir::new(path)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    /// Returns what a supply-chain review wants to know about where `pkg`,
+    /// from the source behind `srcid`, came from: its repository, the commit
+    /// it was published from and whether the repository still has it on a
+    /// branch or tag, and when and by whom it was published. Whatever is not
+    /// known is `null`.
+    ///
+    /// The repository is cloned into a temporary directory to look for the
+    /// commit, and the publisher is looked up with the registry's web API.
+    fn provenance(
+        pkg: &Package,
+        srcid: SourceId,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<serde_json::Value> {
+        let repository = pkg.manifest().metadata().repository.clone();
+        let info = read_vcs_info(pkg)?;
+        let reachable = match (&repository, &info) {
+            (Some(url), Some(info)) => commit_reachable(url, &info.git.sha1, config)?,
+            _ => None,
+        };
+
+        let mut published = None;
+        if srcid.is_registry() {
+            let src = load_source(config, srcid, &HashSet::new())?;
+            if let Some(api) = registry_api(config, &*src, opts.api)? {
+                match api::versions(config, &api, &pkg.name()) {
+                    Ok(versions) => {
+                        let vers = pkg.version().to_string();
+                        published = versions.into_iter().find(|v| v.num == vers);
+                    }
+                    Err(e) => config.shell().warn(format!(
+                        "could not look up who published {}: {}",
+                        pkg.name(),
+                        e
+                    ))?,
+                }
+            }
+        }
+
+        Ok(serde_json::json!({
+            "name": pkg.name().as_str(),
+            "version": pkg.version().to_string(),
+            "source": srcid.into_url().to_string(),
+            "checksum": package_checksum(pkg, Some(srcid), config).ok().flatten(),
+            "repository": repository,
+            "commit": info.as_ref().map(|info| &info.git.sha1),
+            "path_in_vcs": info.as_ref().and_then(|info| info.path_in_vcs.as_ref()),
+            "dirty": info.as_ref().map(|info| info.git.dirty),
+            "commit_reachable": reachable,
+            "published_at": published.as_ref().map(|v| &v.created_at),
+            "published_by": published.and_then(|v| v.published_by),
+        }))
+    }
+
+    /// Returns whether the commit `sha1` is on a branch or tag of the git
+    /// repository at `url`, or `None` if the repository cannot be cloned.
+    fn commit_reachable(url: &str, sha1: &str, config: &Config) -> CargoResult<Option<bool>> {
+        let tmp = tempfile::Builder::new().prefix("cargo-clone-").tempdir()?;
+        config.shell().status("Fetching", url)?;
+        let repo = match git2::build::RepoBuilder::new()
+            .bare(true)
+            .clone(url, tmp.path())
+        {
+            Ok(repo) => repo,
+            Err(e) => {
+                config.shell().warn(format!(
+                    "could not clone `{}` to look for commit {}: {}",
+                    url, sha1, e
+                ))?;
+                return Ok(None);
+            }
+        };
+        let oid = match git2::Oid::from_str(sha1) {
+            Ok(oid) if repo.find_commit(oid).is_ok() => oid,
+            _ => return Ok(Some(false)),
+        };
+        for reference in repo.references()? {
+            let tip = match reference?.peel_to_commit() {
+                Ok(commit) => commit.id(),
+                Err(_) => continue,
+            };
+            if tip == oid || repo.graph_descendant_of(tip, oid)? {
+                return Ok(Some(true));
+            }
+        }
+        Ok(Some(false))
+    }
+
+    /// Files `cargo package` generates, which `diff_versions` leaves out
+    /// unless asked not to.
+    pub const GENERATED_FILES: &[&str] = &["Cargo.toml.orig", ".cargo_vcs_info.json"];
+
+    /// Writes a unified diff between versions `old` and `new` of `name` from
+    /// the source behind `srcid` to `out`, as `git diff` would show it.
+    ///
+    /// Both versions are cloned into a temporary directory with `opts`'s
+    /// include and exclude globs, and their files compared there. Returns
+    /// whether they differ.
+    pub fn diff_versions(
+        name: &str,
+        old: &str,
+        new: &str,
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        out: &mut dyn Write,
+        config: &Config,
+    ) -> CargoResult<bool> {
+        let tmp = tempfile::Builder::new().prefix("cargo-clone-").tempdir()?;
+        let mut trees = Vec::new();
+        let repo = git2::Repository::init_bare(tmp.path().join("repo"))?;
+        for (side, vers) in &[("old", old), ("new", new)] {
+            let dir = tmp.path().join(side);
+            let prefix = match dir.to_str() {
+                Some(prefix) => prefix,
+                None => bail!("temporary path '{}' is not valid UTF-8", dir.display()),
+            };
+            // A bare version is the exact one, as in a crate spec.
+            let vers = if vers.starts_with(|c: char| c.is_ascii_digit()) {
+                format!("={}", vers)
+            } else {
+                vers.to_string()
+            };
+            let side_opts = CloneOpts {
+                prefix: Some(prefix),
+                vers: Some(&vers),
+                allow_yanked: true,
+                pre: true,
+                include: opts.include,
+                exclude: opts.exclude,
+                untracked: true,
+                ..Default::default()
+            };
+            clone_one(Some(name), srcid, &side_opts, false, config)?;
+            trees.push(write_tree(&repo, &dir)?);
+        }
+
+        let old_tree = repo.find_tree(trees[0])?;
+        let new_tree = repo.find_tree(trees[1])?;
+        let mut diff = repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+        diff.find_similar(None)?;
+        let mut result = Ok(());
+        let printed = diff.print(git2::DiffFormat::Patch, |_, _, line| {
+            let prefix = match line.origin() {
+                origin @ '+' | origin @ '-' | origin @ ' ' => Some(origin as u8),
+                _ => None,
+            };
+            result = prefix
+                .map_or(Ok(()), |prefix| out.write_all(&[prefix]))
+                .and_then(|_| out.write_all(line.content()));
+            result.is_ok()
+        });
+        // A failed write stops the printing, but is the error to report.
+        result?;
+        printed?;
+        Ok(diff.deltas().len() > 0)
+    }
+
+    /// Clones `krate` from the registry behind `srcid`, and the repository
+    /// its manifest links to at the commit it was published from, or else
+    /// at its release tag, and compares the two. Files of the package that
+    /// are missing from the repository or differ from it are printed, and
+    /// any makes this fail.
+    ///
+    /// The files `cargo package` generates are left out, except that
+    /// `Cargo.toml.orig` is compared with the repository's `Cargo.toml`.
+    /// Files only in the repository are expected, as packages leave files
+    /// out.
+    pub fn verify_source(
+        krate: &str,
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        if srcid.is_path() || srcid.is_git() {
+            bail!("verify-source can only check crates from a registry");
+        }
+        let tmp = tempfile::Builder::new().prefix("cargo-clone-").tempdir()?;
+        let dir = tmp.path().join("package");
+        let prefix = match dir.to_str() {
+            Some(prefix) => prefix,
+            None => bail!("temporary path '{}' is not valid UTF-8", dir.display()),
+        };
+        let pkg_opts = CloneOpts {
+            prefix: Some(prefix),
+            vers: opts.vers,
+            allow_yanked: opts.allow_yanked,
+            pre: opts.pre,
+            untracked: true,
+            ..Default::default()
+        };
+        clone_one(Some(krate), srcid, &pkg_opts, false, config)?;
+        let pkg = PathSource::new(&dir, SourceId::for_path(&dir)?, config).root_package()?;
+
+        let url = match &pkg.manifest().metadata().repository {
+            Some(url) => url,
+            None => bail!("{} does not list a repository", pkg.package_id()),
+        };
+        let checkout = tmp.path().join("repo");
+        config.shell().status("Cloning", url)?;
+        let repo = git2::build::RepoBuilder::new()
+            .clone(url, &checkout)
+            .chain_err(|| format!("failed to clone `{}`", url))?;
+        // The recorded commit is what was published; a tag can be moved.
+        let (base, subdir) = match published_commit(&repo, &pkg, url, config)? {
+            Some((oid, what, subdir)) => (Some((oid, what)), subdir),
+            None => (release_tag(&repo, &pkg), None),
+        };
+        let (oid, what) = match base {
+            Some(base) => base,
+            None => bail!(
+                "cannot find the release of {} in `{}`, \
+                 as neither the commit it was published from nor a tag like v{}",
+                pkg.package_id(),
+                url,
+                pkg.version()
+            ),
+        };
+        repo.set_head_detached(oid)?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+        config.shell().status("Checked out", &what)?;
+        let subdir = match subdir {
+            Some(subdir) => subdir,
+            None => find_package(&checkout, &pkg, config)?,
+        };
+        let upstream = join_nonempty(&checkout, &subdir);
+
+        let mut only_published = Vec::new();
+        let mut modified = Vec::new();
+        let mut checked = 0;
+        for entry in walk::entries(&dir, &Filter::new(&[], &[])?, false)? {
+            let rel = entry.rel.to_string_lossy().replace('\\', "/");
+            let theirs = match rel.as_str() {
+                ".cargo_vcs_info.json" | "Cargo.toml" => continue,
+                "Cargo.toml.orig" => upstream.join("Cargo.toml"),
+                // Packages with binaries get a lock file when packaged.
+                "Cargo.lock" if !upstream.join("Cargo.lock").exists() => continue,
+                _ => upstream.join(&entry.rel),
+            };
+            let same = match entry.kind {
+                Kind::Dir => continue,
+                Kind::File if theirs.is_file() => fs::read(&entry.path)? == fs::read(&theirs)?,
+                Kind::Symlink if fs::symlink_metadata(&theirs).is_ok() => {
+                    fs::read_link(&entry.path)? == fs::read_link(&theirs)?
+                }
+                _ => {
+                    only_published.push(rel);
+                    continue;
+                }
+            };
+            checked += 1;
+            if !same {
+                modified.push(rel);
+            }
+        }
+
+        // The findings are the command's output, so they go to stdout even
+        // with --quiet.
+        for rel in &only_published {
+            println!("only in the package: {}", rel);
+        }
+        for rel in &modified {
+            println!("modified: {}", rel);
+        }
+        let id = format!("{} v{}", pkg.name(), pkg.version());
+        if only_published.is_empty() && modified.is_empty() {
+            config.shell().status(
+                "Verified",
+                format!("{} matches {} of `{}` ({} files)", id, what, url, checked),
+            )?;
+            return Ok(());
+        }
+        bail!(
+            "{} differs from {} of `{}` (only in the package: {}, modified: {})",
+            id,
+            what,
+            url,
+            only_published.len(),
+            modified.len()
+        )
+    }
+
+    /// Writes the files under `dir` to `repo` as a tree, returning its ID.
+    fn write_tree(repo: &git2::Repository, dir: &Path) -> CargoResult<git2::Oid> {
+        let mut builder = repo.treebuilder(None)?;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            let (oid, mode) = if file_type.is_dir() {
+                (write_tree(repo, &path)?, 0o040000)
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(&path)?;
+                let target = target.to_string_lossy();
+                (repo.blob(target.as_bytes())?, 0o120000)
+            } else {
+                (repo.blob_path(&path)?, file_mode(&entry.metadata()?))
+            };
+            builder.insert(entry.file_name(), oid, mode)?;
+        }
+        Ok(builder.write()?)
+    }
+
+    /// Returns the git file mode of a regular file.
+    fn file_mode(metadata: &fs::Metadata) -> i32 {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if metadata.permissions().mode() & 0o111 != 0 {
+                return 0o100755;
+            }
+        }
+        let _ = metadata;
+        0o100644
+    }
+
+    /// Where `update_clones` puts a newer version of a clone.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Reclone {
+        /// Replace the old clone.
+        InPlace,
+        /// Clone into `<name>-<version>/` next to the old clone.
+        Alongside,
+    }
+
+    /// Checks the clones recorded in the clone database that match `specs`
+    /// and `vers` for newer versions in the sources they were cloned from,
+    /// and reports them. With `reclone`, each outdated clone is cloned again
+    /// at the newest version.
+    ///
+    /// Yanked versions are never offered, and prereleases only with `pre`.
+    pub fn update_clones(
+        specs: &[&str],
+        vers: Option<&str>,
+        pre: bool,
+        reclone: Option<Reclone>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let records = find_clones(specs, vers, config)?;
+        if records.is_empty() {
+            config.shell().status("Fresh", "no matching clones")?;
+            return Ok(());
+        }
+
+        let mut newest = HashMap::new();
+        let mut outdated = 0;
+        for record in records {
+            let what = format!("{} v{}", record.name, record.version);
+            if !record.path.exists() {
+                config.shell().status(
+                    "Skipping",
+                    format!("{} (`{}` is gone)", what, record.path.display()),
+                )?;
+                continue;
+            }
+            let srcid = match &record.source {
+                Some(source) => SourceId::from_url(source)?,
+                None => {
+                    config
+                        .shell()
+                        .status("Skipping", format!("{} (cloned from a .crate file)", what))?;
+                    continue;
+                }
+            };
+            let key = (srcid, record.name.clone());
+            if !newest.contains_key(&key) {
+                let version = newest_version(&record.name, srcid, pre, config)?;
+                newest.insert(key.clone(), version);
+            }
+            let latest = match &newest[&key] {
+                Some(latest) => latest,
+                None => bail!("package '{}' not found in {}", record.name, srcid),
+            };
+            if record.version.parse::<Version>().ok().as_ref() >= Some(latest) {
+                config
+                    .shell()
+                    .verbose(|shell| shell.status("Fresh", &what))?;
+                continue;
+            }
+            outdated += 1;
+            config.shell().status(
+                "Outdated",
+                format!(
+                    "{} at `{}` (v{} is available)",
+                    what,
+                    record.path.display(),
+                    latest
+                ),
+            )?;
+
+            let reclone = match reclone {
+                Some(reclone) => reclone,
+                None => continue,
+            };
+            if !record.path.is_dir() {
+                config.shell().warn(format!(
+                    "`{}` is not a directory, so it is not cloned again",
+                    record.path.display()
+                ))?;
+                continue;
+            }
+            let path = match record.path.to_str() {
+                Some(path) => path,
+                None => bail!("path '{}' is not valid UTF-8", record.path.display()),
+            };
+            let parent = record.path.parent().and_then(Path::to_str).unwrap_or(".");
+            let opts = match reclone {
+                Reclone::InPlace => CloneOpts {
+                    prefix: Some(path),
+                    on_exists: OnExists::Overwrite,
+                    ..Default::default()
+                },
+                Reclone::Alongside => CloneOpts {
+                    out_dir: Some(parent),
+                    dir_format: Some("{name}-{version}"),
+                    ..Default::default()
+                },
+            };
+            let spec = format!("{}@={}", record.name, latest);
+            clone_one(Some(&spec), &srcid, &opts, false, config)?;
+        }
+
+        if outdated == 0 {
+            config
+                .shell()
+                .status("Fresh", "every clone is up to date")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the newest version of `name` in the source behind `srcid`,
+    /// leaving out yanked versions, and prereleases unless `pre` is set.
+    fn newest_version(
+        name: &str,
+        srcid: SourceId,
+        pre: bool,
+        config: &Config,
+    ) -> CargoResult<Option<Version>> {
+        let _lock = config.acquire_package_cache_lock()?;
+        let mut src = load_source(config, srcid, &HashSet::new())?;
+        src.update()?;
+        let dep = Dependency::parse_no_deprecated(name, None, src.source_id())?;
+        Ok(src
+            .query_vec(&dep)?
+            .iter()
+            .map(|summary| summary.version().clone())
+            .filter(|version| pre || !version.is_prerelease())
+            .max())
+    }
+
+    fn query_versions(
+        name: &str,
+        srcid: &SourceId,
+        api: Option<&str>,
+        config: &Config,
+    ) -> CargoResult<Vec<VersionInfo>> {
+        let _lock = config.acquire_package_cache_lock()?;
+        let mut src = load_source(config, *srcid, &HashSet::new())?;
+
+        // The API knows about every version, yanked or not, so there is no
+        // need for a potentially slow index update.
+        let api = match api {
+            Some(api) => Some(api),
+            None if sparse::is_crates_io(*srcid) && !src.is_replaced() => Some(api::CRATES_IO_API),
+            None => None,
+        };
+        if let Some(api) = api {
+            return api::versions(config, api, name)?
+                .into_iter()
+                .map(|v| {
+                    Ok(VersionInfo {
+                        version: v.num.to_semver()?,
+                        yanked: v.yanked,
+                        published: Some(v.created_at),
+                    })
+                })
+                .collect();
+        }
+        src.update()?;
+
+        let entries = index::read_entries(config, src.replaced_source_id(), name)?;
+        let mut versions = Vec::new();
+        if entries.is_empty() {
+            // Path, git, and directory sources have no index to consult.
+            let dep = Dependency::parse_no_deprecated(name, None, src.source_id())?;
+            for summary in src.query_vec(&dep)? {
+                versions.push(VersionInfo {
+                    version: summary.version().clone(),
+                    yanked: false,
+                    published: None,
+                });
+            }
+        } else {
+            for entry in entries {
+                versions.push(VersionInfo {
+                    version: entry.vers.to_semver()?,
+                    yanked: entry.yanked,
+                    published: None,
+                });
+            }
+        }
+
+        if !versions.is_empty() {
+            let dates = publish_dates(config, &*src, name, None)?;
+            for info in &mut versions {
+                info.published = dates.get(&info.version).cloned();
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Loads the source behind `srcid`. Local registries and `cargo vendor`
+    /// directories are read as they are; other registries follow cargo's
+    /// source replacement, or are read as a sparse registry, which cargo does
+    /// not know.
+    fn load_source<'a>(
+        config: &'a Config,
+        srcid: SourceId,
+        yanked_whitelist: &HashSet<PackageId>,
+    ) -> CargoResult<Box<dyn Source + 'a>> {
+        // Local registries and directories are read from disk as they are,
+        // which needs no network access.
+        if srcid.is_registry() && !srcid.is_remote_registry() {
+            let path = srcid.url().to_file_path().expect("path must be valid");
+            return Ok(Box::new(RegistrySource::local(
+                srcid,
+                &path,
+                yanked_whitelist,
+                config,
+            )));
+        }
+        if is_directory(srcid) {
+            let path = srcid.url().to_file_path().expect("path must be valid");
+            return Ok(Box::new(DirectorySource::new(&path, srcid, config)));
+        }
+        let replacement = sparse_replacement(config, srcid)?;
+        if let Some(replacement) = replacement.filter(|&id| id != srcid) {
+            let yanked = yanked_whitelist
+                .iter()
+                .map(|id| id.map_source(srcid, replacement))
+                .collect();
+            let src = SparseSource::new(replacement, &yanked, config)?;
+            return Ok(Box::new(ReplacedSource::new(
+                srcid,
+                replacement,
+                Box::new(src),
+            )));
+        }
+        let replaced = config
+            .get_string("source.crates-io.replace-with")?
+            .is_some();
+        if sparse::is_crates_io(srcid) && replaced && replacement.is_none() {
+            // Cargo's source config only knows crates.io by its git index.
+            let crates_io = SourceId::crates_io(config)?;
+            return SourceConfigMap::new(config)?.load(crates_io, yanked_whitelist);
+        }
+        if sparse::is_sparse(srcid) {
+            return Ok(Box::new(SparseSource::new(
+                srcid,
+                yanked_whitelist,
+                config,
+            )?));
+        }
+        SourceConfigMap::new(config)?.load(srcid, yanked_whitelist)
+    }
+
+    /// Returns whether `srcid` is a directory of unpacked crates, as written
+    /// by `cargo vendor`. Cargo has no method for this, so the ID is compared
+    /// with the directory source at the same path.
+    fn is_directory(srcid: SourceId) -> bool {
+        srcid
+            .url()
+            .to_file_path()
+            .ok()
+            .and_then(|path| SourceId::for_directory(&path).ok())
+            == Some(srcid)
+    }
+
+    /// Returns the sparse registry that cargo's `[source]` config replaces
+    /// `srcid` with, if it does. Cargo itself would try to read that as a git
+    /// index, so it is loaded here instead.
+    fn sparse_replacement(config: &Config, srcid: SourceId) -> CargoResult<Option<SourceId>> {
+        if !srcid.is_registry() {
+            return Ok(None);
+        }
+        let sources = match config.get_table("source")? {
+            Some(sources) => sources.val,
+            None => return Ok(None),
+        };
+        let string = |name: &str, key: &str| match sources.get(name) {
+            Some(ConfigValue::Table(table, _)) => match table.get(key) {
+                Some(ConfigValue::String(s, _)) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut name = if sparse::is_crates_io(srcid) {
+            "crates-io".to_string()
+        } else {
+            let found = sources.keys().find(|name| {
+                string(name, "registry")
+                    .is_some_and(|url| url.as_str().into_url().ok().as_ref() == Some(srcid.url()))
+            });
+            match found {
+                Some(name) => name.clone(),
+                None => return Ok(None),
+            }
+        };
+        // Cargo reports cycles when it loads the source, so give up on them.
+        for _ in 0..sources.len() {
+            match string(&name, "replace-with") {
+                Some(next) => name = next,
+                None => break,
+            }
+        }
+        match string(&name, "registry") {
+            Some(url) if url.starts_with("sparse+") => {
+                Ok(Some(SourceId::for_registry(&url.into_url()?)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the web API base URL of the registry behind `src`, if any, or
+    /// `api` if one is given.
+    ///
+    /// A mirror of crates.io is asked for its own API first, and only if it
+    /// has none, crates.io's is used.
+    fn registry_api<T: Source + ?Sized>(
+        config: &Config,
+        src: &T,
+        api: Option<&str>,
+    ) -> CargoResult<Option<String>> {
+        if let Some(api) = api {
+            return Ok(Some(api.to_string()));
+        }
+        let crates_io = sparse::is_crates_io(src.source_id());
+        if crates_io && !src.is_replaced() {
+            return Ok(Some(api::CRATES_IO_API.to_string()));
+        }
+        auth::setup(config, src.replaced_source_id())?;
+        match index::read_config(config, src.replaced_source_id())?.and_then(|c| c.api) {
+            Some(api) => Ok(Some(api)),
+            // A local copy of crates.io is meant to be used offline.
+            None if crates_io && src.replaced_source_id().is_remote_registry() => {
+                Ok(Some(api::CRATES_IO_API.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up when each version of `name` was published.
+    ///
+    /// Dates are only known to registries with a web API; if there is none,
+    /// or it can't be reached, the map is empty.
+    fn publish_dates<T: Source + ?Sized>(
+        config: &Config,
+        src: &T,
+        name: &str,
+        api: Option<&str>,
+    ) -> CargoResult<HashMap<Version, String>> {
+        let mut dates = HashMap::new();
+        if let Some(api) = registry_api(config, src, api)? {
+            match api::versions(config, &api, name) {
+                Ok(versions) => {
+                    for v in versions {
+                        if let Ok(version) = v.num.to_semver() {
+                            dates.insert(version, v.created_at);
+                        }
+                    }
+                }
+                Err(e) => config
+                    .shell()
+                    .warn(format!("could not fetch publish dates: {}", e))?,
+            }
+        }
+        Ok(dates)
+    }
+
+    /// Prints the owners, download count, and newest version and its publish
+    /// date of each of `krates` to stderr, so that they can be looked over
+    /// before any code is downloaded.
+    ///
+    /// These are only known to registries with a web API. Whatever cannot be
+    /// looked up is reported as a warning.
+    pub fn show_info(
+        krates: &[&str],
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let api = if srcid.is_registry() {
+            let src = load_source(config, *srcid, &HashSet::new())?;
+            registry_api(config, &*src, opts.api)?
+        } else {
+            None
+        };
+        let api = match api {
+            Some(api) => api,
+            None => {
+                config
+                    .shell()
+                    .warn(format!("{} has no web API to look up crates with", srcid))?;
+                return Ok(());
+            }
+        };
+        for &krate in krates {
+            let name = parse_spec(krate)?.0;
+            if let Err(e) = print_info(config, &api, name) {
+                config
+                    .shell()
+                    .warn(format!("could not look up `{}`: {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn print_info(config: &Config, api: &str, name: &str) -> CargoResult<()> {
+        let krate = match api::krate(config, api, name)? {
+            Some(krate) => krate,
+            None => bail!("the registry does not know it"),
+        };
+        let owners: Vec<String> = api::owners(config, api, name)?
+            .into_iter()
+            .map(|owner| match owner.name {
+                Some(name) if name != owner.login => format!("{} ({})", owner.login, name),
+                _ => owner.login,
+            })
+            .collect();
+        let newest = krate.max_stable_version.or(krate.max_version);
+        let published = match &newest {
+            Some(newest) => api::versions(config, api, name)?
+                .into_iter()
+                .find(|v| &v.num == newest)
+                .map(|v| v.created_at),
+            None => None,
+        };
+
+        let mut shell = config.shell();
+        let out = shell.err();
+        match &newest {
+            Some(newest) => writeln!(out, "{} v{}", krate.name, newest)?,
+            None => writeln!(out, "{}", krate.name)?,
+        }
+        writeln!(
+            out,
+            "    owners     {}",
+            if owners.is_empty() {
+                "-".to_string()
+            } else {
+                owners.join(", ")
+            }
+        )?;
+        writeln!(
+            out,
+            "    published  {}",
+            published.as_ref().map_or("-", |d| &d[..10.min(d.len())])
+        )?;
+        writeln!(out, "    downloads  {}", krate.downloads)?;
+        Ok(())
+    }
+
+    /// How many times more downloads a crate with a similar name must have
+    /// for a crate to be taken for a misspelling of it.
+    const TYPOSQUAT_RATIO: u64 = 100;
+
+    /// Warns about each of `krates` whose name is close to that of a much more
+    /// popular crate, as a misspelling or an impostor would be, and unless
+    /// `yes`, asks on the terminal whether to clone it anyway.
+    ///
+    /// Popularity is only known to registries with a web API. If the API
+    /// cannot be reached, or cargo is offline, the check is skipped; it is
+    /// not worth retrying for.
+    pub fn check_typosquats(
+        krates: &[&str],
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        yes: bool,
+        config: &Config,
+    ) -> CargoResult<()> {
+        if krates.is_empty() || !srcid.is_registry() || config.offline() {
+            return Ok(());
+        }
+        let api = {
+            let src = load_source(config, *srcid, &HashSet::new())?;
+            match registry_api(config, &*src, opts.api)? {
+                Some(api) => api,
+                None => return Ok(()),
+            }
+        };
+        // The most downloaded crates, fetched for the first name checked.
+        let mut top = None;
+        for &krate in krates {
+            let name = parse_spec(krate)?.0;
+            let popular = match api::without_retries(|| {
+                similar_popular_crate(config, &api, name, &mut top)
+            }) {
+                Ok(Some(popular)) => popular,
+                Ok(None) => continue,
+                // The other names would fail the same way.
+                Err(e) => {
+                    config.shell().verbose(|s| {
+                        s.warn(format!("could not check the name `{}`: {}", name, e))
+                    })?;
+                    return Ok(());
+                }
+            };
+            config.shell().warn(format!(
+                "`{}` is very close to the name of `{}`, which is much more popular \
+                 ({} downloads); did you mean `{}`?",
+                name, popular.name, popular.downloads, popular.name
+            ))?;
+            if !yes && !confirm(&format!("Clone `{}` anyway?", name), config)? {
+                bail!("did not clone `{}`; pass --yes to clone it anyway", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// How many crates make a batch large enough that its size is estimated,
+    /// and confirmed, before anything is downloaded.
+    const LARGE_BATCH: usize = 100;
+
+    /// About how many times the size of its `.crate` file a package takes
+    /// once it is unpacked.
+    const UNPACKED_RATIO: u64 = 4;
+
+    /// Estimates how much cloning `krates` downloads and takes on disk, when
+    /// they are a large batch or `max_total_size` is given. Unless `yes`,
+    /// asks on the terminal whether to clone a large batch; if there is a
+    /// `max_total_size`, the estimate is held to it instead.
+    ///
+    /// The sizes of `.crate` files that are not in the local cache are looked
+    /// up with the registry's web API. Those it cannot tell, or that cargo
+    /// being offline leaves unknown, are left out of the estimate.
+    pub fn check_total_size(
+        krates: &[&str],
+        srcid: &SourceId,
+        opts: &CloneOpts<'_>,
+        max_total_size: Option<u64>,
+        yes: bool,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let large = krates.len() >= LARGE_BATCH && !yes;
+        // Picking versions by hand already asks about each crate.
+        if !large && max_total_size.is_none() || !srcid.is_registry() || opts.interactive {
+            return Ok(());
+        }
+
+        let _lock = config.acquire_package_cache_lock()?;
+        let (mut src, registry_srcid) = open_source(srcid, config)?;
+        let registry_srcid = registry_srcid.expect("a registry has a cache");
+        src.update()?;
+        let mut api = if config.offline() {
+            None
+        } else {
+            registry_api(config, &*src, opts.api)?
+        };
+
+        let mut progress = Progress::new("Sizing", ProgressStyle::Ratio, config);
+        let (mut packages, mut unknown) = (0, 0);
+        let (mut download, mut cached) = (0, 0);
+        for (i, &krate) in krates.iter().enumerate() {
+            progress.tick(i, krates.len(), &format!(": {}", krate))?;
+            let ids = match parse_krate(krate, opts)
+                .and_then(|(name, vers)| select_ids(config, &mut src, name, vers, opts))
+            {
+                Ok(ids) => ids,
+                // It fails the same way when it is cloned.
+                Err(..) => continue,
+            };
+            let mut versions = None;
+            for id in ids {
+                packages += 1;
+                let vers = id.version().to_string();
+                if let Some(file) = index::crate_file(config, registry_srcid, &id.name(), &vers) {
+                    cached += fs::metadata(file)?.len();
+                    continue;
+                }
+                if versions.is_none() {
+                    if let Some(url) = &api {
+                        match api::without_retries(|| api::versions(config, url, &id.name())) {
+                            Ok(found) => versions = Some(found),
+                            // The other crates would fail the same way.
+                            Err(e) => {
+                                config.shell().verbose(|s| {
+                                    s.warn(format!("could not look up crate sizes: {}", e))
+                                })?;
+                                api = None;
+                            }
+                        }
+                    }
+                }
+                let size = versions
+                    .iter()
+                    .flatten()
+                    .find(|v| v.num == vers)
+                    .and_then(|v| v.crate_size);
+                match size {
+                    Some(size) => download += size,
+                    None => unknown += 1,
+                }
+            }
+        }
+
+        let mut estimate = format!(
+            "{} to download, about {} unpacked, for {} packages",
+            human_size(download),
+            human_size((download + cached) * UNPACKED_RATIO),
+            packages
+        );
+        if unknown > 0 {
+            estimate.push_str(&format!(" ({} of unknown size)", unknown));
+        }
+        config.shell().status("Estimated", estimate)?;
+
+        if let Some(max) = max_total_size {
+            if download > max {
+                bail!(
+                    "the packages to clone total {} to download, more than the \
+                     {} allowed by --max-total-size",
+                    human_size(download),
+                    human_size(max)
+                );
+            }
+        } else if large && !confirm(&format!("Clone these {} crates?", krates.len()), config)? {
+            bail!("nothing was cloned; pass --yes or --max-total-size to clone large batches without asking");
+        }
+        Ok(())
+    }
+
+    /// Returns a crate whose name is a small edit away from `name`, with many
+    /// times the downloads, if there is one. Candidates are the crates the
+    /// registry's search finds for `name`, and the most downloaded crates,
+    /// which are fetched into `top` if it is `None`. A name that is one of
+    /// the most downloaded itself is not checked further.
+    fn similar_popular_crate(
+        config: &Config,
+        api: &str,
+        name: &str,
+        top: &mut Option<Vec<api::Crate>>,
+    ) -> CargoResult<Option<api::Crate>> {
+        if top.is_none() {
+            let sort = ["sort=downloads".to_string()];
+            *top = Some(api::crates(config, api, &sort, Some(100))?);
+        }
+        let top = top.as_deref().unwrap_or_default();
+        if top
+            .iter()
+            .any(|krate| normalize_name(&krate.name) == normalize_name(name))
+        {
+            return Ok(None);
+        }
+
+        let downloads = match api::krate(config, api, name)? {
+            Some(krate) => krate.downloads,
+            // Cloning it fails anyway.
+            None => return Ok(None),
+        };
+        let search = [api::filter("q", name), "sort=downloads".to_string()];
+        let mut candidates = api::crates(config, api, &search, Some(20))?;
+        candidates.extend(top.iter().cloned());
+        Ok(pick_similar_popular(name, downloads, candidates))
+    }
+
+    /// Returns the first of `candidates` whose name is a small edit away from
+    /// `name`, and which has many times the `downloads` of `name`.
+    fn pick_similar_popular(
+        name: &str,
+        downloads: u64,
+        candidates: Vec<api::Crate>,
+    ) -> Option<api::Crate> {
+        let name = normalize_name(name);
+        let max_distance = if name.len() <= 5 { 1 } else { 2 };
+        candidates
+            .into_iter()
+            .filter(|krate| krate.downloads >= downloads.max(1) * TYPOSQUAT_RATIO)
+            .find(|krate| {
+                let distance = edit_distance(&name, &normalize_name(&krate.name));
+                distance > 0 && distance <= max_distance
+            })
+    }
+
+    /// Returns `name` as cargo compares crate names, which takes `-` and `_`
+    /// for the same.
+    fn normalize_name(name: &str) -> String {
+        name.to_lowercase().replace('-', "_")
+    }
+
+    /// Returns `; did you mean ...?` with the names of crates in `src` that
+    /// are close to `name`, which was not found, or nothing if there are
+    /// none.
+    ///
+    /// The names with `-` and `_` swapped are tried first, as those are the
+    /// likeliest mistake. Failing that, the registry's web API is searched
+    /// for names a small edit away, if it has one and cargo is online.
+    fn suggest_names<T: Source + ?Sized>(
+        config: &Config,
+        src: &mut T,
+        name: &str,
+        api: Option<&str>,
+    ) -> CargoResult<String> {
+        let mut names: Vec<String> = Vec::new();
+        for variant in &[name.replace('-', "_"), name.replace('_', "-")] {
+            if variant == name || names.contains(variant) {
+                continue;
+            }
+            let dep = Dependency::parse_no_deprecated(variant, None, src.source_id())?;
+            if !src.query_vec(&dep)?.is_empty() {
+                names.push(variant.clone());
+            }
+        }
+
+        if names.is_empty() && src.source_id().is_registry() && !config.offline() {
+            if let Some(api) = registry_api(config, src, api)? {
+                let search = [api::filter("q", name), "sort=downloads".to_string()];
+                match api::without_retries(|| api::crates(config, &api, &search, Some(20))) {
+                    Ok(candidates) => {
+                        let normal = normalize_name(name);
+                        let max_distance = if normal.len() <= 5 { 1 } else { 2 };
+                        names.extend(
+                            candidates
+                                .into_iter()
+                                .filter(|krate| {
+                                    edit_distance(&normal, &normalize_name(&krate.name))
+                                        <= max_distance
+                                })
+                                .map(|krate| krate.name)
+                                .take(3),
+                        );
+                    }
+                    Err(e) => config.shell().verbose(|s| {
+                        s.warn(format!("could not search for names like `{}`: {}", name, e))
+                    })?,
+                }
+            }
+        }
+
+        let names: Vec<String> = names.iter().map(|name| format!("`{}`", name)).collect();
+        Ok(match names.len() {
+            0 => String::new(),
+            1 => format!("; did you mean {}?", names[0]),
+            _ => format!("; did you mean one of {}?", names.join(", ")),
+        })
+    }
+
+    /// Returns how many insertions, deletions, substitutions, and swaps of
+    /// adjacent characters turn `a` into `b`.
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        // d[i][j] is the distance between the first i of `a` and j of `b`.
+        let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for (i, row) in d.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        for (j, cell) in d[0].iter_mut().enumerate() {
+            *cell = j;
+        }
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+                d[i][j] = (d[i - 1][j] + 1)
+                    .min(d[i][j - 1] + 1)
+                    .min(d[i - 1][j - 1] + cost);
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+                }
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    /// Asks a yes or no `question` on standard input, which may be a pipe,
+    /// taking no for an answer if the input ends without one.
+    pub fn confirm(question: &str, config: &Config) -> CargoResult<bool> {
+        {
+            let mut shell = config.shell();
+            let out = shell.err();
+            write!(out, "{} [y/N] ", question)?;
+            out.flush()?;
+        }
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(false);
+        }
+        Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+
+    /// Asks on the terminal which of `summaries` to clone, returning its index.
+    fn pick_version<T: Source + ?Sized>(
+        config: &Config,
+        src: &T,
+        name: &str,
+        summaries: &[Summary],
+        yanked: &[PackageId],
+        api: Option<&str>,
+    ) -> CargoResult<usize> {
+        let dates = publish_dates(config, src, name, api)?;
+        let width = summaries
+            .iter()
+            .map(|s| s.version().to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        {
+            let mut shell = config.shell();
+            let out = shell.err();
+            writeln!(out, "Multiple versions of {} match:", name)?;
+            for (i, summary) in summaries.iter().enumerate() {
+                let date = dates
+                    .get(summary.version())
+                    .map_or("-", |d| &d[..10.min(d.len())]);
+                let line = format!(
+                    "{:>4}) {:<width$}  {:<10}  {}",
+                    i + 1,
+                    summary.version().to_string(),
+                    date,
+                    if yanked.contains(&summary.package_id()) {
+                        "yanked"
+                    } else {
+                        ""
+                    },
+                    width = width
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+        }
+
+        loop {
+            {
+                let mut shell = config.shell();
+                let out = shell.err();
+                write!(out, "Select a version [1]: ")?;
+                out.flush()?;
+            }
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input)? == 0 {
+                bail!("no version of package '{}' was selected", name);
+            }
+            let input = input.trim();
+            if input.is_empty() {
+                return Ok(0);
+            }
+            match input.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= summaries.len() => return Ok(n - 1),
+                _ => config.shell().warn(format!(
+                    "expected a number between 1 and {}",
+                    summaries.len()
+                ))?,
+            }
+        }
+    }
+
+    /// Returns whether `dest` holds anything that cloning would clobber.
+    fn is_occupied(dest: &Path) -> CargoResult<bool> {
+        if !dest.exists() {
+            return Ok(false);
+        }
+        Ok(!dest.is_dir() || dest.read_dir()?.next().is_some())
+    }
+
+    /// Applies the `on_exists` policy to `dest`, returning where to clone to,
+    /// or `None` if the clone should be skipped.
+    ///
+    /// Cloning into an existing directory is only allowed if the directory is
+    /// empty, unless the policy says otherwise. When writing an archive, the
+    /// policy applies to `dest` with the archive's extension added.
+    fn resolve_dest(
+        dest: PathBuf,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<Option<PathBuf>> {
+        let output = |path: &Path| match opts.archive {
+            Some(format) => {
+                let mut path = path.as_os_str().to_owned();
+                path.push(".");
+                path.push(format.extension());
+                PathBuf::from(path)
+            }
+            None => path.to_owned(),
+        };
+
+        let target = output(&dest);
+        if !is_occupied(&target)? {
+            return Ok(Some(target));
+        }
+
+        match opts.on_exists {
+            OnExists::Error => Err(exit::error(
+                Failure::Exists,
+                format!(
+                    "destination path '{}' already exists and is not an empty directory.",
+                    target.display()
+                ),
+            )),
+            OnExists::Skip => {
+                config
+                    .shell()
+                    .status("Skipping", format!("{} (already exists)", target.display()))?;
+                Ok(None)
+            }
+            OnExists::Suffix => {
+                let name = match dest.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => bail!("cannot add a suffix to '{}'", dest.display()),
+                };
+                for n in 1.. {
+                    let candidate = output(&dest.with_file_name(format!("{}-{}", name, n)));
+                    if !is_occupied(&candidate)? {
+                        return Ok(Some(candidate));
+                    }
+                }
+                unreachable!()
+            }
+            OnExists::Overwrite => {
+                // An archive may replace a file, but never a directory that
+                // matters.
+                if opts.archive.is_none() || target.is_dir() {
+                    check_safe_to_clear(&target, config)?;
+                }
+                Ok(Some(target))
+            }
+        }
+    }
+
+    /// Copies `pkg` into a temporary sibling of `to` and renames it into
+    /// place once complete, so that a failed copy never leaves a partial tree
+    /// behind. Anything already at `to` is replaced.
+    fn clone_atomically(
+        pkg: &Package,
+        to: &Path,
+        filter: &Filter,
+        movable: bool,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let tmp = temp_dir_beside(to)?;
+        let staged = tmp.path().join("package");
+        // Moving fails across filesystems, so copying is still the fallback.
+        let moved = movable
+            && filter.is_empty()
+            && !opts.dereference
+            && fs::rename(pkg.root(), &staged).is_ok();
+        if !moved {
+            fs::create_dir(&staged)?;
+            clone_directory(pkg.root(), &staged, filter, opts, config)?;
+        }
+        if let Some(name) = opts.fork {
+            fork_package(&staged, pkg, name)?;
+            git_init_fork(&staged, pkg, name)?;
+        } else if opts.git_init {
+            git_init(&staged, pkg)?;
+        }
+
+        move_into_place(&staged, to, config)
+    }
+
+    /// Writes an archive of `pkg` to a temporary sibling of `to`, then moves
+    /// it into place, so an interrupted clone leaves no partial archive.
+    fn archive_atomically(
+        pkg: &Package,
+        format: ArchiveFormat,
+        filter: &Filter,
+        opts: &CloneOpts<'_>,
+        to: &Path,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let parent = match to.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)?;
+
+        let prefix = format!("{}-{}", pkg.name(), pkg.version());
+        let tmp = tempfile::Builder::new()
+            .prefix(".cargo-clone-")
+            .tempfile_in(parent)?;
+        let _partial = interrupt::remove_on_interrupt(tmp.path());
+        match format {
+            ArchiveFormat::TarGz => {
+                let gz =
+                    flate2::write::GzEncoder::new(tmp.as_file(), flate2::Compression::default());
+                archive::write_tar(pkg.root(), &prefix, filter, opts.dereference, gz)?.finish()?;
+            }
+            ArchiveFormat::Zip => {
+                archive::write_zip(pkg.root(), &prefix, filter, tmp.as_file())?;
+            }
+        }
+
+        // Temporary files are private to the user, which archives need not be.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(tmp.path(), fs::Permissions::from_mode(0o644))?;
+        }
+
+        move_into_place(tmp.path(), to, config)?;
+        tmp.into_temp_path().keep()?;
+        Ok(())
+    }
+
+    /// Clones and checks out the submodules of `repo`, and theirs in turn.
+    fn update_submodules(repo: &git2::Repository, config: &Config) -> CargoResult<()> {
+        for mut submodule in repo.submodules()? {
+            let name = submodule.path().display().to_string();
+            config
+                .shell()
+                .status("Updating", format!("submodule {}", name))?;
+            submodule
+                .update(true, None)
+                .chain_err(|| format!("failed to update submodule `{}`", name))?;
+            update_submodules(&submodule.open()?, config)?;
+        }
+        Ok(())
+    }
+
+    /// Makes `dir`, a copy of `pkg`, a git repository with one commit of all
+    /// its files, tagged `v<version>`. Its `origin` remote is the package's
+    /// repository, if it lists one.
+    fn git_init(dir: &Path, pkg: &Package) -> CargoResult<()> {
+        let repo = git2::Repository::init(dir)?;
+        let sig = match repo.signature() {
+            Ok(sig) => sig,
+            Err(_) => git2::Signature::now("cargo-clone", "cargo-clone@localhost")?,
+        };
+        let message = format!("Import {} {}", pkg.name(), pkg.version());
+        let commit = commit_all(&repo, &sig, &message)?;
+        let commit = repo.find_object(commit, None)?;
+        repo.tag(
+            &format!("v{}", pkg.version()),
+            &commit,
+            &sig,
+            &message,
+            false,
+        )?;
+
+        if let Some(url) = &pkg.manifest().metadata().repository {
+            repo.remote("origin", url)?;
+        }
+        Ok(())
+    }
+
+    /// Makes `dir`, a fork of `pkg` as `name`, a git repository of its own,
+    /// with one commit of all its files.
+    fn git_init_fork(dir: &Path, pkg: &Package, name: &str) -> CargoResult<()> {
+        let repo = git2::Repository::init(dir)?;
+        let sig = match repo.signature() {
+            Ok(sig) => sig,
+            Err(_) => git2::Signature::now("cargo-clone", "cargo-clone@localhost")?,
+        };
+        let message = format!("Start {} from {} {}", name, pkg.name(), pkg.version());
+        commit_all(&repo, &sig, &message)?;
+        Ok(())
+    }
+
+    /// Commits every file in the work tree of the new repository `repo`.
+    fn commit_all(
+        repo: &git2::Repository,
+        sig: &git2::Signature<'_>,
+        message: &str,
+    ) -> CargoResult<git2::Oid> {
+        let mut index = repo.index()?;
+        // Published files a `.gitignore` matches are still part of the
+        // package.
+        index.add_all(["*"], git2::IndexAddOption::FORCE, None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        Ok(repo.commit(Some("HEAD"), sig, sig, message, &tree, &[])?)
+    }
+
+    /// Keys of `[package]` that a fork leaves out, as they describe where
+    /// the original lives and who publishes it.
+    const FORK_CLEARED: &[&str] = &[
+        "authors",
+        "categories",
+        "documentation",
+        "homepage",
+        "keywords",
+        "publish",
+        "repository",
+    ];
+
+    /// Turns `dir`, a copy of `pkg`, into a new package called `name`, at
+    /// version 0.1.0. The original's publishing metadata and `[badges]` go,
+    /// as do the files `cargo package` adds, and a `[lib]` named after the
+    /// package is renamed with it.
+    fn fork_package(dir: &Path, pkg: &Package, name: &str) -> CargoResult<()> {
+        for file in &["Cargo.toml.orig", ".cargo_vcs_info.json"] {
+            let path = dir.join(file);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let manifest = dir.join("Cargo.toml");
+        let contents = fs::read_to_string(&manifest)?;
+        let old_lib = pkg.name().replace('-', "_");
+        let mut source: Vec<&str> = contents.lines().collect();
+        // Cargo's note that it generated the manifest stops being true.
+        if source
+            .first()
+            .is_some_and(|line| line.starts_with("# THIS FILE IS AUTOMATICALLY GENERATED"))
+        {
+            let end = source
+                .iter()
+                .position(|line| !line.starts_with('#') && !line.trim().is_empty())
+                .unwrap_or(source.len());
+            source.drain(..end);
+        }
+        let mut lines = Vec::new();
+        let mut section = String::new();
+        // Lines left of a value that spans several, and whether it is kept.
+        let mut open = 0;
+        let mut keep = true;
+        for line in source {
+            let trimmed = line.trim();
+            if open > 0 {
+                open += brackets(trimmed);
+                if keep {
+                    lines.push(line.to_string());
+                }
+                continue;
+            }
+            if trimmed.starts_with('[') && !trimmed.contains('=') {
+                section = trimmed
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .trim()
+                    .to_string();
+            }
+            if section == "badges" || section.starts_with("badges.") {
+                continue;
+            }
+            let key = match trimmed.split_once('=') {
+                Some((key, _)) if !trimmed.starts_with('[') => key.trim(),
+                _ => {
+                    lines.push(line.to_string());
+                    continue;
+                }
+            };
+            let key = key.split('.').next().unwrap().trim();
+            let indent = &line[..line.len() - line.trim_start().len()];
+            open = brackets(trimmed);
+            keep = true;
+            match (section.as_str(), key) {
+                ("package", "name") => lines.push(format!(
+                    "{}name = {}",
+                    indent,
+                    toml::Value::String(name.to_string())
+                )),
+                ("package", "version") => lines.push(format!("{}version = \"0.1.0\"", indent)),
+                ("package", key) if FORK_CLEARED.contains(&key) => keep = false,
+                ("lib", "name") if trimmed.contains(&format!("\"{}\"", old_lib)) => {
+                    lines.push(format!(
+                        "{}name = {}",
+                        indent,
+                        toml::Value::String(name.replace('-', "_"))
+                    ))
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+
+        let mut edited = lines.join("\n");
+        edited.push('\n');
+        parse_manifest(&manifest, &edited).chain_err(|| {
+            format!(
+                "failed to rewrite the manifest of {} as {}",
+                pkg.name(),
+                name
+            )
+        })?;
+        fs::write(&manifest, edited)?;
+        Ok(())
+    }
+
+    /// Returns how many more brackets `line` opens than it closes.
+    fn brackets(line: &str) -> i32 {
+        line.chars()
+            .map(|c| match c {
+                '[' => 1,
+                ']' => -1,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Clones the repository of the git package `pkg` to `to`, checked out at
+    /// the commit the package was read from. Returns the path of the package
+    /// inside the clone.
+    ///
+    /// The clone is made from cargo's copy of the repository, so nothing is
+    /// fetched again, and then pointed at the real remote.
+    fn git_clone_atomically(
+        pkg: &Package,
+        to: &Path,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<PathBuf> {
+        let checkout = git2::Repository::discover(pkg.root())?;
+        let workdir = match checkout.workdir() {
+            Some(workdir) => workdir.canonicalize()?,
+            None => bail!("{} is not in a git checkout", pkg.package_id()),
+        };
+        let subdir = pkg
+            .root()
+            .canonicalize()?
+            .strip_prefix(&workdir)?
+            .to_owned();
+        let head = checkout.head()?.peel_to_commit()?.id();
+        let db = match checkout.find_remote("origin")?.url() {
+            Some(url) => url.to_string(),
+            None => bail!("cannot find the git database of {}", pkg.package_id()),
+        };
+
+        let tmp = temp_dir_beside(to)?;
+        let repo = git2::build::RepoBuilder::new()
+            .bare(false)
+            .clone(&db, tmp.path())?;
+        repo.remote_set_url("origin", pkg.package_id().source_id().url().as_str())?;
+        repo.set_head_detached(head)?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+        if !opts.skip_submodules {
+            update_submodules(&repo, config)?;
+        }
+        drop(repo);
+
+        move_into_place(tmp.path(), to, config)?;
+        tmp.into_path();
+        Ok(join_nonempty(to, &subdir))
+    }
+
+    /// Clones the repository that `pkg`'s manifest links to to `to`, at its
+    /// default branch. With `vcs_commit`, the commit the package was
+    /// published from is checked out instead. With `diff_upstream`, that is
+    /// the release's tag, and the published files are committed on top.
+    ///
+    /// Returns the path of the package inside the clone, or of the clone
+    /// itself if the package cannot be found in it.
+    fn repo_clone_atomically(
+        pkg: &Package,
+        to: &Path,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<PathBuf> {
+        let url = match &pkg.manifest().metadata().repository {
+            Some(url) => url,
+            None => bail!("{} does not list a repository", pkg.package_id()),
+        };
+
+        let tmp = temp_dir_beside(to)?;
+        config.shell().status("Cloning", url)?;
+        let repo = git2::build::RepoBuilder::new()
+            .clone(url, tmp.path())
+            .chain_err(|| format!("failed to clone `{}`", url))?;
+
+        let mut base = None;
+        let mut subdir = None;
+        if opts.diff_upstream {
+            base = release_tag(&repo, pkg);
+        }
+        if base.is_none() && (opts.vcs_commit || opts.diff_upstream) {
+            if let Some((oid, what, path)) = published_commit(&repo, pkg, url, config)? {
+                base = Some((oid, what));
+                subdir = path;
+            }
+        }
+        match &base {
+            Some((oid, what)) => {
+                repo.set_head_detached(*oid)?;
+                repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+                config.shell().status("Checked out", what)?;
+            }
+            None if opts.diff_upstream => bail!(
+                "cannot find the release of {} in `{}`, \
+                 as neither a tag like v{} nor the commit it was published from",
+                pkg.package_id(),
+                url,
+                pkg.version()
+            ),
+            None if opts.vcs_commit => config
+                .shell()
+                .warn("leaving the default branch checked out")?,
+            None => {}
+        }
+
+        let subdir = match subdir {
+            Some(subdir) => subdir,
+            None => find_package(tmp.path(), pkg, config)?,
+        };
+        if let Some((_, what)) = &base {
+            if opts.diff_upstream {
+                commit_published(&repo, &subdir, pkg, what, config)?;
+            }
+        }
+        drop(repo);
+
+        move_into_place(tmp.path(), to, config)?;
+        tmp.into_path();
+        Ok(join_nonempty(to, &subdir))
+    }
+
+    /// Finds the commit `pkg` was published from in `repo`, cloned from
+    /// `url`, along with a description of it and the package's directory in
+    /// the repository if that was recorded. Warns if there is none.
+    fn published_commit(
+        repo: &git2::Repository,
+        pkg: &Package,
+        url: &str,
+        config: &Config,
+    ) -> CargoResult<Option<(git2::Oid, String, Option<PathBuf>)>> {
+        let info = match read_vcs_info(pkg)? {
+            Some(info) => info,
+            None => {
+                config.shell().warn(format!(
+                    "{} does not record the commit it was published from",
+                    pkg.package_id()
+                ))?;
+                return Ok(None);
+            }
+        };
+        let commit = git2::Oid::from_str(&info.git.sha1)
+            .and_then(|oid| repo.find_commit(oid))
+            .ok();
+        match commit {
+            Some(commit) => Ok(Some((
+                commit.id(),
+                format!("commit {}", info.git.sha1),
+                info.path_in_vcs.map(PathBuf::from),
+            ))),
+            None => {
+                config.shell().warn(format!(
+                    "commit {} that {} was published from is not in `{}`",
+                    info.git.sha1,
+                    pkg.package_id(),
+                    url
+                ))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Adds the repository that `pkg`'s manifest links to as a submodule of
+    /// the git repository around the current directory, at `to`. It is
+    /// checked out at the commit or tag the package was published from, if
+    /// either can be found, and staged in the superproject.
+    ///
+    /// Returns the path of the package inside the submodule.
+    fn submodule_add(pkg: &Package, to: &Path, config: &Config) -> CargoResult<PathBuf> {
+        let url = match &pkg.manifest().metadata().repository {
+            Some(url) => url,
+            None => bail!("{} does not list a repository", pkg.package_id()),
+        };
+        let superproject = match git2::Repository::discover(config.cwd()) {
+            Ok(repo) => repo,
+            Err(_) => bail!("--as-submodule must be used inside a git repository"),
+        };
+        let workdir = match superproject.workdir() {
+            Some(workdir) => workdir.canonicalize()?,
+            None => bail!("--as-submodule cannot be used in a bare repository"),
+        };
+        let to = config.cwd().join(to);
+        let parent = match to.parent() {
+            Some(parent) => parent,
+            None => bail!("cannot add a submodule at `{}`", to.display()),
+        };
+        fs::create_dir_all(parent)?;
+        let rel = match parent.canonicalize()?.strip_prefix(&workdir) {
+            Ok(rel) => rel.join(to.file_name().expect("destination has a name")),
+            Err(_) => bail!("`{}` is outside of `{}`", to.display(), workdir.display()),
+        };
+
+        config.shell().status(
+            "Adding",
+            format!("submodule {} from {}", rel.display(), url),
+        )?;
+        let git = |cwd: &Path, args: &[&OsStr]| {
+            process("git")
+                .args(args)
+                .cwd(cwd)
+                .exec_with_output()
+                .map(drop)
+        };
+        git(
+            &workdir,
+            &[
+                "submodule".as_ref(),
+                "add".as_ref(),
+                "--quiet".as_ref(),
+                "--".as_ref(),
+                url.as_ref(),
+                rel.as_os_str(),
+            ],
+        )?;
+
+        let repo = git2::Repository::open(&to)?;
+        let mut subdir = None;
+        let base = match published_commit(&repo, pkg, url, config)? {
+            Some((oid, what, path)) => {
+                subdir = path;
+                Some((oid, what))
+            }
+            None => release_tag(&repo, pkg),
+        };
+        match base {
+            Some((oid, what)) => {
+                repo.set_head_detached(oid)?;
+                repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+                config.shell().status("Checked out", what)?;
+                git(&workdir, &["add".as_ref(), "--".as_ref(), rel.as_os_str()])?;
+            }
+            None => config
+                .shell()
+                .warn("leaving the default branch checked out")?,
+        }
+        drop(repo);
+
+        let subdir = match subdir {
+            Some(subdir) => subdir,
+            None => find_package(&to, pkg, config)?,
+        };
+        Ok(join_nonempty(&to, &subdir))
+    }
+
+    /// Finds the tag `pkg`'s version was released as, trying the usual ways
+    /// of naming one.
+    fn release_tag(repo: &git2::Repository, pkg: &Package) -> Option<(git2::Oid, String)> {
+        let (name, vers) = (pkg.name(), pkg.version());
+        let tags = [
+            format!("v{}", vers),
+            format!("{}", vers),
+            format!("{}-v{}", name, vers),
+            format!("{}-{}", name, vers),
+            format!("{}@{}", name, vers),
+            format!("{}/v{}", name, vers),
+        ];
+        tags.iter().find_map(|tag| {
+            let commit = repo
+                .revparse_single(&format!("refs/tags/{}", tag))
+                .and_then(|obj| obj.peel_to_commit())
+                .ok()?;
+            Some((commit.id(), format!("tag {}", tag)))
+        })
+    }
+
+    /// Replaces the files of the package at `subdir` of `repo`'s checkout
+    /// with the published ones, and commits them on top of `base`.
+    fn commit_published(
+        repo: &git2::Repository,
+        subdir: &Path,
+        pkg: &Package,
+        base: &str,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let workdir = repo.workdir().expect("clone has a working directory");
+        let dir = workdir.join(subdir);
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                paths::remove_dir_all(entry.path())?;
+            } else {
+                paths::remove_file(entry.path())?;
+            }
+        }
+        let filter = Filter::new(&[], &[])?;
+        clone_directory(pkg.root(), &dir, &filter, &CloneOpts::default(), config)?;
+
+        // Index paths always use forward slashes.
+        let mut spec = String::new();
+        for component in subdir.components() {
+            spec.push_str(&component.as_os_str().to_string_lossy());
+            spec.push('/');
+        }
+        spec.push('*');
+        let mut index = repo.index()?;
+        index.add_all([&spec], git2::IndexAddOption::FORCE, None)?;
+        index.update_all([&spec], None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+
+        let sig = match repo.signature() {
+            Ok(sig) => sig,
+            Err(_) => git2::Signature::now("cargo-clone", "cargo-clone@localhost")?,
+        };
+        let parent = repo.head()?.peel_to_commit()?;
+        let message = format!(
+            "Published {} {}\n\nThe files of the published package, on top of {}.\n",
+            pkg.name(),
+            pkg.version(),
+            base
+        );
+        repo.commit(Some("HEAD"), &sig, &sig, &message, &tree, &[&parent])?;
+        Ok(())
+    }
+
+    /// The `.cargo_vcs_info.json` that `cargo package` adds to packages
+    /// published from a clean git checkout.
+    #[derive(Deserialize)]
+    struct VcsInfo {
+        git: GitVcsInfo,
+        /// The package's directory in the repository, recorded by newer
+        /// versions of cargo.
+        path_in_vcs: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct GitVcsInfo {
+        sha1: String,
+        /// Whether the checkout had uncommitted changes.
+        #[serde(default)]
+        dirty: bool,
+    }
+
+    /// Reads `pkg`'s `.cargo_vcs_info.json`, if it has one.
+    fn read_vcs_info(pkg: &Package) -> CargoResult<Option<VcsInfo>> {
+        let path = pkg.root().join(".cargo_vcs_info.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let info = serde_json::from_str(&paths::read(&path)?)
+            .chain_err(|| format!("failed to parse `{}`", path.display()))?;
+        Ok(Some(info))
+    }
+
+    /// Returns where under `repo` the package named like `pkg` lives.
+    fn find_package(repo: &Path, pkg: &Package, config: &Config) -> CargoResult<PathBuf> {
+        let srcid = SourceId::for_path(repo)?;
+        let found = PathSource::new_recursive(repo, srcid, config)
+            .read_packages()
+            .ok()
+            .and_then(|pkgs| pkgs.into_iter().find(|p| p.name() == pkg.name()));
+        match found {
+            Some(found) => Ok(found.root().strip_prefix(repo)?.to_owned()),
+            None => {
+                config.shell().warn(format!(
+                    "package `{}` not found in its repository",
+                    pkg.name()
+                ))?;
+                Ok(PathBuf::new())
+            }
+        }
+    }
+
+    fn join_nonempty(path: &Path, rel: &Path) -> PathBuf {
+        if rel.as_os_str().is_empty() {
+            path.to_owned()
+        } else {
+            path.join(rel)
+        }
+    }
+
+    /// Makes a directory to unpack packages into for one clone. It is put in
+    /// the output directory, or else the current one, so that packages can
+    /// usually be moved from it rather than copied.
+    fn scratch_dir(opts: &CloneOpts<'_>, config: &Config) -> CargoResult<TempDir> {
+        let base = config.cwd().join(opts.out_dir.unwrap_or("."));
+        let dir = match tempfile::Builder::new()
+            .prefix(".cargo-clone-")
+            .tempdir_in(base)
+        {
+            Ok(dir) => dir,
+            Err(_) => tempfile::Builder::new().prefix("cargo-clone-").tempdir()?,
+        };
+        Ok(TempDir::new(dir))
+    }
+
+    /// Creates a temporary directory next to `to`, to be moved into place
+    /// once it is complete. It removes itself if we bail out early.
+    fn temp_dir_beside(to: &Path) -> CargoResult<TempDir> {
+        let parent = match to.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)?;
+        let dir = tempfile::Builder::new()
+            .prefix(".cargo-clone-")
+            .tempdir_in(parent)?;
+        Ok(TempDir::new(dir))
+    }
+
+    /// Renames `from` to `to`, removing whatever `to` held before.
+    fn move_into_place(from: &Path, to: &Path, config: &Config) -> CargoResult<()> {
+        if to.exists() {
+            config
+                .shell()
+                .verbose(|shell| shell.status("Replacing", to.display()))?;
+            if to.is_dir() && !to.symlink_metadata()?.file_type().is_symlink() {
+                paths::remove_dir_all(to)?;
+            } else {
+                paths::remove_file(to)?;
+            }
+        }
+        fs::rename(from, to)?;
+        Ok(())
+    }
+
+    /// Refuses to clear directories that are very unlikely to be meant, such
+    /// as `/`, the home directory, or the current directory and its parents.
+    fn check_safe_to_clear(dest: &Path, config: &Config) -> CargoResult<()> {
+        let dest = dest.canonicalize()?;
+        if !dest.is_dir() {
+            bail!("destination path '{}' is not a directory", dest.display());
+        }
+
+        let mut protected = vec![
+            ("the current directory", env::current_dir()?),
+            (
+                "the cargo home directory",
+                config.home().as_path_unlocked().to_path_buf(),
+            ),
+        ];
+        if let Some(home) = env::var_os("HOME") {
+            protected.push(("the home directory", PathBuf::from(home)));
+        }
+        for (what, path) in protected {
+            let path = path.canonicalize().unwrap_or(path);
+            if path.starts_with(&dest) {
+                bail!(
+                    "refusing to overwrite '{}' as it is or contains {}",
+                    dest.display(),
+                    what
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out where `pkg` should be cloned to.
+    fn dest_path(pkg: PackageId, opts: &CloneOpts<'_>, nested: bool) -> CargoResult<PathBuf> {
+        if let Some(store) = opts.store {
+            return Ok(Path::new(store)
+                .join(pkg.name().as_str())
+                .join(pkg.version().to_string()));
+        }
+
+        // A prefix on its own names the destination itself.
+        if let (Some(prefix), None, None, false, false, false) = (
+            opts.prefix,
+            opts.out_dir,
+            opts.dir_format,
+            opts.all_versions,
+            nested,
+            opts.vendor_layout,
+        ) {
+            return Ok(PathBuf::from(prefix));
+        }
+
+        let mut dest = match opts.out_dir.or(opts.prefix) {
+            Some(path) => PathBuf::from(path),
+            None if opts.vendor_layout => env::current_dir()?.join("vendor"),
+            None => env::current_dir()?,
+        };
+        if let (Some(fork), None) = (opts.fork, opts.dir_format) {
+            dest.push(fork);
+            return Ok(dest);
+        }
+        let format = match opts.dir_format {
+            Some(format) => format,
+            None if opts.all_versions || opts.vendor_layout => "{name}-{version}",
+            None => "{name}",
+        };
+        dest.push(render_dir_format(format, pkg)?);
+        Ok(dest)
+    }
+
+    /// Expands the `{name}`, `{version}`, `{major}`, `{minor}` and `{patch}`
+    /// placeholders of a `--dir-format` template.
+    pub fn render_dir_format(format: &str, pkg: PackageId) -> CargoResult<PathBuf> {
+        let mut out = String::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => bail!("unclosed '{{' in directory format '{}'", format),
+            };
+            let version = pkg.version();
+            match &rest[start + 1..end] {
+                "name" => out.push_str(&pkg.name()),
+                "version" => out.push_str(&version.to_string()),
+                "major" => out.push_str(&version.major.to_string()),
+                "minor" => out.push_str(&version.minor.to_string()),
+                "patch" => out.push_str(&version.patch.to_string()),
+                other => bail!(
+                    "unknown placeholder '{{{}}}' in directory format '{}'",
+                    other,
+                    format
+                ),
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+
+        let path = PathBuf::from(out);
+        let escapes = path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)));
+        if path.as_os_str().is_empty() || escapes {
+            bail!(
+                "directory format '{}' must expand to a relative path \
+                 without '..' (got '{}')",
+                format,
+                path.display()
+            );
+        }
+        Ok(path)
+    }
+
+    /// Splits a crate spec of the form `name` or `name@version` into its parts.
+    pub fn parse_spec(spec: &str) -> CargoResult<(&str, Option<&str>)> {
+        let mut parts = spec.splitn(2, '@');
+        let name = parts.next().unwrap();
+        let vers = parts.next();
+
+        if name.is_empty() {
+            bail!("invalid crate spec '{}': missing crate name", spec);
+        }
+        if vers == Some("") {
+            bail!("invalid crate spec '{}': missing version after '@'", spec);
+        }
+
+        Ok((name, vers))
+    }
+
+    /// Turns a crate argument given as a package ID spec, such as
+    /// `registry+https://github.com/rust-lang/crates.io-index#serde@1.0.188`,
+    /// or as a crates.io or docs.rs URL, into a `name` or `name@version` spec
+    /// and the source the spec names, if any. Anything else is returned as
+    /// it is.
+    pub fn parse_crate_arg(arg: &str) -> CargoResult<(String, Option<SourceId>)> {
+        let web = ["crates.io/", "www.crates.io/", "docs.rs/"];
+        let full = if web.iter().any(|host| arg.starts_with(host)) {
+            format!("https://{}", arg)
+        } else {
+            arg.to_string()
+        };
+        if !full.contains("://") {
+            return Ok((arg.to_string(), None));
+        }
+
+        let (kind, rest) = match full.split_once('+') {
+            Some((kind, rest)) if ["registry", "sparse", "git", "path"].contains(&kind) => {
+                (Some(kind), rest)
+            }
+            _ => (None, &full[..]),
+        };
+        let (base, fragment) = match rest.split_once('#') {
+            Some((base, fragment)) => (base, Some(fragment)),
+            None => (rest, None),
+        };
+        let mut url = base.into_url()?;
+        let segments: Vec<String> = url.path_segments().map_or(Vec::new(), |segments| {
+            segments
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        });
+
+        // Pages about a crate on crates.io and docs.rs.
+        if kind.is_none() && fragment.is_none() {
+            let page = match url.host_str() {
+                Some("crates.io") | Some("www.crates.io")
+                    if segments.len() >= 2 && segments[0] == "crates" =>
+                {
+                    // Only a version may follow the name, or another page.
+                    let vers = segments.get(2).filter(|v| v.to_semver().is_ok());
+                    Some((segments[1].clone(), vers.cloned()))
+                }
+                Some("docs.rs") => {
+                    let rest = match segments.first().map(String::as_str) {
+                        Some("crate") => &segments[1..],
+                        _ => &segments[..],
+                    };
+                    let vers = rest.get(1).filter(|v| *v != "latest");
+                    rest.first().map(|name| (name.clone(), vers.cloned()))
+                }
+                _ => None,
+            };
+            return match page {
+                Some((name, Some(vers))) => Ok((format!("{}@{}", name, vers), None)),
+                Some((name, None)) => Ok((name, None)),
+                None => bail!(
+                    "invalid crate '{}': not a crates.io or docs.rs crate page",
+                    arg
+                ),
+            };
+        }
+
+        // The name and version are in the fragment, or the name is the last
+        // part of the URL, as in cargo's package ID specs.
+        let last = segments.last().cloned().unwrap_or_default();
+        let (name, vers) = match fragment {
+            Some(fragment) => match fragment.split_once(&['@', ':'][..]) {
+                Some((name, vers)) => (name.to_string(), Some(vers.to_string())),
+                None if fragment.to_semver().is_ok() => (last, Some(fragment.to_string())),
+                None => (fragment.to_string(), None),
+            },
+            None => (last, None),
+        };
+        if name.is_empty() || vers.as_deref() == Some("") {
+            bail!("invalid package ID spec '{}'", arg);
+        }
+
+        let srcid = match kind {
+            Some("registry") => SourceId::for_registry(&url)?,
+            Some("sparse") => SourceId::for_registry(&format!("sparse+{}", url).into_url()?)?,
+            Some("git") => {
+                let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+                let reference = if let Some(rev) = query.get("rev") {
+                    GitReference::Rev(rev.clone())
+                } else if let Some(tag) = query.get("tag") {
+                    GitReference::Tag(tag.clone())
+                } else {
+                    let branch = query.get("branch").map_or("master", String::as_str);
+                    GitReference::Branch(branch.to_string())
+                };
+                url.set_query(None);
+                SourceId::for_git(&url, reference)?
+            }
+            _ if url.scheme() == "file" => match url.to_file_path() {
+                Ok(path) => SourceId::for_path(&path)?,
+                Err(()) => bail!("invalid package ID spec '{}': bad path", arg),
+            },
+            // Older versions of cargo leave out the kind of registries.
+            None if url.as_str() == cargo::sources::CRATES_IO_INDEX => {
+                SourceId::for_registry(&url)?
+            }
+            _ => bail!(
+                "invalid package ID spec '{}': start it with registry+, sparse+, \
+                 git+, or path+ to say what kind of source it is",
+                arg
+            ),
+        };
+        match vers {
+            Some(vers) => Ok((format!("{}@{}", name, vers), Some(srcid))),
+            None => Ok((name, Some(srcid))),
+        }
+    }
+
+    /// Turns a `--vers` argument into a version requirement.
+    ///
+    /// A plain version such as `1.2.3` means exactly that version, like
+    /// `cargo install --vers`; anything else is parsed as a semver requirement.
+    fn parse_version_req(vers: &str) -> CargoResult<VersionReq> {
+        if let Ok(v) = vers.to_semver() {
+            return Ok(VersionReq::exact(&v));
+        }
+        match VersionReq::parse(vers) {
+            Ok(req) => Ok(req),
+            Err(e) => bail!("invalid version requirement '{}': {}", vers, e),
+        }
+    }
+
+    fn select_pkgs<'a, T>(
+        config: &'a Config,
+        mut src: T,
+        name: Option<&str>,
+        vers: Option<&str>,
+        scratch: Option<&Path>,
+        opts: &CloneOpts<'_>,
+        list_all: &mut dyn FnMut(&mut T) -> CargoResult<Vec<Package>>,
+    ) -> CargoResult<Vec<Package>>
+    where
+        T: Source + 'a,
+    {
+        src.update()?;
+
+        match name {
+            Some(name) => {
+                let ids = select_ids(config, &mut src, name, vers, opts)?;
+                download_pkgs(config, src, &ids, scratch)
+            }
+            None => {
+                let mut candidates = list_all(&mut src)?;
+                match candidates.len() {
+                    0 => bail!("no packages found in {}", src.source_id()),
+                    1 => Ok(candidates),
+                    _ => {
+                        candidates.sort_by_key(|pkg| pkg.name());
+                        let names: Vec<&str> =
+                            candidates.iter().map(|pkg| pkg.name().as_str()).collect();
+                        bail!(
+                            "{} contains several packages, pick one with --package: {}",
+                            src.source_id(),
+                            names.join(", ")
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    /// Picks the versions of `name` to clone from `src`, which must already
+    /// be up to date.
+    fn select_ids<T: Source>(
+        config: &Config,
+        src: &mut T,
+        name: &str,
+        vers: Option<&str>,
+        opts: &CloneOpts<'_>,
+    ) -> CargoResult<Vec<PackageId>> {
+        let req = match vers {
+            Some(v) => Some(parse_version_req(v)?),
+            None => None,
+        };
+        let entries = if opts.allow_yanked || opts.rust_version.is_some() {
+            index::read_entries(config, src.replaced_source_id(), name)?
+        } else {
+            Vec::new()
+        };
+        let yanked = if opts.allow_yanked {
+            allow_yanked(src, &entries)?
+        } else {
+            Vec::new()
+        };
+
+        // Requirements are matched here rather than by the source so
+        // that prereleases can be handled the same way for all of them.
+        let any = Dependency::parse_no_deprecated(name, None, src.source_id())?;
+        let mut available = src.query_vec(&any)?;
+        if available.is_empty() {
+            let suggestion = suggest_names(config, src, name, opts.api)?;
+            return Err(exit::error(
+                Failure::NotFound,
+                format!("package '{}' not found{}", name, suggestion),
+            ));
+        }
+        available.sort_by(|a, b| b.version().cmp(a.version()));
+
+        let mut summaries: Vec<Summary> = available
+            .iter()
+            .filter(|s| match &req {
+                Some(req) if opts.pre => matches_pre(req, s.version()),
+                Some(req) => req.matches(s.version()),
+                None => opts.pre || !s.version().is_prerelease(),
+            })
+            .cloned()
+            .collect();
+
+        if summaries.is_empty() {
+            let only_pre = available.iter().any(|s| {
+                s.version().is_prerelease()
+                    && req.as_ref().is_none_or(|req| matches_pre(req, s.version()))
+            });
+            if only_pre && !opts.pre {
+                return Err(exit::error(
+                    Failure::NoVersion,
+                    format!(
+                        "only prerelease versions of package '{}' match; \
+                     use --pre to select them",
+                        name
+                    ),
+                ));
+            }
+            let near: Vec<String> = available
+                .iter()
+                .take(5)
+                .map(|s| s.version().to_string())
+                .collect();
+            return Err(exit::error(
+                Failure::NoVersion,
+                format!(
+                    "no version of package '{}' matches '{}'; \
+                 latest available versions: {}",
+                    name,
+                    vers.unwrap_or("*"),
+                    near.join(", ")
+                ),
+            ));
+        }
+
+        if let Some(rust) = &opts.rust_version {
+            let mut required = HashMap::new();
+            for entry in &entries {
+                if let (Ok(vers), Some(rv)) = (entry.vers.to_semver(), &entry.rust_version) {
+                    required.insert(vers, parse_rust_version(rv)?);
+                }
+            }
+            summaries.retain(|s| required.get(s.version()).is_none_or(|rv| rv <= rust));
+            if summaries.is_empty() {
+                return Err(exit::error(
+                    Failure::NoVersion,
+                    format!(
+                        "no version of package '{}' matching '{}' supports Rust {}",
+                        name,
+                        vers.unwrap_or("*"),
+                        rust
+                    ),
+                ));
+            }
+        }
+
+        if let Some(date) = opts.published_before {
+            let dates = publish_dates(config, src, name, opts.api)?;
+            if dates.is_empty() {
+                bail!(
+                    "publish dates of package '{}' are not available \
+                     from this registry",
+                    name
+                );
+            }
+            // Dates are ISO 8601, so comparing them as strings works.
+            summaries.retain(|s| dates.get(s.version()).is_some_and(|d| &d[..] < date));
+            if summaries.is_empty() {
+                return Err(exit::error(
+                    Failure::NoVersion,
+                    format!(
+                        "no version of package '{}' matching '{}' was published before {}",
+                        name,
+                        vers.unwrap_or("*"),
+                        date
+                    ),
+                ));
+            }
+        }
+
+        if opts.interactive && !opts.all_versions && summaries.len() > 1 {
+            let choice = pick_version(config, src, name, &summaries, &yanked, opts.api)?;
+            summaries = vec![summaries.swap_remove(choice)];
+        } else if !opts.all_versions {
+            summaries.truncate(1);
+        }
+        for summary in &summaries {
+            emit_resolved(summary.package_id());
+            if yanked.contains(&summary.package_id()) {
+                config.shell().warn(format!(
+                    "selected yanked version {} v{}",
+                    summary.name(),
+                    summary.version()
+                ))?;
+            }
+        }
+        Ok(summaries.iter().map(|s| s.package_id()).collect())
+    }
+
+    /// Emits the event for a package picked to be cloned.
+    fn emit_resolved(id: PackageId) {
+        message::emit(
+            "resolved",
+            serde_json::json!({
+                "name": id.name().as_str(),
+                "version": id.version().to_string(),
+                "source": id.source_id().into_url().to_string(),
+            }),
+        );
+    }
+
+    /// Like `VersionReq::matches`, but also accepts prereleases of versions
+    /// that the requirement matches.
+    fn matches_pre(req: &VersionReq, version: &Version) -> bool {
+        let mut release = version.clone();
+        release.pre.clear();
+        req.matches(version) || req.matches(&release)
+    }
+
+    /// Allows `src` to return the yanked versions among `entries`.
+    fn allow_yanked<T: Source>(src: &mut T, entries: &[IndexEntry]) -> CargoResult<Vec<PackageId>> {
+        let mut yanked = Vec::new();
+        for entry in entries.iter().filter(|e| e.yanked) {
+            yanked.push(PackageId::new(
+                &entry.name[..],
+                &entry.vers[..],
+                src.source_id(),
+            )?);
+        }
+        src.add_to_yanked_whitelist(&yanked);
+        Ok(yanked)
+    }
+
+    /// Parses a Rust version such as `1.56` or `1.56.1`, ignoring any
+    /// prerelease part like `-nightly`.
+    pub fn parse_rust_version(vers: &str) -> CargoResult<Version> {
+        let release = vers.trim().split('-').next().unwrap();
+        let mut parts = Vec::new();
+        for part in release.split('.') {
+            match part.parse::<u64>() {
+                Ok(n) => parts.push(n),
+                Err(_) => bail!("invalid Rust version '{}'", vers),
+            }
+        }
+        if parts.is_empty() || parts.len() > 3 {
+            bail!("invalid Rust version '{}'", vers);
+        }
+        parts.resize(3, 0);
+        Ok(Version::new(parts[0], parts[1], parts[2]))
+    }
+
+    /// Downloads the given packages from `src`, returning them newest first.
+    ///
+    /// With `scratch`, registry packages that cargo has not unpacked already
+    /// are unpacked there straight from their `.crate` files, rather than
+    /// into cargo's shared `src` directory for a clone that only copies them.
+    fn download_pkgs<'a, T>(
+        config: &'a Config,
+        src: T,
+        ids: &[PackageId],
+        scratch: Option<&Path>,
+    ) -> CargoResult<Vec<Package>>
+    where
+        T: Source + 'a,
+    {
+        let srcid = src.replaced_source_id();
+        if srcid.is_remote_registry() {
+            reuse_cached(config, srcid, ids)?;
+            emit_downloads(config, srcid, ids)?;
+        }
+        if srcid.is_remote_registry() && !sparse::is_sparse(srcid) {
+            download_authorized(config, srcid, ids)?;
+        }
+        // Cargo only checks a `cargo vendor` directory against its checksums
+        // when building.
+        for &id in ids {
+            src.verify(id)?;
+        }
+
+        let mut pkgs = Vec::new();
+        let mut rest = ids.to_vec();
+        if let Some(scratch) = scratch.filter(|_| srcid.is_remote_registry()) {
+            let (unpack, unpacked) = ids.iter().partition::<Vec<PackageId>, _>(|id| {
+                !index::unpacked_path(config, srcid, &id.name(), &id.version().to_string()).exists()
+            });
+            download_crate_files(config, srcid, &unpack)?;
+            for id in unpack {
+                let vers = id.version().to_string();
+                let file = index::crate_path(config, srcid, &id.name(), &vers)
+                    .expect("registries keep .crate files");
+                pkgs.push(unpack_crate_file(&file, scratch, config)?);
+            }
+            rest = unpacked;
+        }
+
+        // Cargo's own downloads cannot be slowed down, so with a rate limit
+        // the `.crate` files are fetched here first.
+        if api::rate_limit().is_some() && srcid.is_remote_registry() {
+            download_crate_files(config, srcid, &rest)?;
+        }
+        if !rest.is_empty() {
+            let mut sources = SourceMap::new();
+            sources.insert(Box::new(src));
+            let pkg_set = PackageSet::new(&rest, sources, config)?;
+            pkgs.extend(pkg_set.get_many(rest.iter().cloned())?.into_iter().cloned());
+        }
+        if srcid.is_registry() {
+            verify_crate_files(config, srcid, ids)?;
+        }
+        pkgs.sort_by(|a, b| b.version().cmp(a.version()));
+        Ok(pkgs)
+    }
+
+    /// Emits an event for each of `ids` whose `.crate` file is not in the
+    /// cache of the registry behind `srcid`, and so is about to be downloaded.
+    fn emit_downloads(config: &Config, srcid: SourceId, ids: &[PackageId]) -> CargoResult<()> {
+        let dl = index::read_config(config, srcid)?.map(|registry| registry.dl);
+        for &id in ids {
+            let vers = id.version().to_string();
+            if index::crate_file(config, srcid, &id.name(), &vers).is_none() {
+                message::emit(
+                    "downloading",
+                    serde_json::json!({
+                        "name": id.name().as_str(),
+                        "version": vers,
+                        "url": dl.as_ref().map(|dl| sparse::download_url(dl, id)),
+                    }),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the `.crate` file of each of `ids` against the checksum in the
+    /// index of the registry behind `srcid`, which cargo only does when it
+    /// downloads one. A file that does not match is removed, along with what
+    /// was unpacked from it, so that it is downloaded again next time.
+    fn verify_crate_files(config: &Config, srcid: SourceId, ids: &[PackageId]) -> CargoResult<()> {
+        for &id in ids {
+            let (name, vers) = (id.name(), id.version().to_string());
+            let file = match index::crate_file(config, srcid, &name, &vers) {
+                Some(file) => file,
+                None => continue,
+            };
+            let expected = match index::read_entries(config, srcid, &name)?
+                .into_iter()
+                .find(|entry| entry.vers == vers)
+            {
+                Some(entry) => entry.cksum,
+                None => continue,
+            };
+            let actual = Sha256::new().update_path(&file)?.finish_hex();
+            if actual != expected {
+                fs::remove_file(&file)?;
+                let unpacked = index::unpacked_path(config, srcid, &name, &vers);
+                if unpacked.exists() {
+                    paths::remove_dir_all(&unpacked)?;
+                }
+                bail!(
+                    "the checksum of {} v{} does not match the registry's index\n\
+                     expected: {}\n\
+                     found:    {}\n\
+                     the downloaded file was removed, and is downloaded again \
+                     the next time it is cloned",
+                    name,
+                    vers,
+                    expected,
+                    actual
+                );
+            }
+            config.shell().verbose(|shell| {
+                shell.status(
+                    "Verified",
+                    format!("{} v{} (sha256 {})", name, vers, actual),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Puts the `.crate` files of `ids` that are in another of cargo's cache
+    /// directories for the registry behind `srcid`, such as one written by a
+    /// newer cargo, where they are looked for, so that they are not
+    /// downloaded again. Only files matching the index's checksum are used.
+    fn reuse_cached(config: &Config, srcid: SourceId, ids: &[PackageId]) -> CargoResult<()> {
+        let dirs = cache_dirs(config, srcid, "cache")?;
+        for &id in ids {
+            let (name, vers) = (id.name(), id.version().to_string());
+            let path = match index::crate_path(config, srcid, &name, &vers) {
+                Some(path) if !path.is_file() => path,
+                _ => continue,
+            };
+            let file = format!("{}-{}.crate", name, vers);
+            let cached: Vec<PathBuf> = dirs
+                .iter()
+                .map(|dir| dir.join(&file))
+                .filter(|cached| cached.is_file())
+                .collect();
+            if cached.is_empty() {
+                continue;
+            }
+            let cksum = index::read_entries(config, srcid, &name)?
+                .into_iter()
+                .find(|entry| entry.vers == vers)
+                .map(|entry| entry.cksum);
+            for cached in cached {
+                if cksum.as_deref() != Some(&*Sha256::new().update_path(&cached)?.finish_hex()) {
+                    continue;
+                }
+                config
+                    .shell()
+                    .verbose(|shell| shell.status("Reusing", format!("{}", cached.display())))?;
+                fs::create_dir_all(path.parent().unwrap())?;
+                // `.crate` files are never changed, so they can be shared.
+                if fs::hard_link(&cached, &path).is_err() {
+                    fs::copy(&cached, &path)?;
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Downloads the `.crate` files of `ids` from the git-indexed registry
+    /// behind `srcid` if it requires a token, which cargo's downloads cannot
+    /// send. They go into the registry's cache, where cargo then finds them.
+    fn download_authorized(config: &Config, srcid: SourceId, ids: &[PackageId]) -> CargoResult<()> {
+        match index::read_auth_config(config, srcid)? {
+            Some(registry) if registry.auth_required => {}
+            _ => return Ok(()),
+        }
+        auth::setup(config, srcid)?;
+        download_crate_files(config, srcid, ids)
+    }
+
+    /// Downloads the `.crate` files of `ids` that are not in the cache of the
+    /// registry behind `srcid` yet, checking them against the index.
+    ///
+    /// Each file is downloaded next to where it belongs under a `.part`
+    /// name, and only moved into place once its checksum matches. An
+    /// interrupted download is resumed from its `.part` file.
+    fn download_crate_files(
+        config: &Config,
+        srcid: SourceId,
+        ids: &[PackageId],
+    ) -> CargoResult<()> {
+        let dl = match index::read_config(config, srcid)? {
+            Some(registry) => registry.dl,
+            None => bail!("{} has no config.json", srcid),
+        };
+        for &id in ids {
+            let (name, vers) = (id.name(), id.version().to_string());
+            let path = match index::crate_path(config, srcid, &name, &vers) {
+                Some(path) if !path.is_file() => path,
+                _ => continue,
+            };
+            let cksum = index::read_entries(config, srcid, &name)?
+                .into_iter()
+                .find(|entry| entry.vers == vers)
+                .map(|entry| entry.cksum);
+
+            let url = sparse::download_url(&dl, id);
+            let part = index::part_path(&path);
+            if part.exists() {
+                config.shell().status("Resuming", id)?;
+            } else {
+                config.shell().status("Downloading", id)?;
+            }
+            let (code, body) = api::download(config, &url, &part)?;
+            if code != 200 {
+                bail!("failed to download `{}` (HTTP status {})", url, code);
+            }
+            if cksum != Some(Sha256::new().update(&body).finish_hex()) {
+                fs::remove_file(&part)?;
+                bail!("failed to verify the checksum of `{}`", id);
+            }
+            fs::rename(&part, &path)?;
+        }
+        Ok(())
+    }
+
+    fn clone_directory(
+        from: &Path,
+        to: &Path,
+        filter: &Filter,
+        opts: &CloneOpts<'_>,
+        config: &Config,
+    ) -> CargoResult<()> {
+        let entries = walk::entries(from, filter, opts.dereference)?;
+        for entry in &entries {
+            let to = to.join(&entry.rel);
+            if entry.kind != Kind::Dir {
+                config
+                    .shell()
+                    .verbose(|shell| shell.status("Copying", entry.rel.display()))?;
+            }
+            match entry.kind {
+                Kind::Dir => fs::create_dir(&to)?,
+                // Copying keeps the permissions, including executable bits.
+                Kind::File => {
+                    fs::copy(&entry.path, &to)?;
+                }
+                Kind::Symlink => copy_symlink(&entry.path, &to)?,
+            }
+        }
+
+        if opts.preserve {
+            // Children come after their parents, so going backwards sets a
+            // directory's times only once nothing more is written into it.
+            for entry in entries.iter().rev() {
+                let to = to.join(&entry.rel);
+                let metadata = fs::symlink_metadata(&entry.path)?;
+                let mtime = FileTime::from_last_modification_time(&metadata);
+                match entry.kind {
+                    Kind::Dir => {
+                        fs::set_permissions(&to, metadata.permissions())?;
+                        filetime::set_file_mtime(&to, mtime)?;
+                    }
+                    Kind::File => filetime::set_file_mtime(&to, mtime)?,
+                    Kind::Symlink => filetime::set_symlink_file_times(
+                        &to,
+                        FileTime::from_last_access_time(&metadata),
+                        mtime,
+                    )?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn copy_symlink(from: &Path, to: &Path) -> CargoResult<()> {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn copy_symlink(from: &Path, to: &Path) -> CargoResult<()> {
+        let target = fs::read_link(from)?;
+        if fs::metadata(from).map(|m| m.is_dir()).unwrap_or(false) {
+            std::os::windows::fs::symlink_dir(target, to)?;
+        } else {
+            std::os::windows::fs::symlink_file(target, to)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use cargo::core::Shell;
+
+        #[test]
+        fn parse_spec_splits_name_and_version() {
+            assert_eq!(parse_spec("serde").unwrap(), ("serde", None));
+            assert_eq!(parse_spec("serde@1.0.0").unwrap(), ("serde", Some("1.0.0")));
+            assert_eq!(parse_spec("serde@^1.2").unwrap(), ("serde", Some("^1.2")));
+            assert_eq!(
+                parse_spec("serde@1.0.0-beta.1").unwrap(),
+                ("serde", Some("1.0.0-beta.1"))
+            );
+        }
+
+        #[test]
+        fn parse_spec_rejects_missing_parts() {
+            assert!(parse_spec("").is_err());
+            assert!(parse_spec("@1.0.0").is_err());
+            assert!(parse_spec("serde@").is_err());
+        }
+
+        #[test]
+        fn parse_version_req_exact_versions() {
+            let req = parse_version_req("1.2.3").unwrap();
+            assert!(req.matches(&Version::parse("1.2.3").unwrap()));
+            assert!(!req.matches(&Version::parse("1.2.4").unwrap()));
+
+            let req = parse_version_req("1.0.0-beta.2").unwrap();
+            assert!(req.matches(&Version::parse("1.0.0-beta.2").unwrap()));
+            assert!(!req.matches(&Version::parse("1.0.0-beta.3").unwrap()));
+            assert!(!req.matches(&Version::parse("1.0.0").unwrap()));
+        }
+
+        #[test]
+        fn parse_version_req_requirements() {
+            let req = parse_version_req("^1.2").unwrap();
+            assert!(req.matches(&Version::parse("1.9.0").unwrap()));
+            assert!(!req.matches(&Version::parse("2.0.0").unwrap()));
+            assert!(!req.matches(&Version::parse("1.3.0-rc.1").unwrap()));
+
+            let req = parse_version_req("< 0.5").unwrap();
+            assert!(req.matches(&Version::parse("0.4.9").unwrap()));
+
+            assert!(parse_version_req("not a version").is_err());
+        }
+
+        fn package_id(name: &str, vers: &str) -> PackageId {
+            let srcid = SourceId::for_registry(&"https://example.com/index".into_url().unwrap());
+            PackageId::new(name, vers, srcid.unwrap()).unwrap()
+        }
+
+        #[test]
+        fn render_dir_format_placeholders() {
+            let id = package_id("serde", "1.2.3-rc.1");
+            let render = |format| render_dir_format(format, id).unwrap();
+            assert_eq!(render("{name}"), PathBuf::from("serde"));
+            assert_eq!(
+                render("{name}-{version}"),
+                PathBuf::from("serde-1.2.3-rc.1")
+            );
+            assert_eq!(
+                render("{name}/v{major}.{minor}.{patch}"),
+                Path::new("serde").join("v1.2.3")
+            );
+            assert_eq!(render("fixed"), PathBuf::from("fixed"));
+        }
+
+        #[test]
+        fn render_dir_format_errors() {
+            let id = package_id("serde", "1.0.0");
+            let err = render_dir_format("{name}-{vers}", id).unwrap_err();
+            assert!(err.to_string().contains("unknown placeholder '{vers}'"));
+            assert!(render_dir_format("{name", id).is_err());
+            assert!(render_dir_format("../{name}", id).is_err());
+            assert!(render_dir_format("/tmp/{name}", id).is_err());
+            assert!(render_dir_format("", id).is_err());
+        }
+
+        /// A configuration for a run in `cwd`, that prints nothing.
+        fn quiet_config(cwd: &Path) -> Config {
+            let shell = Shell::from_write(Box::new(io::sink()));
+            Config::new(shell, cwd.to_path_buf(), cwd.join("cargo-home"))
+        }
+
+        fn write_package(dir: &Path, name: &str) -> CargoResult<()> {
+            fs::create_dir_all(dir.join("src"))?;
+            fs::write(dir.join("src").join("lib.rs"), "")?;
+            let manifest = format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name);
+            fs::write(dir.join("Cargo.toml"), manifest)?;
+            Ok(())
+        }
+
+        #[test]
+        fn insert_member_on_one_line() {
+            let contents = "[workspace]\nmembers = [\"a\"]  # the crates\n";
+            assert_eq!(insert_member(contents, "b"), None);
+            let contents = "[workspace]\nmembers = [\"a\"]\n\n[profile.dev]\nopt-level = 1\n";
+            assert_eq!(
+                insert_member(contents, "b").unwrap(),
+                "[workspace]\nmembers = [\"a\", \"b\"]\n\n[profile.dev]\nopt-level = 1\n"
+            );
+            let contents = "[workspace]\nmembers = []\n";
+            assert_eq!(
+                insert_member(contents, "b").unwrap(),
+                "[workspace]\nmembers = [\"b\"]\n"
+            );
+        }
+
+        #[test]
+        fn insert_member_keeps_layout_and_comments() {
+            let contents = "\
+[workspace]
+members = [
+  # Our own.
+  \"a\",
+  \"c\"  # the last one
+]
+";
+            assert_eq!(
+                insert_member(contents, "b").unwrap(),
+                "\
+[workspace]
+members = [
+  # Our own.
+  \"a\",
+  \"c\", # the last one
+  \"b\",
+]
+"
+            );
+        }
+
+        #[test]
+        fn insert_member_without_members() {
+            let contents = "[package]\nname = \"x\"\n\n[workspace]\nexclude = [\"old\"]\n";
+            assert_eq!(
+                insert_member(contents, "b").unwrap(),
+                "[package]\nname = \"x\"\n\n[workspace]\nmembers = [\"b\"]\nexclude = [\"old\"]\n"
+            );
+            assert_eq!(insert_member("[package]\nname = \"x\"\n", "b"), None);
+        }
+
+        #[test]
+        fn add_member_once() -> CargoResult<()> {
+            let root = tempfile::tempdir()?;
+            let root = root.path().canonicalize()?;
+            fs::write(
+                root.join("Cargo.toml"),
+                "[workspace]\nmembers = [\n    \"a\",  # first\n]\n",
+            )?;
+            write_package(&root.join("a"), "a")?;
+            write_package(&root.join("vendor").join("b"), "b")?;
+            let config = quiet_config(&root);
+            let dir = root.join("vendor").join("b");
+            let pkg = PathSource::new(&dir, SourceId::for_path(&dir)?, &config).root_package()?;
+
+            add_member(&pkg, &dir, &config)?;
+            let expected = "[workspace]\nmembers = [\n    \"a\",  # first\n    \"vendor/b\",\n]\n";
+            assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, expected);
+            add_member(&pkg, &dir, &config)?;
+            assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, expected);
+            Ok(())
+        }
+
+        #[test]
+        fn add_member_covered_by_glob() -> CargoResult<()> {
+            let root = tempfile::tempdir()?;
+            let root = root.path().canonicalize()?;
+            let manifest = "[workspace]\nmembers = [\"vendor/*\"]\n";
+            fs::write(root.join("Cargo.toml"), manifest)?;
+            write_package(&root.join("vendor").join("b"), "b")?;
+            let config = quiet_config(&root);
+            let dir = root.join("vendor").join("b");
+            let pkg = PathSource::new(&dir, SourceId::for_path(&dir)?, &config).root_package()?;
+
+            add_member(&pkg, &dir, &config)?;
+            assert_eq!(fs::read_to_string(root.join("Cargo.toml"))?, manifest);
+            Ok(())
+        }
+
+        #[test]
+        fn link_local_dependencies() -> CargoResult<()> {
+            let dir = tempfile::tempdir()?;
+            let dir = dir.path();
+            for (name, vers) in &[
+                ("a", "1.2.0"),
+                ("b", "0.2.1"),
+                ("c", "0.1.0"),
+                ("d", "2.0.0"),
+            ] {
+                let pkg = dir.join(format!("{}-{}", name, vers));
+                fs::create_dir_all(&pkg)?;
+                let manifest = format!("[package]\nname = \"{}\"\nversion = \"{}\"\n", name, vers);
+                fs::write(pkg.join("Cargo.toml"), manifest)?;
+            }
+            let app = dir.join("app");
+            fs::create_dir_all(&app)?;
+            fs::write(
+                app.join("Cargo.toml"),
+                "\
+[package]
+name = \"app\"
+version = \"0.1.0\"
+
+[dependencies]
+a = \"1\"
+b = { version = \"0.2\", features = [\"x\"] }
+renamed = { package = \"c\", version = \"0.1\" }
+d = \"1\"
+e = \"1\"
+
+[dev-dependencies.a]
+version = \"1.1\"
+",
+            )?;
+
+            link_local(dir, &quiet_config(dir))?;
+            assert_eq!(
+                fs::read_to_string(app.join("Cargo.toml"))?,
+                "\
+[package]
+name = \"app\"
+version = \"0.1.0\"
+
+[dependencies]
+a = { version = \"1\", path = \"../a-1.2.0\" }
+b = { path = \"../b-0.2.1\", version = \"0.2\", features = [\"x\"] }
+renamed = { path = \"../c-0.1.0\", package = \"c\", version = \"0.1\" }
+d = \"1\"
+e = \"1\"
+
+[dev-dependencies.a]
+path = \"../a-1.2.0\"
+version = \"1.1\"
+"
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn fork_package_rewrites_manifest() -> CargoResult<()> {
+            let dir = tempfile::tempdir()?;
+            let dir = dir.path();
+            fs::create_dir_all(dir.join("src"))?;
+            fs::write(dir.join("src").join("lib.rs"), "")?;
+            fs::write(dir.join("Cargo.toml.orig"), "")?;
+            fs::write(dir.join(".cargo_vcs_info.json"), "{}")?;
+            fs::write(
+                dir.join("Cargo.toml"),
+                "\
+# THIS FILE IS AUTOMATICALLY GENERATED BY CARGO
+#
+# When uploading crates to the registry Cargo will automatically
+# \"normalize\" Cargo.toml files for maximal compatibility.
+
+[package]
+edition = \"2018\"
+name = \"my-crate\"
+version = \"1.4.2\"
+authors = [
+    \"Someone <someone@example.com>\",
+]
+description = \"Does things\"
+keywords = [\"things\"]
+license = \"MIT\"
+repository = \"https://github.com/someone/my-crate\"
+
+[lib]
+name = \"my_crate\"
+path = \"src/lib.rs\"
+
+[dependencies.serde]
+version = \"1.0\"
+[badges.travis-ci]
+repository = \"someone/my-crate\"
+",
+            )?;
+            let config = quiet_config(dir);
+            let pkg = PathSource::new(dir, SourceId::for_path(dir)?, &config).root_package()?;
+
+            fork_package(dir, &pkg, "my-fork")?;
+            assert_eq!(
+                fs::read_to_string(dir.join("Cargo.toml"))?,
+                "\
+[package]
+edition = \"2018\"
+name = \"my-fork\"
+version = \"0.1.0\"
+description = \"Does things\"
+license = \"MIT\"
+
+[lib]
+name = \"my_fork\"
+path = \"src/lib.rs\"
+
+[dependencies.serde]
+version = \"1.0\"
+"
+            );
+            assert!(!dir.join("Cargo.toml.orig").exists());
+            assert!(!dir.join(".cargo_vcs_info.json").exists());
+            Ok(())
+        }
+
+        #[test]
+        fn parse_crate_arg_plain_specs() {
+            assert_eq!(
+                parse_crate_arg("serde").unwrap(),
+                ("serde".to_string(), None)
+            );
+            assert_eq!(
+                parse_crate_arg("serde@1.0.0").unwrap(),
+                ("serde@1.0.0".to_string(), None)
+            );
+        }
+
+        #[test]
+        fn parse_crate_arg_package_id_specs() {
+            let arg = "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.188";
+            let (spec, srcid) = parse_crate_arg(arg).unwrap();
+            assert_eq!(spec, "serde@1.0.188");
+            let srcid = srcid.unwrap();
+            assert!(srcid.is_registry());
+            assert_eq!(
+                srcid.url().as_str(),
+                "https://github.com/rust-lang/crates.io-index"
+            );
+
+            let arg = "sparse+https://index.example.com/#foo";
+            let (spec, srcid) = parse_crate_arg(arg).unwrap();
+            assert_eq!(spec, "foo");
+            assert!(srcid.unwrap().is_registry());
+
+            let arg = "git+https://github.com/serde-rs/serde?tag=v1.0.0#serde_derive@1.0.0";
+            let (spec, srcid) = parse_crate_arg(arg).unwrap();
+            assert_eq!(spec, "serde_derive@1.0.0");
+            assert!(srcid.unwrap().is_git());
+        }
+
+        #[test]
+        fn parse_crate_arg_web_pages() {
+            let parse = |arg| parse_crate_arg(arg).unwrap();
+            assert_eq!(
+                parse("https://crates.io/crates/x/1.0.0"),
+                ("x@1.0.0".to_string(), None)
+            );
+            assert_eq!(parse("crates.io/crates/serde"), ("serde".to_string(), None));
+            assert_eq!(
+                parse("https://crates.io/crates/serde/versions"),
+                ("serde".to_string(), None)
+            );
+            assert_eq!(
+                parse("https://docs.rs/serde/1.0.188/serde/"),
+                ("serde@1.0.188".to_string(), None)
+            );
+            assert_eq!(
+                parse("https://docs.rs/crate/serde/latest"),
+                ("serde".to_string(), None)
+            );
+        }
+
+        #[test]
+        fn parse_crate_arg_malformed() {
+            assert!(parse_crate_arg("https://crates.io/about").is_err());
+            assert!(parse_crate_arg("https://example.com/crates/serde").is_err());
+            assert!(parse_crate_arg("registry+https://example.com/index#@1.0.0").is_err());
+            assert!(parse_crate_arg("registry+https://example.com/index#serde@").is_err());
+            assert!(parse_crate_arg("https://crates.io/crates").is_err());
+        }
+
+        #[test]
+        fn url_package_from_file_name() {
+            let url = "https://static.crates.io/crates/serde-json-core/serde-json-core-0.5.1.crate";
+            assert_eq!(
+                url_package(url),
+                Some(("serde-json-core".to_string(), "0.5.1".to_string()))
+            );
+            let url = "https://example.com/foo-1.0.0-beta.2.crate?token=x";
+            assert_eq!(
+                url_package(url),
+                Some(("foo".to_string(), "1.0.0-beta.2".to_string()))
+            );
+        }
+
+        #[test]
+        fn url_package_from_path() {
+            let url = "https://crates.io/api/v1/crates/serde/1.0.100/download";
+            assert_eq!(
+                url_package(url),
+                Some(("serde".to_string(), "1.0.100".to_string()))
+            );
+        }
+
+        #[test]
+        fn url_package_unknown() {
+            assert_eq!(url_package("https://example.com/foo.crate"), None);
+            assert_eq!(url_package("https://example.com/download"), None);
+        }
+
+        #[test]
+        fn edit_distance_counts_edits() {
+            assert_eq!(edit_distance("serde", "serde"), 0);
+            assert_eq!(edit_distance("serde", "serd"), 1);
+            assert_eq!(edit_distance("serde", "sserde"), 1);
+            assert_eq!(edit_distance("serde", "sarde"), 1);
+            assert_eq!(edit_distance("serde", "sedre"), 1);
+            assert_eq!(edit_distance("", "abc"), 3);
+            assert_eq!(edit_distance("tokio", "rayon"), 5);
+        }
+
+        fn krate(name: &str, downloads: u64) -> api::Crate {
+            api::Crate {
+                name: name.to_string(),
+                downloads,
+                max_stable_version: None,
+                max_version: None,
+            }
+        }
+
+        #[test]
+        fn pick_similar_popular_finds_lookalikes() {
+            let candidates = vec![krate("rand", 1_000), krate("serde", 1_000_000)];
+            let found = pick_similar_popular("serd", 50, candidates.clone());
+            assert_eq!(found.map(|c| c.name), Some("serde".to_string()));
+            // `-` and `_` are the same to cargo.
+            let candidates = vec![krate("serde_json", 1_000_000)];
+            let found = pick_similar_popular("serde-jsn", 0, candidates);
+            assert_eq!(found.map(|c| c.name), Some("serde_json".to_string()));
+        }
+
+        #[test]
+        fn pick_similar_popular_skips_others() {
+            // Not popular enough.
+            let found = pick_similar_popular("serd", 50, vec![krate("serde", 4_999)]);
+            assert!(found.is_none());
+            // The same name, once normalized.
+            let found = pick_similar_popular("serde-json", 0, vec![krate("serde_json", 1_000)]);
+            assert!(found.is_none());
+            // Too far from a short name.
+            let found = pick_similar_popular("srd", 0, vec![krate("serde", 1_000)]);
+            assert!(found.is_none());
+        }
     }
 }
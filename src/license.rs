@@ -0,0 +1,219 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A summary of the licenses of the packages a run cloned, for reviewing a
+//! dependency tree: how many packages use each license expression, and which
+//! ones have no license or one that is not a plain SPDX expression.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use cargo::util::CargoResult;
+
+use serde_json::json;
+
+use crate::ops::Cloned;
+
+/// The SPDX license identifiers crates commonly use. An identifier that is
+/// not among them is reported, even if SPDX lists it, so that someone looks.
+const LICENSES: &[&str] = &[
+    "0BSD",
+    "AFL-3.0",
+    "AGPL-3.0",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+    "Apache-1.1",
+    "Apache-2.0",
+    "Artistic-1.0",
+    "Artistic-2.0",
+    "BlueOak-1.0.0",
+    "BSD-1-Clause",
+    "BSD-2-Clause",
+    "BSD-2-Clause-Patent",
+    "BSD-3-Clause",
+    "BSD-3-Clause-Clear",
+    "BSD-4-Clause",
+    "BSL-1.0",
+    "bzip2-1.0.6",
+    "CC-BY-3.0",
+    "CC-BY-4.0",
+    "CC-BY-SA-4.0",
+    "CC-PDDC",
+    "CC0-1.0",
+    "CDDL-1.0",
+    "CECILL-2.1",
+    "curl",
+    "ECL-2.0",
+    "EPL-1.0",
+    "EPL-2.0",
+    "EUPL-1.2",
+    "GPL-2.0",
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "ICU",
+    "ISC",
+    "LGPL-2.0",
+    "LGPL-2.0-only",
+    "LGPL-2.0-or-later",
+    "LGPL-2.1",
+    "LGPL-2.1-only",
+    "LGPL-2.1-or-later",
+    "LGPL-3.0",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "libpng-2.0",
+    "MIT",
+    "MIT-0",
+    "MPL-1.1",
+    "MPL-2.0",
+    "MS-PL",
+    "MS-RL",
+    "NCSA",
+    "OFL-1.1",
+    "OpenSSL",
+    "PostgreSQL",
+    "Python-2.0",
+    "Ruby",
+    "Unicode-3.0",
+    "Unicode-DFS-2016",
+    "Unlicense",
+    "UPL-1.0",
+    "W3C",
+    "WTFPL",
+    "X11",
+    "Zlib",
+];
+
+/// The SPDX license exceptions crates commonly use, after `WITH`.
+const EXCEPTIONS: &[&str] = &[
+    "Autoconf-exception-3.0",
+    "Bison-exception-2.2",
+    "Classpath-exception-2.0",
+    "GCC-exception-3.1",
+    "LLVM-exception",
+    "Linux-syscall-note",
+];
+
+/// Returns why `expr` is not a plain SPDX license expression, if it is not.
+fn check(expr: &str) -> Option<String> {
+    if expr.contains('/') {
+        return Some("uses `/` rather than OR".to_string());
+    }
+    let spaced = expr.replace('(', " ( ").replace(')', " ) ");
+    let mut depth = 0;
+    // Whether a license is expected next, rather than an operator.
+    let mut operand = true;
+    let mut exception = false;
+    for token in spaced.split_whitespace() {
+        match token {
+            "(" if operand => depth += 1,
+            ")" if !operand && depth > 0 => depth -= 1,
+            "AND" | "OR" | "WITH" if !operand => {
+                operand = true;
+                exception = token == "WITH";
+            }
+            _ if operand && token != "(" && token != ")" => {
+                if exception {
+                    if !EXCEPTIONS.iter().any(|e| e.eq_ignore_ascii_case(token)) {
+                        return Some(format!("`{}` is not a known license exception", token));
+                    }
+                } else if token.starts_with("LicenseRef-") {
+                    return Some(format!("uses a custom license, `{}`", token));
+                } else {
+                    let id = token.trim_end_matches('+');
+                    if !LICENSES.iter().any(|l| l.eq_ignore_ascii_case(id)) {
+                        return Some(format!("`{}` is not a known SPDX license", token));
+                    }
+                }
+                operand = false;
+                exception = false;
+            }
+            _ => return Some(format!("`{}` is out of place", token)),
+        }
+    }
+    if operand || depth > 0 {
+        return Some("is incomplete".to_string());
+    }
+    None
+}
+
+/// Writes a summary of the licenses of `cloned` to `out`: the number of
+/// packages with each license expression, then the packages whose license
+/// is nonstandard or missing. With `json`, it is one JSON object instead.
+pub fn report(cloned: &[Cloned], json: bool, out: &mut dyn Write) -> CargoResult<()> {
+    let mut counts = BTreeMap::new();
+    let mut nonstandard = Vec::new();
+    let mut missing = Vec::new();
+    for cloned in cloned {
+        let record = &cloned.record;
+        let reason = match (&cloned.license, &cloned.license_file) {
+            (Some(license), _) => check(license),
+            (None, Some(file)) => Some(format!("only has a license file, `{}`", file)),
+            (None, None) => {
+                missing.push(json!({ "name": record.name, "version": record.version }));
+                continue;
+            }
+        };
+        if let Some(license) = &cloned.license {
+            *counts.entry(license.clone()).or_insert(0) += 1;
+        }
+        if let Some(reason) = reason {
+            nonstandard.push(json!({
+                "name": record.name,
+                "version": record.version,
+                "license": cloned.license,
+                "license_file": cloned.license_file,
+                "reason": reason,
+            }));
+        }
+    }
+
+    if json {
+        let report = json!({
+            "packages": cloned.len(),
+            "licenses": counts,
+            "nonstandard": nonstandard,
+            "missing": missing,
+        });
+        writeln!(out, "{}", report)?;
+        return Ok(());
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    writeln!(out, "Licenses of {} packages:", cloned.len())?;
+    for (license, count) in &counts {
+        writeln!(out, "{:>6}  {}", count, license)?;
+    }
+    if !nonstandard.is_empty() {
+        writeln!(out, "Nonstandard licenses:")?;
+        for package in &nonstandard {
+            writeln!(
+                out,
+                "    {} v{}: {}",
+                package["name"].as_str().unwrap(),
+                package["version"].as_str().unwrap(),
+                package["reason"].as_str().unwrap()
+            )?;
+        }
+    }
+    if !missing.is_empty() {
+        writeln!(out, "No license:")?;
+        for package in &missing {
+            writeln!(
+                out,
+                "    {} v{}",
+                package["name"].as_str().unwrap(),
+                package["version"].as_str().unwrap()
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading the packages pinned in a `Cargo.lock`.
+
+use std::fs;
+use std::path::Path;
+
+use cargo::core::SourceId;
+use cargo::sources::CRATES_IO_INDEX;
+use cargo::util::{CargoResult, CargoResultExt};
+
+use serde::Deserialize;
+
+use crate::sparse;
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+/// One `[[package]]` of a lockfile.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    /// Where the package comes from, such as `registry+<index url>`; missing
+    /// for path dependencies.
+    pub source: Option<String>,
+}
+
+/// Reads every package from the lockfile at `path`.
+pub fn read(path: &Path) -> CargoResult<Vec<LockedPackage>> {
+    let contents =
+        fs::read_to_string(path).chain_err(|| format!("failed to read `{}`", path.display()))?;
+    let lockfile: Lockfile = toml::from_str(&contents)
+        .chain_err(|| format!("failed to parse lockfile `{}`", path.display()))?;
+    Ok(lockfile.package)
+}
+
+impl LockedPackage {
+    /// Returns whether the package was locked from the registry behind
+    /// `srcid`.
+    ///
+    /// Cargo records crates.io by its git index even when it uses the sparse
+    /// one, so either matches crates.io.
+    pub fn is_from(&self, srcid: SourceId) -> bool {
+        let source = match &self.source {
+            Some(source) if srcid.is_registry() => source,
+            _ => return false,
+        };
+        let url = match source.strip_prefix("registry+") {
+            Some(url) => url,
+            None if source.starts_with("sparse+") => &source[..],
+            None => return false,
+        };
+        url == srcid.url().as_str()
+            || sparse::is_crates_io(srcid)
+                && (url == CRATES_IO_INDEX || url == sparse::CRATES_IO_INDEX)
+    }
+}
@@ -6,9 +6,12 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use cargo_clone;
-
 use cargo::core::{GitReference, SourceId};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
 use cargo::util::{into_url::IntoUrl, Config};
 
 use docopt::Docopt;
@@ -21,15 +24,102 @@ type Result<T> = std::result::Result<T, failure::Error>;
 
 #[derive(Deserialize, Debug)]
 pub struct Options {
+    cmd_list: bool,
+    cmd_update: bool,
+    cmd_clean: bool,
+    cmd_diff: bool,
+    cmd_verify_source: bool,
+    arg_old: Option<String>,
+    arg_new: Option<String>,
+    flag_output: Option<String>,
+    flag_all_files: bool,
+    flag_older_than: Option<String>,
+    flag_crate: Vec<String>,
+    flag_dry_run: bool,
+    flag_all: bool,
+    flag_in_place: bool,
+    flag_alongside: bool,
+
     flag_verbose: Option<bool>,
     flag_quiet: Option<bool>,
     flag_color: Option<String>,
-    flag_version: Option<bool>,
+    flag_offline: bool,
+    flag_proxy: Option<String>,
+    flag_retries: Option<u32>,
+    flag_limit_rate: Option<String>,
 
     flag_prefix: Option<String>,
+    flag_out_dir: Option<String>,
+    flag_dir_format: Option<String>,
+    flag_force: bool,
+    flag_on_exists: Option<String>,
+    flag_print_path: bool,
+    flag_archive: Option<String>,
+    flag_stdout: bool,
+    flag_keep_archive: bool,
+    flag_keep_archive_in: Option<String>,
+    flag_include: Vec<String>,
+    flag_exclude: Vec<String>,
+    flag_keep_git: bool,
+    flag_shallow: bool,
+    flag_no_submodules: bool,
+    flag_from_repo: bool,
+    flag_vcs_commit: bool,
+    flag_diff_upstream: bool,
+    flag_git_init: bool,
+    flag_patch: bool,
+    flag_add_member: bool,
+    flag_workspace: bool,
+    flag_exclude_broken: bool,
+    flag_link_local: bool,
+    flag_vendor_layout: bool,
+    flag_into_store: bool,
+    flag_fork: Option<String>,
+    flag_provenance: bool,
+    flag_sbom: Option<String>,
+    flag_license_report: bool,
+    flag_security_report: bool,
+    flag_check_typos: bool,
+    flag_yes: bool,
+    flag_max_total_size: Option<String>,
+    flag_show_info: bool,
+    flag_confirm: bool,
+    flag_verify: bool,
+    flag_store: Option<String>,
+    flag_as_submodule: bool,
+    flag_preserve: bool,
+    flag_dereference: bool,
+    flag_jobs: Option<usize>,
+    flag_skip_existing: bool,
+    flag_state: Option<String>,
+    flag_owner: Option<String>,
+    flag_keyword: Option<String>,
+    flag_category: Option<String>,
+    flag_top_downloads: Option<usize>,
+    flag_top: Option<usize>,
+    flag_top_by: Option<String>,
+    flag_min_downloads: Option<u64>,
+    flag_sample: Option<usize>,
+    flag_seed: Option<u64>,
+    flag_from_file: Option<String>,
+    flag_lockfile: Option<String>,
+    flag_locked_dep: Option<String>,
+    flag_recursive: bool,
+    flag_deps: bool,
+    flag_deps_depth: Option<usize>,
 
     arg_crate: Vec<String>,
+    flag_package: Option<String>,
     flag_vers: Option<String>,
+    flag_all_versions: bool,
+    flag_allow_yanked: bool,
+    flag_pre: bool,
+    flag_interactive: bool,
+    flag_rust_version: Option<String>,
+    flag_published_before: Option<String>,
+    flag_list_versions: bool,
+    flag_format: Option<String>,
+    flag_message_format: Option<String>,
     flag_git: Option<String>,
     flag_branch: Option<String>,
     flag_tag: Option<String>,
@@ -37,42 +127,255 @@ pub struct Options {
 
     flag_path: Option<String>,
 
+    flag_registry: Option<String>,
     flag_alt_registry: Option<String>,
 
+    flag_index: Option<String>,
     flag_registry_url: Option<String>,
 
     flag_local_registry: Option<String>,
+    flag_vendor_dir: Option<String>,
+    flag_crate_file: Option<String>,
+    flag_crate_url: Option<String>,
+
+    flag_protocol: Option<String>,
+    flag_api: Option<String>,
+    flag_api_cache: Option<String>,
+    flag_api_cache_ttl: Option<String>,
 }
 
-pub const USAGE: &'static str = "
+pub const USAGE: &str = "
 Clone source code of a Rust crate
 
 Usage:
-    cargo clone [options] [<crate>]...
+    cargo clone list [options] [<crate>]...
+    cargo clone update [options] [<crate>]...
+    cargo clone clean [options] [--crate NAME]... [<crate>]...
+    cargo clone diff [options] [--include GLOB]... [--exclude GLOB]... <crate> <old> <new>
+    cargo clone verify-source [options] <crate>
+    cargo clone [options] [--include GLOB]... [--exclude GLOB]... [<crate>]...
+
+A crate is given as <name> or <name>@<version>, as a package ID spec such as
+registry+<index-url>#<name>@<version>, or as a crates.io or docs.rs URL.
+
+Every clone is recorded. `cargo clone list` prints them, or those of the
+given crates, whose names may be globs, as a table or as JSON lines.
+`cargo clone update` checks them for newer versions, and can clone those too.
+`cargo clone clean` deletes them. `cargo clone diff` shows what changed
+between two versions of a crate, as a unified diff. `cargo clone verify-source`
+checks a published crate against its repository at the commit or tag it was
+published from, and lists the files that are not the same. A crate called
+`list`, `update`, `clean`, `diff`, or `verify-source` can still be cloned
+with -p <name>.
+
+Defaults for the store, jobs, keep-archive, keep-archive-in, and api options
+can be set in ~/.config/cargo-clone/config.toml, or under [clone] in cargo's
+own configuration, which takes precedence.
+
+A failed run exits with 2 if a crate was not found, 3 if no version of a
+crate matches, 4 if a destination already exists, 5 if a registry or
+repository could not be reached, 6 if any of several crates failed to clone,
+130 if interrupted with Ctrl-C, and 101 otherwise. An interrupted run removes
+the clones it had not finished.
 
 Options:
-    --prefix DIR              Directory to clone the package into
+    --prefix DIR              Directory to clone the package into (or a parent
+                              directory when cloning several crates)
+    --out-dir DIR             Parent directory to clone packages into
+    --dir-format FMT          Name of each clone's directory, e.g.
+                              '{name}-{version}' (also {major}, {minor}, {patch})
+    -f, --force               Overwrite a destination directory that is not empty
+    --on-exists POLICY        What to do when the destination is not empty:
+                              error, skip, suffix, overwrite
+    --skip-existing           Same as --on-exists skip
+    --print-path              Print the path of each clone to stdout
+    --archive FORMAT          Write an archive instead of a directory: tar.gz, zip
+    --stdout                  Write a tar of the package to stdout instead
+    --keep-archive            Keep the downloaded .crate file next to the clone
+    --keep-archive-in DIR     Keep the downloaded .crate file in DIR
+    --include GLOB            Only clone files matching GLOB (repeatable)
+    --exclude GLOB            Skip files and directories matching GLOB, e.g.
+                              'tests/**' (repeatable)
+    --as-submodule            Add the crate's repository as a git submodule of
+                              the current repository, at the published commit
+    --git-init                Make the clone a git repository with one commit,
+                              tagged v<version>, and the crate's repository
+                              as its origin
+    --patch                   Point the current workspace's [patch] table at
+                              the clone, to build against it
+    --add-member              Add the clone to the members of the current
+                              workspace
+    --workspace               Write a Cargo.toml into --out-dir or --prefix that
+                              makes every package cloned there one workspace
+    --exclude-broken          With --workspace, leave out packages whose
+                              manifest cargo cannot load
+    --link-local              Point the dependencies of packages cloned into the
+                              same --out-dir or --prefix at each other by path
+    --vendor-layout           Clone into <name>-<version>/ under vendor/ or the
+                              prefix, with a .cargo-checksum.json, like
+                              `cargo vendor`, for use as a directory source
+    --into-store              Clone into <name>/<version>/ in a shared store,
+                              reusing what is already there, and print the
+                              path of each
+    --store DIR               The store for --into-store (by default
+                              $CARGO_CLONE_STORE, or cargo-clone/store in the
+                              cargo home)
+    --in-place                With update, replace outdated clones with the
+                              newest version
+    --alongside               With update, clone the newest version of outdated
+                              clones into <name>-<version>/ next to them
+    --crate NAME              With clean, delete the clones of this crate (a
+                              name or glob, repeatable)
+    --older-than TIME         With clean, only delete clones made more than
+                              TIME ago, e.g. 12h, 30d
+    --all                     With clean, delete every recorded clone
+    --dry-run                 Print what would be downloaded and where it
+                              would go, or with clean what would be deleted,
+                              but write or delete nothing
+    --fork NAME               Start a new project called NAME from the crate:
+                              rename it, reset its version to 0.1.0, drop
+                              its publishing metadata, and git init it
+    -o FILE, --output FILE    Write the diff, or the bill of materials, to FILE
+    --all-files               With diff, also compare the files `cargo package`
+                              generates, such as Cargo.toml.orig
+    --provenance              Print a JSON line for each clone with its
+                              repository, the commit it was published from
+                              and whether the repository still has it, and
+                              when and by whom it was published
+    --sbom FORMAT             Write a software bill of materials of every
+                              package cloned: cyclonedx, spdx (to stdout, or
+                              to the file given with -o)
+    --license-report          Print how many of the packages cloned use each
+                              license, and which have a nonstandard license
+                              or none (as JSON with --format json)
+    --security-report         Print which of the packages cloned have a build
+                              script, are proc-macros, or include prebuilt
+                              binaries (as JSON with --format json)
+    --verify                  Check that each clone is usable: its manifest
+                              parses, `cargo metadata` resolves it offline,
+                              and it has the package's files
+    --preserve                Keep modification times and directory permissions
+    --dereference             Copy the files symlinks point to, not the links
+    -j N, --jobs N            Number of crates to clone in parallel
+    --state FILE              Record cloned crates in FILE and skip the ones
+                              already recorded there, to resume a run
 
-    --vers VERS               Specify a version to clone from crates.io
+    --vers VERS               Specify a version or semver requirement (e.g. ^1.2)
+                              (a crate may also be given as <name>@<version>)
+    --all-versions            Clone every matching version into <name>-<version>/
+    --allow-yanked            Include yanked versions when selecting a version
+    --pre                     Allow prerelease versions to be selected
+    --interactive             Choose from matching versions on the terminal
+    --rust-version VER        Skip versions that need a newer Rust than VER
+                              (`current` for the active toolchain)
+    --published-before DATE   Pick the newest version published before DATE
+                              (YYYY-MM-DD)
+    --owner NAME              Clone every crate owned by a user, or by a team
+                              given as github:<org>:<team>
+    --keyword NAME            Clone every crate with this keyword
+    --category SLUG           Clone every crate in this category
+    --top-downloads N         Clone the N most downloaded crates (of those
+                              matching --owner, --keyword, and --category)
+    --top N                   Clone only the top N of the crates matching
+                              the --owner, --keyword, and --category given,
+                              as ranked by --top-by
+    --top-by KEY              Rank crates for --top by downloads (default), or
+                              by when they were last updated (updated)
+    --min-downloads N         Skip crates downloaded fewer than N times (of
+                              those matching --owner, --keyword, --category,
+                              and --top-downloads)
+    --from-file FILE          Clone the crates listed in FILE, one name or
+                              name@version per line (`-` for stdin)
+    --lockfile FILE           Clone the exact version of every registry package
+                              pinned in a Cargo.lock, into <name>-<version>/
+    --locked-dep SPEC         Clone a dependency at the version the current
+                              workspace's Cargo.lock pins it to
+    -r, --recursive           Also clone every crate the selected crates depend
+                              on, into <name>-<version>/
+    --deps                    Same as --recursive
+    --deps-depth N            Like --recursive, but only N levels of
+                              dependencies down (1 for direct dependencies)
+    --sample N                Clone N crates picked at random from the index
+    --seed S                  Seed for --sample, to pick the same crates again
+    --list-versions           List available versions instead of cloning
+    --format FMT              Output format for list, --list-versions, and the
+                              license and security reports: human, json
+    --message-format FMT      Report progress as human-readable status lines
+                              (human), or also as JSON events on stdout (json)
 
     --git URL                 Git URL to clone the specified crate from
     --branch BRANCH           Branch to use when cloning from git
     --tag TAG                 Tag to use when cloning from git
     --rev SHA                 Specific commit to use when cloning from git
+    --keep-git                Clone the whole repository with its history,
+                              instead of copying the package's files
+    --shallow                 Fetch only the selected commit, not the history
+                              (needs the `git` command)
+    --no-submodules           Leave out the repository's git submodules
+    --from-repo               Clone the git repository a registry crate links to
+                              instead of its published files
+    --vcs-commit              Like --from-repo, but check out the commit the
+                              version was published from
+    --diff-upstream           Like --from-repo, but check out the release's tag
+                              and commit the published files on top of it
 
     --path PATH               Filesystem path to local crate to clone
+    -p NAME, --package NAME   Workspace member to clone from --git or --path,
+                              or else a crate to clone from the registry
 
-    --alt-registry NAME       A registry name from Cargo config to clone the specified crate from
+    --registry NAME           A registry name from Cargo config to clone the specified crate from
+    --alt-registry NAME       Same as --registry
 
-    --registry-url URL        A registry url to clone the specified crate from
+    --index URL               A registry index url to clone the specified crate from
+    --registry-url URL        Same as --index
 
     --local-registry PATH     A local registry path to clone the specified crate from
+    --vendor-dir PATH         A `cargo vendor` directory to clone the specified
+                              crate from, checked against its checksums
+    --crate-file FILE         Clone the package in a .crate file, without
+                              consulting any registry
+    --crate-url URL           Download a .crate file and clone the package in
+                              it, checked against the registry's index if it
+                              is one of the registry's downloads
+
+    --protocol PROTO          Protocol for reading the crates.io or --index
+                              registry index: git, sparse (crates.io defaults
+                              to sparse, or `registries.crates-io.protocol`)
+    --api URL                 Web API to look up crates and versions with, for
+                              mirrors that cannot reach the registry's own
+    --api-cache DIR           Keep web API responses in DIR, and reuse them
+                              without a request until they expire
+    --api-cache-ttl TIME      How long --api-cache responses are reused, e.g.
+                              90s, 30m, 12h, 7d (one day by default)
+
+    --check-typos             Warn about a crate whose name looks like a
+                              misspelling of a much more popular one, and ask
+                              whether to clone it
+    -y, --yes                 Clone a crate --check-typos warns about, or a
+                              batch of 100 crates or more, or with clean
+                              delete clones, without asking
+    --max-total-size SIZE     Estimate what the crates to clone download, and
+                              clone them without asking only if it is at most
+                              SIZE, e.g. 500M, 2G
+    --show-info               Show the owners, newest version and its publish
+                              date, and downloads of each crate named before
+                              cloning it
+    --confirm                 Show the same, and ask before cloning anything
 
     -h, --help                Print this message
     -V, --version             Print version information
-    -v, --verbose             Use verbose output
-    -q, --quiet               Less output printed to stdout
+    -v, --verbose             Use verbose output, such as where each package
+                              comes from and each file copied
+    -q, --quiet               Print only errors, prompts, and requested data
     --color WHEN              Coloring: auto, always, never
+    --offline                 Clone from cargo's local cache and indexes only,
+                              without accessing the network
+    --proxy URL               HTTP proxy for every request, overriding
+                              `http.proxy` and the HTTPS_PROXY variables
+    --retries N               How often to retry a failed download or request,
+                              with backoff (overrides `net.retry`)
+    --limit-rate RATE         Download no faster than RATE bytes per second
+                              per transfer, e.g. 500K, 2M
 ";
 
 fn main() {
@@ -83,8 +386,81 @@ fn main() {
     let mut config = Config::default().expect("Unable to get config.");
 
     if let Err(e) = execute(options, &mut config) {
-        config.shell().error(e).unwrap();
-        std::process::exit(101);
+        cargo::handle_error(&e, &mut config.shell());
+        std::process::exit(cargo_clone::ops::exit_code(&e));
+    }
+}
+
+/// Has git fetches go through cargo's HTTP handle, as cargo itself does, when
+/// cargo's config sets a proxy, certificates, or timeouts that libgit2's own
+/// transport would ignore, or when --limit-rate is given.
+fn init_git_transports(config: &Config) {
+    let rate = cargo_clone::ops::rate_limit();
+    let custom = matches!(cargo::ops::needs_custom_http_transport(config), Ok(true));
+    if !custom && rate.is_none() {
+        return;
+    }
+    let mut handle = match cargo::ops::http_handle(config) {
+        Ok(handle) => handle,
+        Err(..) => return,
+    };
+    if let Some(rate) = rate {
+        if handle.max_recv_speed(rate).is_err() {
+            return;
+        }
+    }
+    // Registering is only safe before any transport is made, which holds
+    // this early, and leaks the handle, which lives as long as the process.
+    unsafe {
+        git2_curl::register(handle);
+    }
+}
+
+/// Parses the number of bytes given to `flag`, such as `2M`, with an
+/// optional K, M, or G suffix for powers of 1024.
+fn parse_size(flag: &str, size: &str) -> Result<u64> {
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let scale = match unit {
+        "" => 1,
+        "k" | "K" => 1 << 10,
+        "m" | "M" => 1 << 20,
+        "g" | "G" => 1 << 30,
+        _ => bail!("invalid {} '{}', expected e.g. 500K, 2M", flag, size),
+    };
+    let n = match number.parse::<u64>() {
+        Ok(n) if n > 0 => n,
+        _ => bail!("invalid {} '{}', expected e.g. 500K, 2M", flag, size),
+    };
+    match n.checked_mul(scale) {
+        Some(size) => Ok(size),
+        None => bail!("{} '{}' is out of range", flag, size),
+    }
+}
+
+/// Parses the duration given to `flag`, such as `90`, `90s`, `30m`, `12h`,
+/// or `7d`.
+fn parse_duration(flag: &str, ttl: &str) -> Result<Duration> {
+    let (number, unit) = match ttl.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => ttl.split_at(i),
+        None => (ttl, "s"),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("invalid {} '{}', expected e.g. 30m, 12h, 7d", flag, ttl),
+    };
+    let n = match number.parse::<u64>() {
+        Ok(n) => n,
+        Err(_) => bail!("invalid {} '{}', expected e.g. 30m, 12h, 7d", flag, ttl),
+    };
+    match n.checked_mul(secs) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => bail!("{} '{}' is out of range", flag, ttl),
     }
 }
 
@@ -98,7 +474,7 @@ fn version() -> String {
     )
 }
 
-pub fn execute(options: Options, config: &mut Config) -> Result<Option<()>> {
+pub fn execute(mut options: Options, config: &mut Config) -> Result<Option<()>> {
     let verbose = match options.flag_verbose {
         Some(v) => {
             if v {
@@ -109,56 +485,1077 @@ pub fn execute(options: Options, config: &mut Config) -> Result<Option<()>> {
         }
         None => 0,
     };
+    // Cargo reads these settings from its config, which the environment
+    // overrides.
+    let mut overrides = Vec::new();
+    if let Some(proxy) = options.flag_proxy.as_ref() {
+        overrides.push(("CARGO_HTTP_PROXY", proxy.clone()));
+    }
+    if let Some(retries) = options.flag_retries {
+        overrides.push(("CARGO_NET_RETRY", retries.to_string()));
+    }
+    if !overrides.is_empty() {
+        let mut env: HashMap<String, String> = std::env::vars().collect();
+        for (key, value) in overrides {
+            env.insert(key.to_string(), value);
+        }
+        config.set_env(env);
+    }
     config.configure(
         verbose,
         options.flag_quiet,
         &options.flag_color,
         false,
         false,
-        false,
+        options.flag_offline,
         &None,
         &[],
     )?;
+    cargo_clone::ops::handle_interrupts(config);
+    if let Some(rate) = options.flag_limit_rate.as_deref() {
+        cargo_clone::ops::limit_rate(parse_size("--limit-rate", rate)?);
+    }
+    init_git_transports(config);
+
+    let defaults = cargo_clone::ops::load_defaults(config)?;
+    options.flag_jobs = options.flag_jobs.or(defaults.jobs);
+    options.flag_api = options.flag_api.take().or_else(|| defaults.api.clone());
+
+    if let Some(dir) = options.flag_api_cache.as_deref() {
+        let ttl = options.flag_api_cache_ttl.as_deref().unwrap_or("1d");
+        let ttl = parse_duration("--api-cache-ttl", ttl)?;
+        cargo_clone::ops::set_api_cache(&config.cwd().join(dir), ttl);
+    } else if options.flag_api_cache_ttl.is_some() {
+        bail!("--api-cache-ttl can only be used with --api-cache");
+    }
+
+    if options.flag_message_format.is_some()
+        && (options.cmd_list
+            || options.cmd_update
+            || options.cmd_clean
+            || options.cmd_diff
+            || options.cmd_verify_source
+            || options.flag_list_versions)
+    {
+        bail!("--message-format can only be used when cloning");
+    }
+
+    if options.cmd_list {
+        let json = match options.flag_format.as_deref() {
+            None | Some("human") => false,
+            Some("json") => true,
+            Some(other) => bail!("unknown format '{}', expected 'human' or 'json'", other),
+        };
+        let specs: Vec<&str> = options.arg_crate.iter().map(String::as_str).collect();
+        cargo_clone::ops::list_clones(&specs, options.flag_vers.as_deref(), json, config)?;
+        return Ok(None);
+    }
+
+    if (!options.flag_crate.is_empty() || options.flag_older_than.is_some()) && !options.cmd_clean {
+        bail!("--crate and --older-than can only be used with clean");
+    }
+    if options.flag_dry_run && (options.cmd_update || options.cmd_diff || options.cmd_verify_source)
+    {
+        bail!("--dry-run can only be used with clean, or when cloning");
+    }
+    if options.flag_all && !options.cmd_clean {
+        bail!("--all can only be used with clean");
+    }
+    if options.cmd_clean {
+        let mut specs: Vec<&str> = options.flag_crate.iter().map(String::as_str).collect();
+        specs.extend(options.arg_crate.iter().map(String::as_str));
+        let older_than = match options.flag_older_than.as_deref() {
+            Some(age) => Some(parse_duration("--older-than", age)?),
+            None => None,
+        };
+        if specs.is_empty()
+            && options.flag_vers.is_none()
+            && older_than.is_none()
+            && !options.flag_all
+        {
+            bail!(
+                "clean with no crate, --crate, --vers, or --older-than would delete \
+                 every recorded clone; pass --all to do that"
+            );
+        }
+        cargo_clone::ops::clean_clones(
+            &specs,
+            options.flag_vers.as_deref(),
+            older_than,
+            options.flag_dry_run,
+            options.flag_yes,
+            config,
+        )?;
+        return Ok(None);
+    }
+    if (options.flag_in_place || options.flag_alongside) && !options.cmd_update {
+        bail!("--in-place and --alongside can only be used with update");
+    }
+    if options.cmd_update {
+        let reclone = match (options.flag_in_place, options.flag_alongside) {
+            (false, false) => None,
+            (true, false) => Some(cargo_clone::ops::Reclone::InPlace),
+            (false, true) => Some(cargo_clone::ops::Reclone::Alongside),
+            (true, true) => bail!("only one of --in-place and --alongside can be used"),
+        };
+        let specs: Vec<&str> = options.arg_crate.iter().map(String::as_str).collect();
+        cargo_clone::ops::update_clones(
+            &specs,
+            options.flag_vers.as_deref(),
+            options.flag_pre,
+            reclone,
+            config,
+        )?;
+        return Ok(None);
+    }
 
+    let git_refs = [&options.flag_branch, &options.flag_tag, &options.flag_rev];
+    let git_refs = git_refs.iter().filter(|r| r.is_some()).count();
+    if git_refs > 1 {
+        bail!("only one of --branch, --tag, and --rev can be used");
+    }
+    if git_refs > 0 && options.flag_git.is_none() {
+        bail!("--branch, --tag, and --rev can only be used with --git");
+    }
+
+    if options.flag_crate_file.is_some()
+        && (!options.arg_crate.is_empty()
+            || options.flag_package.is_some()
+            || options.flag_git.is_some()
+            || options.flag_path.is_some()
+            || options.flag_registry.is_some()
+            || options.flag_alt_registry.is_some()
+            || options.flag_index.is_some()
+            || options.flag_registry_url.is_some()
+            || options.flag_local_registry.is_some()
+            || options.flag_vendor_dir.is_some()
+            || options.flag_protocol.is_some())
+    {
+        bail!("--crate-file cannot be used with crate names or another source");
+    }
+    if options.flag_crate_url.is_some()
+        && (options.flag_crate_file.is_some()
+            || !options.arg_crate.is_empty()
+            || options.flag_package.is_some()
+            || options.flag_git.is_some()
+            || options.flag_path.is_some()
+            || options.flag_vendor_dir.is_some())
+    {
+        bail!("--crate-url cannot be used with crate names, --crate-file, --git, or --path");
+    }
+
+    // Crates may be given as package ID specs or URLs, which can name their
+    // source too. crates.io is the default anyway.
+    let mut crate_args = Vec::new();
+    let mut spec_source = None;
+    for arg in &options.arg_crate {
+        let (spec, source) = cargo_clone::ops::parse_crate_arg(arg)?;
+        let source = source.filter(|id| {
+            !id.is_default_registry()
+                && id.url().as_str() != cargo_clone::ops::CRATES_IO_SPARSE_INDEX
+        });
+        if let Some(source) = source {
+            if spec_source.is_some_and(|other| other != source) {
+                bail!("crates from different sources cannot be cloned at once");
+            }
+            spec_source = Some(source);
+        }
+        crate_args.push(spec);
+    }
+    if spec_source.is_some()
+        && (options.flag_git.is_some()
+            || options.flag_path.is_some()
+            || options.flag_registry.is_some()
+            || options.flag_alt_registry.is_some()
+            || options.flag_index.is_some()
+            || options.flag_registry_url.is_some()
+            || options.flag_local_registry.is_some()
+            || options.flag_vendor_dir.is_some()
+            || options.flag_protocol.is_some())
+    {
+        bail!("a package ID spec with a source cannot be used with another source");
+    }
+
+    let protocol = match options.flag_protocol.as_deref() {
+        None => None,
+        Some(protocol @ "git") | Some(protocol @ "sparse") => Some(protocol),
+        Some(other) => bail!("invalid protocol '{}', expected git or sparse", other),
+    };
+    if protocol.is_some()
+        && (options.flag_git.is_some()
+            || options.flag_path.is_some()
+            || options.flag_registry.is_some()
+            || options.flag_alt_registry.is_some()
+            || options.flag_local_registry.is_some()
+            || options.flag_vendor_dir.is_some())
+    {
+        bail!("--protocol can only be used with crates.io or --index");
+    }
+
+    if options.flag_shallow {
+        if options.flag_git.is_none() {
+            bail!("--shallow can only be used with --git");
+        }
+        if options.flag_keep_git {
+            bail!("--shallow cannot be used with --keep-git");
+        }
+    }
+
+    if options.flag_exclude_broken && !options.flag_workspace {
+        bail!("--exclude-broken can only be used with --workspace");
+    }
+    if (options.flag_workspace || options.flag_link_local)
+        && (options.flag_add_member
+            || options.flag_archive.is_some()
+            || options.flag_stdout
+            || options.flag_crate_file.is_some()
+            || options.flag_crate_url.is_some())
+    {
+        bail!(
+            "--workspace and --link-local cannot be used with --add-member, \
+             --archive, --stdout, --crate-file, or --crate-url"
+        );
+    }
+    if (options.flag_patch || options.flag_add_member)
+        && (options.flag_archive.is_some() || options.flag_stdout)
+    {
+        bail!("--patch and --add-member cannot be used with --archive or --stdout");
+    }
+    // The key is taken from the source as given, as a shallow checkout or a
+    // source replacement is read from somewhere else.
+    let patch_key = match &options.flag_git {
+        _ if !options.flag_patch => None,
+        Some(url) => Some(url.as_str().into_url()?.to_string()),
+        None => options
+            .flag_registry
+            .as_ref()
+            .or(options.flag_alt_registry.as_ref())
+            .cloned(),
+    };
+
+    // A shallow checkout is cloned from as a path, and removed at the end.
+    let mut _checkout = None;
     let source_id = if let Some(url) = options.flag_git {
         let url = url.into_url()?;
         let gitref = if let Some(rev) = options.flag_rev {
-            GitReference::Rev(rev)
+            Some(GitReference::Rev(rev))
         } else if let Some(tag) = options.flag_tag {
-            GitReference::Tag(tag)
-        } else if let Some(branch) = options.flag_branch {
-            GitReference::Branch(branch)
+            Some(GitReference::Tag(tag))
         } else {
-            GitReference::Branch("master".to_string())
+            options.flag_branch.map(GitReference::Branch)
         };
-        SourceId::for_git(&url, gitref)?
+        if options.flag_shallow {
+            let checkout = cargo_clone::ops::shallow_checkout(
+                url.as_str(),
+                gitref.as_ref(),
+                !options.flag_no_submodules,
+                config,
+            )?;
+            let id = SourceId::for_path(checkout.path())?;
+            _checkout = Some(checkout);
+            id
+        } else {
+            let gitref = gitref.unwrap_or_else(|| GitReference::Branch("master".to_string()));
+            SourceId::for_git(&url, gitref)?
+        }
     } else if let Some(path) = options.flag_path {
         SourceId::for_path(&config.cwd().join(path))?
-    } else if let Some(registry) = options.flag_alt_registry.as_ref() {
+    } else if let Some(registry) = options
+        .flag_registry
+        .as_ref()
+        .or(options.flag_alt_registry.as_ref())
+    {
         SourceId::alt_registry(config, registry)?
-    } else if let Some(url) = options.flag_registry_url.as_ref() {
-        let url = url.into_url()?;
-        SourceId::for_registry(&url)?
+    } else if let Some(url) = options
+        .flag_index
+        .as_ref()
+        .or(options.flag_registry_url.as_ref())
+    {
+        let url = match protocol {
+            Some("sparse") if !url.starts_with("sparse+") => format!("sparse+{}", url),
+            Some("git") if url.starts_with("sparse+") => {
+                bail!("--protocol git cannot be used with a sparse+ index URL")
+            }
+            _ => url.clone(),
+        };
+        SourceId::for_registry(&url.into_url()?)?
     } else if let Some(path) = options.flag_local_registry.as_ref() {
         SourceId::for_local_registry(&config.cwd().join(path))?
-    } else if options.arg_crate.len() == 0 {
+    } else if let Some(path) = options.flag_vendor_dir.as_ref() {
+        SourceId::for_directory(&config.cwd().join(path))?
+    } else if let Some(source) = spec_source {
+        source
+    } else if options.arg_crate.is_empty()
+        && options.flag_package.is_none()
+        && options.flag_owner.is_none()
+        && options.flag_keyword.is_none()
+        && options.flag_category.is_none()
+        && options.flag_top_downloads.is_none()
+        && options.flag_sample.is_none()
+        && options.flag_from_file.is_none()
+        && options.flag_lockfile.is_none()
+        && options.flag_locked_dep.is_none()
+        && options.flag_crate_file.is_none()
+        && options.flag_crate_url.is_none()
+    {
         bail!(
             "must specify a crate to clone from \
              crates.io, or use --path or --git to \
              specify alternate source"
         );
     } else {
-        SourceId::crates_io(config)?
+        cargo_clone::ops::crates_io(protocol, config)?
     };
 
-    let prefix = options.flag_prefix.as_ref().map(|s| &s[..]);
-    let vers = options.flag_vers.as_ref().map(|s| &s[..]);
-    if options.arg_crate.len() != 0 {
-        for item in options.arg_crate.iter() {
-            cargo_clone::ops::clone(Some(&item[..]), &source_id, prefix, vers, config)?;
+    // Only names given by hand are checked for typos, not those of whole
+    // lockfiles or dependency trees.
+    let typed = crate_args.clone();
+    let typed: Vec<&str> = typed.iter().map(String::as_str).collect();
+    if options.flag_check_typos && !options.flag_list_versions {
+        let opts = cargo_clone::ops::CloneOpts {
+            api: options.flag_api.as_deref(),
+            ..Default::default()
+        };
+        cargo_clone::ops::check_typosquats(&typed, &source_id, &opts, options.flag_yes, config)?;
+    }
+    let mut names = crate_args;
+    names.extend(options.flag_package.clone());
+    if let Some(file) = options.flag_from_file.as_deref() {
+        names.extend(read_spec_file(file, config)?);
+    }
+    if let Some(spec) = options.flag_locked_dep.as_deref() {
+        names.push(cargo_clone::ops::locked_dep(spec, &source_id, config)?);
+    }
+    if let Some(file) = options.flag_lockfile.as_deref() {
+        let path = config.cwd().join(file);
+        names.extend(cargo_clone::ops::lockfile_crates(
+            &path, &source_id, config,
+        )?);
+    }
+    let mut query = cargo_clone::ops::CrateQuery {
+        owner: options.flag_owner.as_deref(),
+        keyword: options.flag_keyword.as_deref(),
+        category: options.flag_category.as_deref(),
+        top_downloads: options.flag_top_downloads,
+        top_updated: None,
+        min_downloads: options.flag_min_downloads,
+        api: options.flag_api.as_deref(),
+    };
+    if query.top_downloads == Some(0) {
+        bail!("--top-downloads must be at least 1");
+    }
+    if let Some(n) = options.flag_top {
+        if n == 0 {
+            bail!("--top must be at least 1");
         }
-    } else {
-        cargo_clone::ops::clone(None, &source_id, prefix, vers, config)?;
+        if query.owner.is_none() && query.keyword.is_none() && query.category.is_none() {
+            bail!("--top can only be used with --owner, --keyword, or --category");
+        }
+        if query.top_downloads.is_some() {
+            bail!("only one of --top and --top-downloads can be used");
+        }
+        match options.flag_top_by.as_deref() {
+            None | Some("downloads") => query.top_downloads = Some(n),
+            Some("updated") => query.top_updated = Some(n),
+            Some(other) => bail!(
+                "invalid --top-by '{}', expected downloads or updated",
+                other
+            ),
+        }
+    } else if options.flag_top_by.is_some() {
+        bail!("--top-by can only be used with --top");
     }
+    if query.owner.is_some()
+        || query.keyword.is_some()
+        || query.category.is_some()
+        || query.top_downloads.is_some()
+    {
+        names.extend(cargo_clone::ops::find_crates(&query, &source_id, config)?);
+    } else if query.min_downloads.is_some() {
+        bail!("--min-downloads can only be used with --owner, --keyword, --category, or --top-downloads");
+    }
+    if let Some(n) = options.flag_sample {
+        names.extend(cargo_clone::ops::sample_crates(
+            n,
+            options.flag_seed,
+            &source_id,
+            config,
+        )?);
+    } else if options.flag_seed.is_some() {
+        bail!("--seed can only be used with --sample");
+    }
+    if options.flag_deps_depth == Some(0) {
+        bail!("--deps-depth must be at least 1");
+    }
+    let recursive =
+        options.flag_recursive || options.flag_deps || options.flag_deps_depth.is_some();
+    if recursive {
+        let roots: Vec<&str> = names.iter().map(String::as_str).collect();
+        let roots_opts = cargo_clone::ops::CloneOpts {
+            vers: options.flag_vers.as_deref(),
+            all_versions: options.flag_all_versions,
+            allow_yanked: options.flag_allow_yanked,
+            pre: options.flag_pre,
+            ..Default::default()
+        };
+        names = cargo_clone::ops::dependency_closure(
+            &roots,
+            &source_id,
+            options.flag_deps_depth,
+            &roots_opts,
+            config,
+        )?;
+    }
+    let krates: Vec<&str> = names.iter().map(String::as_str).collect();
+    let patch_key = match patch_key {
+        None if options.flag_patch => Some(cargo_clone::ops::patch_key(&source_id)?),
+        key => key,
+    };
+
+    if options.cmd_diff {
+        let (old, new) = match (&options.arg_old, &options.arg_new) {
+            (Some(old), Some(new)) => (old.as_str(), new.as_str()),
+            _ => bail!("diff needs a crate and two versions"),
+        };
+        let name = match &krates[..] {
+            [name] => *name,
+            _ => bail!("diff compares versions of a single crate"),
+        };
+        let mut exclude = options.flag_exclude.clone();
+        if !options.flag_all_files {
+            exclude.extend(
+                cargo_clone::ops::GENERATED_FILES
+                    .iter()
+                    .map(|file| file.to_string()),
+            );
+        }
+        let opts = cargo_clone::ops::CloneOpts {
+            include: &options.flag_include,
+            exclude: &exclude,
+            ..Default::default()
+        };
+        config.shell().status(
+            "Comparing",
+            format!(
+                "{} v{} to v{}",
+                name,
+                old.trim_start_matches('='),
+                new.trim_start_matches('=')
+            ),
+        )?;
+        let differ = match options.flag_output.as_deref() {
+            Some(file) => {
+                let path = config.cwd().join(file);
+                let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                let differ = cargo_clone::ops::diff_versions(
+                    name, old, new, &source_id, &opts, &mut out, config,
+                )?;
+                out.flush()?;
+                differ
+            }
+            None => {
+                let stdout = std::io::stdout();
+                let mut out = stdout.lock();
+                cargo_clone::ops::diff_versions(
+                    name, old, new, &source_id, &opts, &mut out, config,
+                )?
+            }
+        };
+        if !differ {
+            config
+                .shell()
+                .status("Fresh", "the versions have the same files")?;
+        }
+        return Ok(None);
+    }
+    if options.cmd_verify_source {
+        let krate = match &krates[..] {
+            [krate] => *krate,
+            _ => bail!("verify-source checks a single crate"),
+        };
+        let opts = cargo_clone::ops::CloneOpts {
+            vers: options.flag_vers.as_deref(),
+            allow_yanked: options.flag_allow_yanked,
+            pre: options.flag_pre,
+            ..Default::default()
+        };
+        cargo_clone::ops::verify_source(krate, &source_id, &opts, config)?;
+        return Ok(None);
+    }
+    if options.flag_all_files {
+        bail!("--all-files can only be used with diff");
+    }
+    if options.flag_output.is_some() && options.flag_sbom.is_none() {
+        bail!("--output can only be used with diff or --sbom");
+    }
+    let sbom: Option<cargo_clone::ops::SbomFormat> = match options.flag_sbom.as_deref() {
+        Some(format) => Some(format.parse()?),
+        None => None,
+    };
+    if sbom.is_some()
+        && options.flag_output.is_none()
+        && (options.flag_license_report || options.flag_security_report)
+    {
+        bail!("--sbom needs --output to be used with --license-report or --security-report");
+    }
+
+    if options.flag_list_versions {
+        let json = match options.flag_format.as_deref() {
+            None | Some("human") => false,
+            Some("json") => true,
+            Some(other) => bail!("unknown format '{}', expected 'human' or 'json'", other),
+        };
+        if krates.is_empty() {
+            bail!("--list-versions requires at least one crate name");
+        }
+        cargo_clone::ops::list_versions(
+            &krates,
+            &source_id,
+            json,
+            options.flag_api.as_deref(),
+            config,
+        )?;
+        return Ok(None);
+    }
+
+    let rust_version = match options.flag_rust_version.as_deref() {
+        Some("current") => Some(active_rust_version(config)?),
+        Some(vers) => Some(cargo_clone::ops::parse_rust_version(vers)?),
+        None => None,
+    };
+
+    if let Some(date) = options.flag_published_before.as_deref() {
+        let valid = date.len() == 10
+            && date.char_indices().all(|(i, c)| match i {
+                4 | 7 => c == '-',
+                _ => c.is_ascii_digit(),
+            });
+        if !valid {
+            bail!("invalid date '{}', expected YYYY-MM-DD", date);
+        }
+    }
+
+    if options.flag_prefix.is_some() && options.flag_out_dir.is_some() {
+        bail!("--prefix and --out-dir cannot be used together");
+    }
+
+    if options.flag_stdout {
+        if options.flag_provenance
+            || sbom.is_some()
+            || options.flag_license_report
+            || options.flag_security_report
+        {
+            bail!(
+                "--stdout cannot be used with --provenance, --sbom, --license-report, \
+                 or --security-report"
+            );
+        }
+        if krates.len() > 1 || options.flag_all_versions {
+            bail!("--stdout can only write a single package");
+        }
+        if options.flag_archive.is_some() || options.flag_print_path {
+            bail!("--stdout cannot be used with --archive or --print-path");
+        }
+        if options.flag_keep_archive || options.flag_keep_archive_in.is_some() {
+            bail!("--stdout cannot be used with --keep-archive");
+        }
+    }
+
+    let json_messages = match options.flag_message_format.as_deref() {
+        None | Some("human") => false,
+        Some("json") => true,
+        Some(other) => bail!(
+            "unknown message format '{}', expected 'human' or 'json'",
+            other
+        ),
+    };
+    // Nothing else may be printed on stdout between the events.
+    if json_messages
+        && (options.flag_stdout
+            || options.flag_print_path
+            || options.flag_into_store
+            || options.flag_provenance
+            || options.flag_license_report
+            || options.flag_security_report
+            || (sbom.is_some() && options.flag_output.is_none()))
+    {
+        bail!(
+            "--message-format json cannot be used with --stdout, --print-path, --into-store, \
+             --provenance, --license-report, --security-report, or --sbom without --output"
+        );
+    }
+    if json_messages {
+        cargo_clone::ops::enable_json_messages();
+    }
+
+    if options.flag_dry_run
+        && (options.flag_stdout
+            || options.flag_crate_url.is_some()
+            || options.flag_provenance
+            || sbom.is_some()
+            || options.flag_license_report
+            || options.flag_security_report
+            || options.flag_verify)
+    {
+        bail!(
+            "--dry-run cannot be used with --stdout, --crate-url, --provenance, --sbom, \
+             --license-report, --security-report, or --verify"
+        );
+    }
+
+    if options.flag_keep_git {
+        if !source_id.is_git() {
+            bail!("--keep-git can only be used with --git");
+        }
+        if options.flag_archive.is_some() || options.flag_stdout {
+            bail!("--keep-git cannot be used with --archive or --stdout");
+        }
+        if !options.flag_include.is_empty() || !options.flag_exclude.is_empty() {
+            bail!("--keep-git cannot be used with --include or --exclude");
+        }
+    }
+
+    let from_repo = options.flag_from_repo || options.flag_vcs_commit || options.flag_diff_upstream;
+    if from_repo && options.flag_as_submodule {
+        bail!("--as-submodule cannot be used with --from-repo");
+    }
+    if from_repo || options.flag_as_submodule {
+        if source_id.is_git() || source_id.is_path() {
+            bail!("--from-repo and --as-submodule can only be used with a registry");
+        }
+        if options.flag_archive.is_some() || options.flag_stdout || options.flag_keep_git {
+            bail!(
+                "--from-repo and --as-submodule cannot be used with --archive, \
+                 --stdout, or --keep-git"
+            );
+        }
+        if !options.flag_include.is_empty() || !options.flag_exclude.is_empty() {
+            bail!("--from-repo and --as-submodule cannot be used with --include or --exclude");
+        }
+    }
+
+    if options.flag_git_init
+        && (options.flag_archive.is_some()
+            || options.flag_stdout
+            || options.flag_keep_git
+            || from_repo
+            || options.flag_as_submodule)
+    {
+        bail!(
+            "--git-init cannot be used with --archive, --stdout, --keep-git, \
+             --from-repo, or --as-submodule"
+        );
+    }
+
+    if options.flag_vendor_layout
+        && (options.flag_archive.is_some()
+            || options.flag_stdout
+            || options.flag_keep_git
+            || from_repo
+            || options.flag_as_submodule
+            || options.flag_git_init
+            || options.flag_dir_format.is_some())
+    {
+        bail!(
+            "--vendor-layout cannot be used with --archive, --stdout, --keep-git, \
+             --from-repo, --as-submodule, --git-init, or --dir-format"
+        );
+    }
+
+    let store = match &options.flag_store {
+        _ if !options.flag_into_store => None,
+        Some(dir) => Some(config.cwd().join(dir)),
+        // `$CARGO_CLONE_STORE` sets `clone.store` in cargo's configuration.
+        None => match &defaults.store {
+            Some(dir) => Some(config.cwd().join(dir)),
+            None => Some(
+                config
+                    .home()
+                    .join("cargo-clone")
+                    .join("store")
+                    .into_path_unlocked(),
+            ),
+        },
+    };
+    let store = match store.map(|dir| dir.into_os_string().into_string()) {
+        Some(Ok(dir)) => Some(dir),
+        Some(Err(dir)) => bail!("store path '{}' is not valid UTF-8", dir.to_string_lossy()),
+        None => None,
+    };
+    if options.flag_store.is_some() && !options.flag_into_store {
+        bail!("--store can only be used with --into-store");
+    }
+    if options.flag_into_store
+        && (options.flag_prefix.is_some()
+            || options.flag_out_dir.is_some()
+            || options.flag_dir_format.is_some()
+            || options.flag_on_exists.is_some()
+            || options.flag_force
+            || options.flag_archive.is_some()
+            || options.flag_stdout
+            || options.flag_keep_git
+            || from_repo
+            || options.flag_as_submodule
+            || options.flag_vendor_layout
+            || options.flag_workspace
+            || options.flag_link_local)
+    {
+        bail!(
+            "--into-store cannot be used with --prefix, --out-dir, --dir-format, \
+             --on-exists, --force, --archive, --stdout, --keep-git, --from-repo, \
+             --as-submodule, --vendor-layout, --workspace, or --link-local"
+        );
+    }
+
+    if let Some(name) = options.flag_fork.as_deref() {
+        cargo::util::validate_package_name(name, "the name of the fork", "")?;
+        if krates.len() > 1 || options.flag_all_versions {
+            bail!("--fork can only clone a single crate");
+        }
+        if options.flag_archive.is_some()
+            || options.flag_stdout
+            || options.flag_keep_git
+            || from_repo
+            || options.flag_as_submodule
+            || options.flag_vendor_layout
+            || options.flag_into_store
+            || options.flag_patch
+        {
+            bail!(
+                "--fork cannot be used with --archive, --stdout, --keep-git, --from-repo, \
+                 --as-submodule, --vendor-layout, --into-store, or --patch"
+            );
+        }
+    }
+
+    if options.flag_jobs == Some(0) {
+        bail!("--jobs must be at least 1");
+    }
+
+    let on_exists = match (
+        options.flag_on_exists.as_deref(),
+        options.flag_force,
+        options.flag_skip_existing,
+    ) {
+        (None, false, false) => cargo_clone::ops::OnExists::Error,
+        (Some(policy), false, false) => policy.parse()?,
+        (None, true, false) => cargo_clone::ops::OnExists::Overwrite,
+        (None, false, true) => cargo_clone::ops::OnExists::Skip,
+        _ => bail!("only one of --on-exists, --force, and --skip-existing can be used"),
+    };
+
+    let report_json = match options.flag_format.as_deref() {
+        None | Some("human") => false,
+        Some("json") => true,
+        Some(other) => bail!("unknown format '{}', expected 'human' or 'json'", other),
+    };
+    let reports = Reports {
+        sbom,
+        output: options.flag_output.as_deref(),
+        json: report_json,
+        license: options.flag_license_report,
+        security: options.flag_security_report,
+        verify: options.flag_verify,
+    };
+
+    if options.flag_show_info || options.flag_confirm {
+        let opts = cargo_clone::ops::CloneOpts {
+            api: options.flag_api.as_deref(),
+            ..Default::default()
+        };
+        cargo_clone::ops::show_info(&typed, &source_id, &opts, config)?;
+    }
+    if options.flag_confirm {
+        let question = match &krates[..] {
+            [krate] => format!("Clone `{}`?", krate),
+            [] => "Clone it?".to_string(),
+            krates => format!("Clone these {} crates?", krates.len()),
+        };
+        if !cargo_clone::ops::confirm(&question, config)? {
+            bail!("nothing was cloned");
+        }
+    }
+
+    // Archives are kept by default only where they can be.
+    let keep_archive_in = match &options.flag_keep_archive_in {
+        Some(dir) => Some(dir.clone()),
+        None if options.flag_stdout => None,
+        None => match defaults
+            .keep_archive_in
+            .clone()
+            .map(PathBuf::into_os_string)
+        {
+            Some(dir) => match dir.into_string() {
+                Ok(dir) => Some(dir),
+                Err(dir) => bail!(
+                    "keep-archive-in path '{}' is not valid UTF-8",
+                    dir.to_string_lossy()
+                ),
+            },
+            None => None,
+        },
+    };
+    let keep_archive = options.flag_keep_archive
+        || keep_archive_in.is_some()
+        || defaults.keep_archive && !options.flag_stdout;
+
+    let opts = cargo_clone::ops::CloneOpts {
+        prefix: options.flag_prefix.as_deref(),
+        out_dir: options.flag_out_dir.as_deref(),
+        // A lockfile or dependency graph may hold several versions of a crate.
+        dir_format: match options.flag_dir_format.as_deref() {
+            None if options.flag_lockfile.is_some() || recursive => Some("{name}-{version}"),
+            format => format,
+        },
+        on_exists,
+        // With --recursive these picked the roots; every spec is now exact.
+        vers: if recursive {
+            None
+        } else {
+            options.flag_vers.as_deref()
+        },
+        all_versions: options.flag_all_versions,
+        // Locked versions may have been yanked since.
+        allow_yanked: options.flag_allow_yanked
+            || options.flag_lockfile.is_some()
+            || options.flag_locked_dep.is_some(),
+        pre: options.flag_pre,
+        interactive: options.flag_interactive,
+        rust_version,
+        published_before: options.flag_published_before.as_deref(),
+        print_path: options.flag_print_path || store.is_some(),
+        archive: match options.flag_archive.as_deref() {
+            Some(format) => Some(format.parse()?),
+            None => None,
+        },
+        stdout: options.flag_stdout,
+        keep_archive,
+        keep_archive_in: keep_archive_in.as_deref(),
+        include: &options.flag_include,
+        exclude: &options.flag_exclude,
+        keep_git: options.flag_keep_git,
+        skip_submodules: options.flag_no_submodules,
+        from_repo,
+        vcs_commit: options.flag_vcs_commit,
+        diff_upstream: options.flag_diff_upstream,
+        as_submodule: options.flag_as_submodule,
+        git_init: options.flag_git_init,
+        preserve: options.flag_preserve,
+        dereference: options.flag_dereference,
+        jobs: options.flag_jobs.unwrap_or(1),
+        state: options.flag_state.as_deref(),
+        api: options.flag_api.as_deref(),
+        patch: patch_key.as_deref(),
+        add_member: options.flag_add_member,
+        vendor_layout: options.flag_vendor_layout,
+        store: store.as_deref(),
+        fork: options.flag_fork.as_deref(),
+        untracked: false,
+        provenance: options.flag_provenance,
+        find_binaries: options.flag_security_report,
+        verify: options.flag_verify,
+        dry_run: options.flag_dry_run,
+    };
+    if !options.flag_dry_run {
+        let max_total_size = match options.flag_max_total_size.as_deref() {
+            Some(size) => Some(parse_size("--max-total-size", size)?),
+            None => None,
+        };
+        // --confirm has asked about the whole run already.
+        let yes = options.flag_yes || options.flag_confirm;
+        cargo_clone::ops::check_total_size(
+            &krates,
+            &source_id,
+            &opts,
+            max_total_size,
+            yes,
+            config,
+        )?;
+    }
+    if let Some(file) = options.flag_crate_file.as_deref() {
+        if !krates.is_empty() {
+            bail!("--crate-file cannot be used with crate names or another source");
+        }
+        cargo_clone::ops::clone_crate_file(&config.cwd().join(file), &opts, config)?;
+        reports.write(config)?;
+        return Ok(None);
+    }
+    if let Some(url) = options.flag_crate_url.as_deref() {
+        if !krates.is_empty() {
+            bail!("--crate-url cannot be used with crate names, --crate-file, --git, or --path");
+        }
+        cargo_clone::ops::clone_crate_url(url, &source_id, &opts, config)?;
+        reports.write(config)?;
+        return Ok(None);
+    }
+    let result = cargo_clone::ops::clone(&krates, &source_id, &opts, config);
+    // What was cloned before a failure is reported too.
+    reports.write(config)?;
+    if options.flag_dry_run {
+        result?;
+        return Ok(None);
+    }
+    // Whatever was cloned before a failure is still linked up.
+    let dir = match options
+        .flag_out_dir
+        .as_ref()
+        .or(options.flag_prefix.as_ref())
+    {
+        Some(dir) => config.cwd().join(dir),
+        None => config.cwd().to_path_buf(),
+    };
+    if options.flag_link_local {
+        cargo_clone::ops::link_local(&dir, config)?;
+    }
+    if options.flag_workspace {
+        cargo_clone::ops::write_workspace(&dir, options.flag_exclude_broken, config)?;
+    }
+    result?;
     Ok(None)
 }
+
+/// The reports to make of the packages cloned, once cloning is done.
+struct Reports<'a> {
+    sbom: Option<cargo_clone::ops::SbomFormat>,
+    /// Where to write the SBOM, rather than stdout.
+    output: Option<&'a str>,
+    /// Print the license and security reports as JSON.
+    json: bool,
+    license: bool,
+    security: bool,
+    /// Finish checking the clones for `--verify`, and sum up which are
+    /// usable.
+    verify: bool,
+}
+
+impl Reports<'_> {
+    fn write(&self, config: &Config) -> Result<()> {
+        let mut cloned = cargo_clone::ops::take_cloned();
+        if self.verify {
+            cargo_clone::ops::verify_clones(&mut cloned, config)?;
+        }
+        let stdout = std::io::stdout();
+        if let Some(format) = self.sbom {
+            match self.output {
+                Some(file) => {
+                    let path = config.cwd().join(file);
+                    let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                    cargo_clone::ops::write_sbom(format, &cloned, &mut out)?;
+                    out.flush()?;
+                    config.shell().status(
+                        "Wrote",
+                        format!("SBOM of {} packages to {}", cloned.len(), file),
+                    )?;
+                }
+                None => {
+                    cargo_clone::ops::write_sbom(format, &cloned, &mut stdout.lock())?;
+                }
+            }
+        }
+        if self.license {
+            cargo_clone::ops::license_report(&cloned, self.json, &mut stdout.lock())?;
+        }
+        if self.security {
+            cargo_clone::ops::security_report(&cloned, self.json, &mut stdout.lock())?;
+        }
+        if self.verify && cloned.len() > 1 {
+            let unusable: Vec<String> = cloned
+                .iter()
+                .filter(|c| c.problems.as_ref().is_some_and(|p| !p.is_empty()))
+                .map(|c| format!("{} v{}", c.record.name, c.record.version))
+                .collect();
+            let checked = cloned.iter().filter(|c| c.problems.is_some()).count();
+            config.shell().status(
+                "Checked",
+                format!("{} clones, {} usable", checked, checked - unusable.len()),
+            )?;
+            if !unusable.is_empty() {
+                config.shell().status("Unusable", unusable.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads crate specs from `file`, one per line. Blank lines and `#` comments
+/// are ignored.
+fn read_spec_file(file: &str, config: &Config) -> Result<Vec<String>> {
+    let contents = if file == "-" {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        let path = config.cwd().join(file);
+        std::fs::read_to_string(&path)
+            .map_err(|e| failure::format_err!("failed to read `{}`: {}", path.display(), e))?
+    };
+
+    let mut specs = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let spec = line.split('#').next().unwrap().trim();
+        if spec.is_empty() {
+            continue;
+        }
+        if let Err(e) = cargo_clone::ops::parse_spec(spec) {
+            bail!("{}:{}: {}", file, i + 1, e);
+        }
+        specs.push(spec.to_string());
+    }
+    Ok(specs)
+}
+
+/// Returns the version of the `rustc` that cargo would use.
+fn active_rust_version(config: &Config) -> Result<semver::Version> {
+    let rustc = config.load_global_rustc(None)?;
+    match rustc
+        .verbose_version
+        .lines()
+        .find(|line| line.starts_with("release: "))
+    {
+        Some(line) => cargo_clone::ops::parse_rust_version(&line["release: ".len()..]),
+        None => bail!("failed to determine the version of rustc"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_units() {
+        let parse = |size| parse_size("--limit-rate", size).unwrap();
+        assert_eq!(parse("500"), 500);
+        assert_eq!(parse("500K"), 500 * 1024);
+        assert_eq!(parse("2m"), 2 * 1024 * 1024);
+        assert_eq!(parse("2M"), 2 * 1024 * 1024);
+        assert_eq!(parse("3G"), 3 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn parse_size_errors() {
+        for size in &["", "M", "0", "0K", "2MB", "2T", "1.5G", "-1"] {
+            assert!(parse_size("--limit-rate", size).is_err(), "{}", size);
+        }
+        let err = parse_size("--limit-rate", "99999999999999G").unwrap_err();
+        assert!(err.to_string().contains("out of range"));
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        let parse = |ttl| parse_duration("--api-cache-ttl", ttl).unwrap();
+        assert_eq!(parse("90"), Duration::from_secs(90));
+        assert_eq!(parse("90s"), Duration::from_secs(90));
+        assert_eq!(parse("30m"), Duration::from_secs(30 * 60));
+        assert_eq!(parse("12h"), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(parse("7d"), Duration::from_secs(7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn parse_duration_errors() {
+        for ttl in &["", "d", "7w", "7 d", "-1d", "1.5h"] {
+            assert!(parse_duration("--api-cache-ttl", ttl).is_err(), "{}", ttl);
+        }
+        let err = parse_duration("--api-cache-ttl", "999999999999999999d").unwrap_err();
+        assert!(err.to_string().contains("out of range"));
+    }
+}
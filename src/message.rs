@@ -0,0 +1,46 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Events for `--message-format json`, printed on stdout as one JSON object
+//! per line so that other tools can follow what a run does. Like cargo's
+//! own messages, each has a `reason` saying what kind of event it is:
+//!
+//! - `resolved`: a version of a crate was picked, with its `name`,
+//!   `version` and `source`.
+//! - `downloading`: its `.crate` file is being downloaded from `url`.
+//! - `extracted`: it was cloned to `path`.
+//! - `failed`: the `crate` spec given could not be cloned, for `error`.
+//! - `summary`: the counts of crates `cloned`, `skipped` and `failed`, last.
+//! - `interrupted`: the run was stopped by `signal` instead, after removing
+//!   the clones it had not finished.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{json, Value};
+
+/// Whether events are printed.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Has `emit` print events from now on.
+pub fn enable() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+/// Prints an event with the given `reason` and the fields of the object
+/// `fields`, if events were asked for.
+pub fn emit(reason: &str, fields: Value) {
+    if !JSON.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut event = json!({ "reason": reason });
+    if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), fields) {
+        event.extend(fields);
+    }
+    // A whole line is printed at once, so threads cannot mix their events.
+    println!("{}", event);
+}
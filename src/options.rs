@@ -0,0 +1,230 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Cloning a crate from other tools, without putting together the source
+//! and `ops::CloneOpts` that `ops::clone` takes. `CloneOptions` says what
+//! to clone and where from, and `clone_one` clones it; see the README for
+//! an example.
+
+use std::path::{Path, PathBuf};
+
+use cargo::core::{GitReference, SourceId};
+use cargo::util::into_url::IntoUrl;
+use cargo::util::{CargoResult, Config};
+
+use failure::bail;
+
+use crate::ops::{self, CloneOpts, OnExists};
+
+/// Where a crate is cloned from.
+#[derive(Clone, Debug)]
+enum Origin {
+    CratesIo,
+    Registry(String),
+    Index(String),
+    Git(String),
+    Path(PathBuf),
+}
+
+/// What `clone_one` clones, and how. Like `cargo clone`, it clones the
+/// newest version of the crate from crates.io into `./<name>` unless told
+/// otherwise.
+#[derive(Clone, Debug)]
+pub struct CloneOptions {
+    krate: String,
+    version: Option<String>,
+    dest: Option<PathBuf>,
+    origin: Origin,
+    reference: Option<GitReference>,
+    on_exists: OnExists,
+    pre: bool,
+    allow_yanked: bool,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    git_init: bool,
+    untracked: bool,
+}
+
+impl CloneOptions {
+    /// Clones `krate`, which is a name, or a spec such as `serde@1.0` as
+    /// `cargo clone` takes it.
+    pub fn new(krate: &str) -> CloneOptions {
+        CloneOptions {
+            krate: krate.to_string(),
+            version: None,
+            dest: None,
+            origin: Origin::CratesIo,
+            reference: None,
+            on_exists: OnExists::Error,
+            pre: false,
+            allow_yanked: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            git_init: false,
+            untracked: false,
+        }
+    }
+
+    /// Clones the newest version that matches `req`, such as `1.0` or
+    /// `=1.0.150`.
+    pub fn version(mut self, req: &str) -> CloneOptions {
+        self.version = Some(req.to_string());
+        self
+    }
+
+    /// Clones into `path` rather than `./<name>`.
+    pub fn dest(mut self, path: impl AsRef<Path>) -> CloneOptions {
+        self.dest = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Clones from the registry called `name` in cargo's configuration.
+    pub fn registry(mut self, name: &str) -> CloneOptions {
+        self.origin = Origin::Registry(name.to_string());
+        self
+    }
+
+    /// Clones from the registry with the index at `url`, which starts with
+    /// `sparse+` for a sparse index.
+    pub fn index(mut self, url: &str) -> CloneOptions {
+        self.origin = Origin::Index(url.to_string());
+        self
+    }
+
+    /// Clones from the git repository at `url`, at its `master` branch
+    /// unless `branch`, `tag`, or `rev` says otherwise.
+    pub fn git(mut self, url: &str) -> CloneOptions {
+        self.origin = Origin::Git(url.to_string());
+        self
+    }
+
+    /// Clones from this branch of the `git` repository.
+    pub fn branch(self, branch: &str) -> CloneOptions {
+        self.git_reference(GitReference::Branch(branch.to_string()))
+    }
+
+    /// Clones from this tag of the `git` repository.
+    pub fn tag(self, tag: &str) -> CloneOptions {
+        self.git_reference(GitReference::Tag(tag.to_string()))
+    }
+
+    /// Clones from this commit of the `git` repository.
+    pub fn rev(self, rev: &str) -> CloneOptions {
+        self.git_reference(GitReference::Rev(rev.to_string()))
+    }
+
+    fn git_reference(mut self, reference: GitReference) -> CloneOptions {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Clones from the package or workspace at `path`.
+    pub fn path(mut self, path: impl AsRef<Path>) -> CloneOptions {
+        self.origin = Origin::Path(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// What to do when the destination is not empty; an error by default.
+    pub fn on_exists(mut self, on_exists: OnExists) -> CloneOptions {
+        self.on_exists = on_exists;
+        self
+    }
+
+    /// Allows a prerelease version to be cloned.
+    pub fn pre(mut self, pre: bool) -> CloneOptions {
+        self.pre = pre;
+        self
+    }
+
+    /// Allows a yanked version to be cloned.
+    pub fn allow_yanked(mut self, allow_yanked: bool) -> CloneOptions {
+        self.allow_yanked = allow_yanked;
+        self
+    }
+
+    /// Only clones files matching `glob`; may be given several times.
+    pub fn include(mut self, glob: &str) -> CloneOptions {
+        self.include.push(glob.to_string());
+        self
+    }
+
+    /// Skips files and directories matching `glob`; may be given several
+    /// times.
+    pub fn exclude(mut self, glob: &str) -> CloneOptions {
+        self.exclude.push(glob.to_string());
+        self
+    }
+
+    /// Makes the clone a git repository with one commit.
+    pub fn git_init(mut self, git_init: bool) -> CloneOptions {
+        self.git_init = git_init;
+        self
+    }
+
+    /// Leaves the clone out of the clone database that `cargo clone list`,
+    /// `update`, and `clean` work on.
+    pub fn untracked(mut self, untracked: bool) -> CloneOptions {
+        self.untracked = untracked;
+        self
+    }
+
+    fn source_id(&self, config: &Config) -> CargoResult<SourceId> {
+        if self.reference.is_some() && !matches!(self.origin, Origin::Git(_)) {
+            bail!("`branch`, `tag`, and `rev` can only be used with `git`");
+        }
+        match &self.origin {
+            Origin::CratesIo => ops::crates_io(None, config),
+            Origin::Registry(name) => SourceId::alt_registry(config, name),
+            Origin::Index(url) => SourceId::for_registry(&url.into_url()?),
+            Origin::Git(url) => {
+                let reference = self
+                    .reference
+                    .clone()
+                    .unwrap_or_else(|| GitReference::Branch("master".to_string()));
+                SourceId::for_git(&url.into_url()?, reference)
+            }
+            Origin::Path(path) => SourceId::for_path(&config.cwd().join(path)),
+        }
+    }
+}
+
+/// Clones what `options` says with cargo's default configuration, and
+/// returns where the clone is. It is `None` if there was nothing to do, as
+/// when the destination exists and `OnExists::Skip` was given.
+pub fn clone_one(options: &CloneOptions) -> CargoResult<Option<PathBuf>> {
+    clone_one_with(options, &Config::default()?)
+}
+
+/// Like `clone_one`, with `config` as cargo's configuration, for its shell,
+/// working directory, and settings such as `--offline`.
+pub fn clone_one_with(options: &CloneOptions, config: &Config) -> CargoResult<Option<PathBuf>> {
+    let prefix = match &options.dest {
+        Some(dest) => match dest.to_str() {
+            Some(dest) => Some(dest),
+            None => bail!("destination path '{}' is not valid UTF-8", dest.display()),
+        },
+        None => None,
+    };
+    let opts = CloneOpts {
+        prefix,
+        on_exists: options.on_exists,
+        vers: options.version.as_deref(),
+        allow_yanked: options.allow_yanked,
+        pre: options.pre,
+        include: &options.include,
+        exclude: &options.exclude,
+        git_init: options.git_init,
+        untracked: options.untracked,
+        jobs: 1,
+        ..Default::default()
+    };
+    let srcid = options.source_id(config)?;
+    Ok(ops::clone(&[&options.krate], &srcid, &opts, config)?
+        .into_iter()
+        .next())
+}
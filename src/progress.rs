@@ -0,0 +1,114 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Progress of work that takes a while, so that it does not look hung.
+//!
+//! On a terminal it is a bar like cargo's own. Otherwise, as in CI logs, it
+//! is a status line every few seconds instead.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use cargo::util::{CargoResult, Config, ProgressStyle};
+
+/// How often a status line is printed when there is no bar.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// Whether progress made on this thread is only printed as status
+    /// lines; see `lines_only`.
+    static LINES_ONLY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Has progress made on this thread from now on printed as status lines,
+/// never as a bar, for threads that share the terminal with others. Their
+/// bars would be drawn over each other.
+pub fn lines_only() {
+    LINES_ONLY.with(|lines_only| lines_only.set(true));
+}
+
+/// Formats a number of bytes like `1.5 MiB`.
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// How far along some work is, as a count of things done (`Ratio`) or as a
+/// percentage (`Percentage`), such as of bytes downloaded.
+pub struct Progress<'cfg> {
+    name: String,
+    ratio: bool,
+    bar: cargo::util::Progress<'cfg>,
+    config: &'cfg Config,
+    /// When the last status line was printed, or else when the work began.
+    last: Instant,
+}
+
+impl<'cfg> Progress<'cfg> {
+    pub fn new(name: &str, style: ProgressStyle, config: &'cfg Config) -> Progress<'cfg> {
+        let ratio = match style {
+            ProgressStyle::Ratio => true,
+            ProgressStyle::Percentage => false,
+        };
+        let mut bar = cargo::util::Progress::with_style(name, style, config);
+        if LINES_ONLY.with(Cell::get) {
+            bar.disable();
+        }
+        Progress {
+            name: name.to_string(),
+            ratio,
+            bar,
+            config,
+            last: Instant::now(),
+        }
+    }
+
+    /// Has this progress printed as status lines only, when other output may
+    /// come at any moment, which a bar would be drawn over.
+    pub fn without_bar(mut self) -> Progress<'cfg> {
+        self.bar.disable();
+        self
+    }
+
+    /// Shows that `cur` of `max` is done, followed by `msg`, which should
+    /// start with `: ` if it is not empty. Updates that come too soon after
+    /// the last one are left out.
+    pub fn tick(&mut self, cur: usize, max: usize, msg: &str) -> CargoResult<()> {
+        if self.bar.is_enabled() {
+            if self.bar.update_allowed() {
+                self.bar.tick_now(cur, max, msg)?;
+            }
+            return Ok(());
+        }
+        if self.last.elapsed() < INTERVAL {
+            return Ok(());
+        }
+        self.last = Instant::now();
+        let done = if self.ratio {
+            format!("{}/{}", cur, max)
+        } else {
+            match (cur * 100).checked_div(max) {
+                Some(percent) => format!("{}%", percent),
+                None => return Ok(()),
+            }
+        };
+        self.config
+            .shell()
+            .status(&self.name, format!("{}{}", done, msg))
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Software bills of materials for the packages a run cloned, as CycloneDX
+//! 1.5 or SPDX 2.3 JSON documents.
+
+use std::io::Write;
+
+use cargo::util::CargoResult;
+
+use failure::bail;
+
+use rand::Rng;
+
+use serde_json::json;
+
+use crate::db;
+use crate::ops::Cloned;
+use crate::sparse;
+
+/// The SBOM formats `write` can produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    CycloneDx,
+    Spdx,
+}
+
+impl std::str::FromStr for Format {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> CargoResult<Format> {
+        match s {
+            "cyclonedx" => Ok(Format::CycloneDx),
+            "spdx" => Ok(Format::Spdx),
+            _ => bail!("invalid SBOM format '{}', expected cyclonedx or spdx", s),
+        }
+    }
+}
+
+/// Writes a bill of materials listing each of `cloned` with its version,
+/// checksum, and source.
+pub fn write(format: Format, cloned: &[Cloned], out: &mut dyn Write) -> CargoResult<()> {
+    let document = match format {
+        Format::CycloneDx => cyclonedx(cloned),
+        Format::Spdx => spdx(cloned),
+    };
+    serde_json::to_writer_pretty(&mut *out, &document)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn cyclonedx(cloned: &[Cloned]) -> serde_json::Value {
+    let components: Vec<_> = cloned
+        .iter()
+        .map(|cloned| {
+            let record = &cloned.record;
+            let purl = purl(record);
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": purl,
+                "name": record.name,
+                "version": record.version,
+                "purl": purl,
+            });
+            if let Some(license) = &cloned.license {
+                component["licenses"] = json!([{ "expression": license }]);
+            }
+            if let Some(checksum) = &record.checksum {
+                component["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+            }
+            if let Some(source) = &record.source {
+                component["externalReferences"] =
+                    json!([{ "type": "distribution", "url": source }]);
+            }
+            component
+        })
+        .collect();
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", uuid()),
+        "version": 1,
+        "metadata": {
+            "timestamp": db::now(),
+            "tools": [{ "name": "cargo-clone", "version": env!("CARGO_PKG_VERSION") }],
+        },
+        "components": components,
+    })
+}
+
+fn spdx(cloned: &[Cloned]) -> serde_json::Value {
+    let mut packages = Vec::new();
+    let mut relationships = Vec::new();
+    for (i, cloned) in cloned.iter().enumerate() {
+        let record = &cloned.record;
+        let id = format!("SPDXRef-Package-{}", i + 1);
+        let mut package = json!({
+            "name": record.name,
+            "SPDXID": id,
+            "versionInfo": record.version,
+            "downloadLocation": record.source.as_deref().unwrap_or("NOASSERTION"),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": cloned.license.as_deref().unwrap_or("NOASSERTION"),
+            "copyrightText": "NOASSERTION",
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl(record),
+            }],
+        });
+        if let Some(checksum) = &record.checksum {
+            package["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": checksum }]);
+        }
+        packages.push(package);
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": id,
+        }));
+    }
+    let uuid = uuid();
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": "cargo-clone",
+        "documentNamespace": format!("https://spdx.org/spdxdocs/cargo-clone-{}", uuid),
+        "creationInfo": {
+            "created": db::now(),
+            "creators": [format!("Tool: cargo-clone-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// Returns the package URL of a clone, which names its registry unless that
+/// is crates.io.
+fn purl(record: &db::Record) -> String {
+    let purl = format!("pkg:cargo/{}@{}", record.name, record.version);
+    let crates_io = sparse::CRATES_IO_INDEX.trim_start_matches("sparse+");
+    let registry = record
+        .source
+        .as_deref()
+        .and_then(|source| source.strip_prefix("registry+"))
+        .map(|url| url.trim_start_matches("sparse+"))
+        .filter(|url| *url != "https://github.com/rust-lang/crates.io-index" && *url != crates_io);
+    match registry {
+        Some(url) => format!("{}?repository_url={}", purl, url),
+        None => purl,
+    }
+}
+
+/// Returns a random (version 4) UUID, to tell documents apart.
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
@@ -0,0 +1,139 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A summary of the packages a run cloned that run code at build time or
+//! ship code that was not built from source: those with a build script,
+//! proc-macro crates, and those with prebuilt binaries among their files.
+//! These are where a security review of dependencies starts.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use cargo::util::CargoResult;
+
+use serde_json::json;
+
+use crate::ops::Cloned;
+use crate::walk::{self, Filter, Kind};
+
+/// The first bytes of executables, shared and static libraries, and other
+/// compiled code.
+const MAGIC: &[&[u8]] = &[
+    // ELF
+    b"\x7fELF",
+    // PE (Windows)
+    b"MZ",
+    // Mach-O, both byte orders and word sizes, and fat binaries
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+    // ar archives, such as static libraries
+    b"!<arch>\n",
+    // WebAssembly
+    b"\0asm",
+];
+
+/// Returns the files under `dir` that pass `filter` and are compiled code,
+/// relative to `dir`.
+pub fn find_binaries(dir: &Path, filter: &Filter) -> CargoResult<Vec<PathBuf>> {
+    let mut binaries = Vec::new();
+    for entry in walk::entries(dir, filter, false)? {
+        if entry.kind != Kind::File {
+            continue;
+        }
+        let mut head = [0; 8];
+        let mut file = fs::File::open(&entry.path)?;
+        let mut len = 0;
+        while len < head.len() {
+            match file.read(&mut head[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if MAGIC.iter().any(|magic| head[..len].starts_with(magic)) {
+            binaries.push(entry.rel);
+        }
+    }
+    Ok(binaries)
+}
+
+/// Writes a table of the packages among `cloned` that have a build script,
+/// are proc-macros, or include prebuilt binaries to `out`. With `json`, it
+/// is one JSON object instead.
+pub fn report(cloned: &[Cloned], json: bool, out: &mut dyn Write) -> CargoResult<()> {
+    let flagged: Vec<&Cloned> = cloned
+        .iter()
+        .filter(|c| c.build_script.is_some() || c.proc_macro || !c.binaries.is_empty())
+        .collect();
+
+    if json {
+        let packages: Vec<_> = flagged
+            .iter()
+            .map(|c| {
+                json!({
+                    "name": c.record.name,
+                    "version": c.record.version,
+                    "path": c.record.path,
+                    "build_script": c.build_script,
+                    "proc_macro": c.proc_macro,
+                    "binaries": c.binaries,
+                })
+            })
+            .collect();
+        let report = json!({
+            "packages": cloned.len(),
+            "build_scripts": flagged.iter().filter(|c| c.build_script.is_some()).count(),
+            "proc_macros": flagged.iter().filter(|c| c.proc_macro).count(),
+            "with_binaries": flagged.iter().filter(|c| !c.binaries.is_empty()).count(),
+            "flagged": packages,
+        });
+        writeln!(out, "{}", report)?;
+        return Ok(());
+    }
+
+    writeln!(
+        out,
+        "{} of {} packages run code at build time or include binaries:",
+        flagged.len(),
+        cloned.len()
+    )?;
+    if flagged.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<String> = flagged
+        .iter()
+        .map(|c| format!("{} v{}", c.record.name, c.record.version))
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    writeln!(
+        out,
+        "    {:<width$}  build script  proc-macro  binaries",
+        "package",
+        width = width
+    )?;
+    for (c, name) in flagged.iter().zip(&names) {
+        let binaries: Vec<String> = c
+            .binaries
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        let row = format!(
+            "    {:<width$}  {:<12}  {:<10}  {}",
+            name,
+            if c.build_script.is_some() { "yes" } else { "" },
+            if c.proc_macro { "yes" } else { "" },
+            binaries.join(", "),
+            width = width
+        );
+        writeln!(out, "{}", row.trim_end())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Registries whose index is served over HTTP with the sparse protocol.
+//!
+//! The version of cargo this builds on only knows git indexes, so a sparse
+//! registry is read as a local registry kept under the cargo home. Each index
+//! file and `.crate` is fetched into it the first time it is needed, which is
+//! much faster than updating a full git index.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use cargo::core::source::MaybePackage;
+use cargo::core::{Dependency, Package, PackageId, Source, SourceId, Summary};
+use cargo::sources::registry::RegistryConfig;
+use cargo::sources::RegistrySource;
+use cargo::util::{CargoResult, CargoResultExt, Config};
+
+use failure::bail;
+
+use crate::exit::{self, Failure};
+use crate::{api, auth, index};
+
+/// The sparse index of crates.io.
+pub const CRATES_IO_INDEX: &str = "sparse+https://index.crates.io/";
+
+/// The index files fetched during this run, which are not fetched again.
+static FETCHED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Returns whether `srcid` is a registry with a sparse index.
+pub fn is_sparse(srcid: SourceId) -> bool {
+    srcid.is_registry() && srcid.url().scheme().starts_with("sparse+")
+}
+
+/// Returns whether `srcid` is crates.io, through either of its indexes.
+pub fn is_crates_io(srcid: SourceId) -> bool {
+    srcid.is_default_registry() || srcid.is_registry() && srcid.url().as_str() == CRATES_IO_INDEX
+}
+
+/// Returns the URL of the sparse index behind `srcid`, without `sparse+`.
+pub fn index_url(srcid: SourceId) -> String {
+    srcid.url().as_str()["sparse+".len()..].to_string()
+}
+
+/// Returns the directory the files of the sparse registry behind `srcid` are
+/// fetched into, laid out like a local registry.
+pub fn cache_root(config: &Config, srcid: SourceId) -> PathBuf {
+    config
+        .home()
+        .join("registry")
+        .join("sparse")
+        .join(index::registry_dir(srcid))
+        .into_path_unlocked()
+}
+
+/// Returns the contents of the file at `rel` in the sparse index behind
+/// `srcid`, or `None` if the index does not have one.
+///
+/// The file is fetched once per run and kept in the cache. When cargo is
+/// offline, the cached copy is used instead. If the index asks for
+/// authentication, the request is repeated with the registry's token.
+pub fn fetch(config: &Config, srcid: SourceId, rel: &str) -> CargoResult<Option<Vec<u8>>> {
+    let path = cache_root(config, srcid).join("index").join(rel);
+    let base = index_url(srcid);
+    let url = format!("{}/{}", base.trim_end_matches('/'), rel);
+
+    let fetched = FETCHED
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|fetched| fetched.contains(&url));
+    if fetched || config.offline() {
+        return read_cached(&path);
+    }
+
+    let (mut code, mut body, _) = api::get(config, &url)?;
+    if (code == 401 || code == 403) && auth::token_for(&url).is_none() {
+        auth::authorize(&base, &auth::token(config, srcid)?);
+        let (retry_code, retry_body, _) = api::get(config, &url)?;
+        code = retry_code;
+        body = retry_body;
+    }
+    match code {
+        200 => {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, &body)?;
+        }
+        // A crate that is gone from the index must not linger in the cache.
+        404 | 410 | 451 => {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        code => {
+            return Err(exit::error(
+                Failure::Network,
+                format!("failed to get `{}` (HTTP status {})", url, code),
+            ))
+        }
+    }
+    FETCHED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashSet::new)
+        .insert(url);
+    if code == 200 {
+        Ok(Some(body))
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_cached(path: &Path) -> CargoResult<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Expands the `dl` template of a registry's `config.json` into the download
+/// URL of one `.crate` file.
+pub fn download_url(dl: &str, pkg: PackageId) -> String {
+    let name = pkg.name().to_string();
+    let markers = ["{crate}", "{version}", "{prefix}", "{lowerprefix}"];
+    if !markers.iter().any(|m| dl.contains(m)) {
+        return format!(
+            "{}/{}/{}/download",
+            dl.trim_end_matches('/'),
+            name,
+            pkg.version()
+        );
+    }
+    let path = index::index_path(&name);
+    let prefix = path.rsplit_once('/').map_or("", |(prefix, _)| prefix);
+    dl.replace("{crate}", &name)
+        .replace("{version}", &pkg.version().to_string())
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{prefix}", prefix)
+}
+
+/// A sparse registry, read as a local registry that files are fetched into
+/// on demand.
+pub struct SparseSource<'cfg> {
+    srcid: SourceId,
+    root: PathBuf,
+    inner: RegistrySource<'cfg>,
+    config: &'cfg Config,
+    dl: Option<String>,
+}
+
+impl<'cfg> SparseSource<'cfg> {
+    pub fn new(
+        srcid: SourceId,
+        yanked_whitelist: &HashSet<PackageId>,
+        config: &'cfg Config,
+    ) -> CargoResult<SparseSource<'cfg>> {
+        let root = cache_root(config, srcid);
+        fs::create_dir_all(root.join("index"))?;
+        Ok(SparseSource {
+            srcid,
+            inner: RegistrySource::local(srcid, &root, yanked_whitelist, config),
+            root,
+            config,
+            dl: None,
+        })
+    }
+
+    fn crate_file(&self, pkg: PackageId) -> PathBuf {
+        self.root
+            .join(format!("{}-{}.crate", pkg.name(), pkg.version()))
+    }
+
+    fn write_crate_file(&self, pkg: PackageId, contents: &[u8]) -> CargoResult<()> {
+        let tmp = tempfile::Builder::new()
+            .prefix(".cargo-clone-")
+            .tempfile_in(&self.root)?;
+        fs::write(tmp.path(), contents)?;
+        tmp.persist(self.crate_file(pkg))?;
+        Ok(())
+    }
+
+    /// Has the inner registry read the `.crate` file of `pkg`, which must be
+    /// in place. A file that fails its checksum is removed.
+    fn unpack(&mut self, pkg: PackageId) -> CargoResult<MaybePackage> {
+        let result = self.inner.download(pkg);
+        if result.is_err() {
+            let _ = fs::remove_file(self.crate_file(pkg));
+        }
+        result
+    }
+}
+
+impl<'cfg> Source for SparseSource<'cfg> {
+    fn source_id(&self) -> SourceId {
+        self.srcid
+    }
+
+    fn supports_checksums(&self) -> bool {
+        true
+    }
+
+    fn requires_precise(&self) -> bool {
+        false
+    }
+
+    fn query(&mut self, dep: &Dependency, f: &mut dyn FnMut(Summary)) -> CargoResult<()> {
+        fetch(
+            self.config,
+            self.srcid,
+            &index::index_path(&dep.package_name()),
+        )?;
+        self.inner.query(dep, f)
+    }
+
+    fn fuzzy_query(&mut self, dep: &Dependency, f: &mut dyn FnMut(Summary)) -> CargoResult<()> {
+        fetch(
+            self.config,
+            self.srcid,
+            &index::index_path(&dep.package_name()),
+        )?;
+        self.inner.fuzzy_query(dep, f)
+    }
+
+    fn update(&mut self) -> CargoResult<()> {
+        let config = match fetch(self.config, self.srcid, "config.json")? {
+            Some(contents) => contents,
+            None => bail!("{} has no config.json", self.srcid),
+        };
+        let config: RegistryConfig =
+            serde_json::from_slice(&config).chain_err(|| "failed to parse registry config.json")?;
+        self.dl = Some(config.dl);
+        auth::setup(self.config, self.srcid)?;
+        self.inner.update()
+    }
+
+    fn download(&mut self, pkg: PackageId) -> CargoResult<MaybePackage> {
+        if self.crate_file(pkg).exists() {
+            return self.unpack(pkg);
+        }
+        let url = match &self.dl {
+            Some(dl) => download_url(dl, pkg),
+            None => bail!("{} must be updated before downloading", self.srcid),
+        };
+        // Cargo's downloads cannot carry a token, so those are made here.
+        if auth::token_for(&url).is_some() {
+            // A part left by an interrupted download is resumed.
+            let part = index::part_path(&self.crate_file(pkg));
+            self.config.shell().status("Downloading", pkg)?;
+            let (code, _) = api::download(self.config, &url, &part)?;
+            if code != 200 {
+                return Err(exit::error(
+                    Failure::Network,
+                    format!("failed to download `{}` (HTTP status {})", url, code),
+                ));
+            }
+            fs::rename(&part, self.crate_file(pkg))?;
+            return self.unpack(pkg);
+        }
+        Ok(MaybePackage::Download {
+            url,
+            descriptor: pkg.to_string(),
+        })
+    }
+
+    fn finish_download(&mut self, pkg: PackageId, contents: Vec<u8>) -> CargoResult<Package> {
+        self.write_crate_file(pkg, &contents)?;
+        match self.unpack(pkg)? {
+            MaybePackage::Ready(pkg) => Ok(pkg),
+            MaybePackage::Download { .. } => unreachable!("the .crate file is in place"),
+        }
+    }
+
+    fn fingerprint(&self, pkg: &Package) -> CargoResult<String> {
+        self.inner.fingerprint(pkg)
+    }
+
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn add_to_yanked_whitelist(&mut self, pkgs: &[PackageId]) {
+        self.inner.add_to_yanked_whitelist(pkgs)
+    }
+
+    fn is_yanked(&mut self, pkg: PackageId) -> CargoResult<bool> {
+        self.inner.is_yanked(pkg)
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2015 Jan Likar.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Selecting which of a package's files get cloned.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use cargo::util::{CargoResult, CargoResultExt};
+
+use glob::{MatchOptions, Pattern};
+
+use walkdir::{WalkDir, WalkDirIterator};
+
+/// Include and exclude globs, matched against paths relative to the package
+/// root.
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> CargoResult<Filter> {
+        let parse = |globs: &[String]| -> CargoResult<Vec<Pattern>> {
+            globs
+                .iter()
+                .map(|glob| {
+                    Ok(Pattern::new(glob)
+                        .chain_err(|| format!("invalid glob pattern '{}'", glob))?)
+                })
+                .collect()
+        };
+        // Excluding `dir/**` excludes `dir` itself too, rather than leaving it
+        // behind empty.
+        let mut exclude = exclude.to_vec();
+        exclude.extend(
+            exclude
+                .iter()
+                .filter_map(|glob| glob.strip_suffix("/**"))
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        );
+        Ok(Filter {
+            include: parse(include)?,
+            exclude: parse(&exclude)?,
+        })
+    }
+
+    /// Returns whether the filter lets every file through.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn matches(patterns: &[Pattern], rel: &Path) -> bool {
+        // `*` stays within a directory; `**` crosses them.
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_path_with(rel, options))
+    }
+}
+
+/// Returns whether `rel`, a path relative to the package root, passes
+/// `filter` on its own, without regard to its parent directories.
+pub fn passes(filter: &Filter, rel: &Path) -> bool {
+    !Filter::matches(&filter.exclude, rel)
+        && (filter.include.is_empty() || Filter::matches(&filter.include, rel))
+}
+
+/// What an `Entry` is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Dir,
+    File,
+    /// A symlink, which is only listed when links are not followed.
+    Symlink,
+}
+
+/// One directory, file, or symlink to clone.
+pub struct Entry {
+    pub path: PathBuf,
+    /// The path relative to the package root.
+    pub rel: PathBuf,
+    pub kind: Kind,
+}
+
+/// Lists the directories and files under `from` that pass `filter`, parents
+/// before children and otherwise sorted by name.
+///
+/// Excluded directories are not descended into. When include globs are
+/// given, only the directories leading to an included file are listed. With
+/// `follow_links`, symlinks are listed as what they point to.
+pub fn entries(from: &Path, filter: &Filter, follow_links: bool) -> CargoResult<Vec<Entry>> {
+    let mut files = Vec::new();
+    let mut dirs = BTreeSet::new();
+    let walk = WalkDir::new(from)
+        .min_depth(1)
+        .follow_links(follow_links)
+        .sort_by(|a, b| a.cmp(b))
+        .into_iter()
+        .filter_entry(|entry| {
+            let rel = entry.path().strip_prefix(from).unwrap();
+            !(entry.file_type().is_dir() && Filter::matches(&filter.exclude, rel))
+        });
+    for entry in walk {
+        let entry = entry?;
+        let file_type = entry.file_type();
+        let rel = entry.path().strip_prefix(from).unwrap().to_owned();
+
+        if file_type.is_dir() {
+            if filter.include.is_empty() {
+                dirs.insert(rel);
+            }
+            continue;
+        }
+
+        let kind = if file_type.is_symlink() {
+            Kind::Symlink
+        } else if file_type.is_file() && entry.file_name() != ".cargo-ok" {
+            // .cargo-ok is not wanted in this context
+            Kind::File
+        } else {
+            continue;
+        };
+        if Filter::matches(&filter.exclude, &rel)
+            || !filter.include.is_empty() && !Filter::matches(&filter.include, &rel)
+        {
+            continue;
+        }
+        if !filter.include.is_empty() {
+            dirs.extend(rel.ancestors().skip(1).map(Path::to_owned));
+        }
+        files.push((rel, kind));
+    }
+    dirs.remove(Path::new(""));
+
+    // Sorting by path puts each directory before its contents.
+    let mut entries: Vec<Entry> = dirs
+        .into_iter()
+        .map(|rel| (rel, Kind::Dir))
+        .chain(files)
+        .map(|(rel, kind)| Entry {
+            path: from.join(&rel),
+            rel,
+            kind,
+        })
+        .collect();
+    entries.sort_by(|a, b| a.rel.cmp(&b.rel));
+    Ok(entries)
+}